@@ -11,6 +11,7 @@ export type AttrValue = {
   Number: number;
   DatesPairCollection: DatePair[];
   Image: string;
+  Computed: number | string;
 };
 
 export const AttrValueKeys: (keyof AttrValue)[] = [
@@ -21,6 +22,7 @@ export const AttrValueKeys: (keyof AttrValue)[] = [
   'DateCollection',
   'DatesPairCollection',
   'Image',
+  'Computed',
 ];
 
 export type ShemaItemValue = {
@@ -43,7 +45,9 @@ type SchemaSettingsType<K extends keyof AttrValue> = K extends 'Text'
             ? SchemaDatesPairCollectionSettings
             : K extends 'Image'
               ? SchemaImageSettings
-              : never;
+              : K extends 'Computed'
+                ? SchemaComputedSettings
+                : never;
 
 export type SchemaTextSettings = {
   font?: 'Serif' | 'Sans';
@@ -70,6 +74,14 @@ export type SchemaDateSettings = {};
 export type SchemaDateCollectionSettings = {};
 export type SchemaDatesPairCollectionSettings = {};
 export type SchemaImageSettings = {};
+
+// Read-only field calculated by backend, i.e. `len(read)` or `max(read.finished)`
+export type SchemaComputedSettings = {
+  expression: string;
+  returns?: 'Number' | 'Text' | 'Date';
+  decimalPlaces?: number;
+  displayName?: string;
+};
 export type SchemaItem = {
   name: string;
   value: ShemaItemValue;
@@ -30,6 +30,78 @@ export const c_prepare_cache = async () => {
     .catch(errorHandler);
 };
 
+export const c_rebuild_cache = async () => {
+  return invoke('c_rebuild_cache')
+    .then((v) => v as boolean)
+    .catch(errorHandler);
+};
+
+export const c_delete_cache = async (rootPath?: string) => {
+  return invoke('c_delete_cache', { rootPath })
+    .then((v) => v as boolean)
+    .catch(errorHandler);
+};
+
+export type CacheIssue = {
+  kind: 'Missing' | 'Stale' | 'Orphaned';
+  // Files, folders, collection or search table of schema
+  table: string;
+  // Search rows left without note have no path
+  path?: string;
+};
+
+export type CacheVerifyReport = {
+  files_checked: number;
+  folders_checked: number;
+  issues: CacheIssue[];
+  fixed: number;
+  errors: ErrorFromRust[];
+};
+
+// Compares cache with disk, fix repairs found issues without full rebuild
+export const c_verify_cache = async (fix?: boolean) => {
+  return invoke('c_verify_cache', { fix })
+    .then((v) => v as CacheVerifyReport)
+    .catch(errorHandler);
+};
+
+export type QueryPlanStep = {
+  id: number;
+  // 0 for top level steps
+  parent: number;
+  detail: string;
+};
+
+export type QueryDiagnostics = {
+  // 'files', 'count', 'aggregate', 'field values', 'tags', 'folder tree', 'folder counts',
+  // 'folder lookup', 'verify' or 'repair'. Views are evaluated with 'files', 'count' and 'aggregate'.
+  source: string;
+  sql: string;
+  plan: QueryPlanStep[];
+  elapsed_ms: number;
+  rows: number;
+};
+
+// Debug only, queries are recorded with plan and time while enabled
+export const c_set_query_diagnostics = async (enabled: boolean) => {
+  return invoke('c_set_query_diagnostics', { enabled })
+    .then((v) => v as boolean)
+    .catch(errorHandler);
+};
+
+// Queries recorded since last call
+export const c_get_query_diagnostics = async () => {
+  return invoke('c_get_query_diagnostics')
+    .then((v) => v as QueryDiagnostics[])
+    .catch(errorHandler);
+};
+
+export const c_cancel_scan = async () => {
+  return invoke('c_cancel_scan')
+    .then((v) => v as boolean)
+    .catch(errorHandler);
+};
+
 export const c_watch_path = async () => {
   return invoke('c_watch_path')
     .then((v) => v as boolean)
@@ -57,24 +129,332 @@ export type BookListGetResult = {
   books: IBookFromDb[];
 };
 
-export const c_get_files_path = async (path: string) => {
-  return invoke('c_get_files_path', { path })
+export const c_get_files_path = async (path: string, includeSubfolders = true) => {
+  return invoke('c_get_files_path', { path, includeSubfolders })
     .then((v) => v as BookListGetResult)
     .catch(errorHandler);
 };
 
+// Includes is case insensitive substring, not for number fields
+export type CompareOp = 'Eq' | 'Ne' | 'Lt' | 'Lte' | 'Gt' | 'Gte' | 'Includes';
+
+// Collection fields match when any of their items matches
+export type FilesFilter =
+  | { type: 'And'; filters: FilesFilter[] }
+  | { type: 'Or'; filters: FilesFilter[] }
+  | { type: 'Not'; filter: FilesFilter }
+  | { type: 'Compare'; field: string; op: CompareOp; value: string | number }
+  | { type: 'Contains'; field: string; value: string }
+  | { type: 'Any'; field: string; values: string[] }
+  | { type: 'All'; field: string; values: string[] }
+  | {
+      type: 'DateRange';
+      field: string;
+      from?: string;
+      to?: string;
+      // Only for DatesPairCollection, checks only this date of reads
+      part?: 'Started' | 'Finished';
+    }
+  | { type: 'IsNull'; field: string }
+  // Not set, empty text or collection without items
+  | { type: 'IsEmpty'; field: string }
+  | { type: 'IsNotEmpty'; field: string }
+  // Full text search in text attributes and note body
+  | { type: 'Search'; query: string };
+
+export type SortKey = {
+  field: string;
+  direction: 'Asc' | 'Desc';
+};
+
+export type FilesQueryOptions = {
+  filter?: FilesFilter;
+  sort?: SortKey[];
+  limit?: number;
+  offset?: number;
+  // next_cursor from previous page, can't be used together with offset
+  cursor?: string;
+  include_subfolders?: boolean;
+  // Full text search with FTS5 syntax: words, "phrases", prefix*, AND, OR, NOT.
+  // Results are ordered by relevance when sort is empty.
+  search?: string;
+};
+
+export type SnippetPart = {
+  text: string;
+  highlight: boolean;
+};
+
+export type SearchHit = {
+  path: string;
+  // Higher is better
+  score: number;
+  snippet: SnippetPart[];
+};
+
+export type FilesQueryResult = {
+  schema: Schema;
+  books: IBookFromDb[];
+  total: number;
+  next_cursor?: string;
+  // Same order as books, empty without search
+  search_hits: SearchHit[];
+};
+
+export const c_query_files = async (path: string, options: FilesQueryOptions = {}) => {
+  return invoke('c_query_files', { path, options })
+    .then((v) => v as FilesQueryResult)
+    .catch(errorHandler);
+};
+
+// Note with collection is counted in group of every item, reads use finished date unless part is set
+export type GroupKey = {
+  field: string;
+  // Dates are cut to '2024', '2024-03' or '2024-03-15'
+  date_bucket?: 'Year' | 'Month' | 'Day';
+  // Numbers are put into ranges of this width, key is start of range
+  width?: number;
+  part?: 'Started' | 'Finished';
+};
+
+export type Aggregate = {
+  op: 'Count' | 'Sum' | 'Avg' | 'Min' | 'Max';
+  // Not used by Count
+  field?: string;
+};
+
+export type AggregateOptions = {
+  group_by?: GroupKey[];
+  aggregates?: Aggregate[];
+  filter?: FilesFilter;
+  search?: string;
+  include_subfolders?: boolean;
+  order?: 'Key' | 'CountDesc';
+  limit?: number;
+};
+
+export type AggregateGroup = {
+  // Same order as group_by, null for notes without value
+  keys: (string | number | null)[];
+  count: number;
+  // Same order as aggregates
+  values: (string | number | null)[];
+};
+
+export type AggregateResult = {
+  groups: AggregateGroup[];
+  // Notes matching filter
+  total: number;
+};
+
+/**
+ * Books finished per month:
+ * `{ group_by: [{ field: 'read', part: 'Finished', date_bucket: 'Month' }] }`
+ * Average rating by author:
+ * `{ group_by: [{ field: 'author' }], aggregates: [{ op: 'Avg', field: 'myRating' }] }`
+ */
+export const c_aggregate_files = async (path: string, options: AggregateOptions) => {
+  return invoke('c_aggregate_files', { path, options })
+    .then((v) => v as AggregateResult)
+    .catch(errorHandler);
+};
+
+// Positions are string indexes in query text
+export type QueryParseError = {
+  message: string;
+  start: number;
+  end: number;
+};
+
+export type ParsedQuery = {
+  filter?: FilesFilter;
+  sort: SortKey[];
+  // Top level words and phrases, pass as search to get ranking and snippets
+  search?: string;
+  errors: QueryParseError[];
+};
+
+/**
+ * Parses text like `author:"Le Guin" myRating>=4 -tags:dnf sort:-myRating`
+ * against schema of folder. Result can be passed to c_query_files.
+ */
+export const c_parse_query = async (path: string, text: string) => {
+  return invoke('c_parse_query', { path, text })
+    .then((v) => v as ParsedQuery)
+    .catch(errorHandler);
+};
+
+export type QuerySuggestion = {
+  kind: 'Field' | 'Value' | 'Keyword';
+  label: string;
+  // Replaces text between start and end
+  insert: string;
+  start: number;
+  end: number;
+};
+
+export const c_query_suggestions = async (path: string, text: string, cursor: number) => {
+  return invoke('c_query_suggestions', { path, text, cursor })
+    .then((v) => v as QuerySuggestion[])
+    .catch(errorHandler);
+};
+
+export type QuickOpenHit = {
+  path: string;
+  // First text field or file name
+  title: string;
+  schema_name: string;
+  // Field value that matched best
+  matched: string;
+  // Higher is better
+  score: number;
+};
+
+// Typo tolerant, fast enough to call on every keystroke
+export const c_quick_open = async (query: string, limit?: number) => {
+  return invoke('c_quick_open', { query, limit })
+    .then((v) => v as QuickOpenHit[])
+    .catch(errorHandler);
+};
+
+// Normalized tags of all schemas with parents, 'scifi', 'genre', 'genre/fantasy'
 export const c_get_all_tags = async () => {
   return invoke('c_get_all_tags', {})
     .then((v) => v as string[])
     .catch(errorHandler);
 };
 
+export type TagNode = {
+  // Normalized full path, 'genre/fantasy'
+  tag: string;
+  // Most used spelling of last segment
+  name: string;
+  // Notes with exactly this tag
+  count: number;
+  // Notes with this tag or any tag under it
+  total: number;
+  children: TagNode[];
+};
+
+export type TagDetails = {
+  tag: TagNode;
+  // From root to direct parent, without children
+  parents: TagNode[];
+};
+
+export const c_get_tag_tree = async () => {
+  return invoke('c_get_tag_tree')
+    .then((v) => v as TagNode[])
+    .catch(errorHandler);
+};
+
+export const c_get_tag = async (tag: string) => {
+  return invoke('c_get_tag', { tag })
+    .then((v) => v as TagDetails)
+    .catch(errorHandler);
+};
+
+// Tag is normalized, 'Sci-Fi' finds 'scifi' and 'SciFi'. Parent tag includes its children by default.
+export const c_get_files_by_tag = async (tag: string, includeChildren = true) => {
+  return invoke('c_get_files_by_tag', { tag, includeChildren })
+    .then((v) => v as BookListGetResult[])
+    .catch(errorHandler);
+};
+
+export type FieldValueCount = {
+  value: string | number;
+  // Notes that have this value
+  count: number;
+};
+
+// Most used values first, prefix is case insensitive
+export const c_get_field_values = async (
+  path: string,
+  field: string,
+  prefix?: string,
+  limit?: number,
+) => {
+  return invoke('c_get_field_values', { path, field, prefix, limit })
+    .then((v) => v as FieldValueCount[])
+    .catch(errorHandler);
+};
+
 export const c_get_all_folders = async (schemaPath: string) => {
   return invoke('c_get_all_folders', { schemaPath })
     .then((v) => v as string[])
     .catch(errorHandler);
 };
 
+export type FolderNode = {
+  path: string;
+  name: string;
+  schema_path: string;
+  // Notes directly in this folder
+  count: number;
+  // Notes in this folder and all its subfolders
+  total: number;
+  children: FolderNode[];
+};
+
+// Schema folders are roots, updated by 'folder_tree_changed' event
+export const c_get_folder_tree = async () => {
+  return invoke('c_get_folder_tree')
+    .then((v) => v as FolderNode[])
+    .catch(errorHandler);
+};
+
+// Stored in views.yaml of schema folder
+export type SavedView = {
+  // Empty for new view, generated from name on save
+  id: string;
+  name: string;
+  // Relative to schema folder
+  folder?: string;
+  include_subfolders?: boolean;
+  filter?: FilesFilter;
+  sort?: SortKey[];
+  search?: string;
+  // Empty means all fields
+  visible_fields?: string[];
+  group_by?: string;
+};
+
+export type ViewEvaluation = {
+  view: SavedView;
+  result: FilesQueryResult;
+  // Counts of all groups, empty without group_by
+  groups: AggregateGroup[];
+};
+
+export const c_get_views = async (schemaPath: string) => {
+  return invoke('c_get_views', { schemaPath })
+    .then((v) => v as SavedView[])
+    .catch(errorHandler);
+};
+
+// Creates view when id is empty, otherwise replaces view with the same id
+export const c_save_view = async (schemaPath: string, view: SavedView) => {
+  return invoke('c_save_view', { schemaPath, view })
+    .then((v) => v as SavedView)
+    .catch(errorHandler);
+};
+
+export const c_delete_view = async (schemaPath: string, id: string) => {
+  return invoke('c_delete_view', { schemaPath, id })
+    .then((v) => v as boolean)
+    .catch(errorHandler);
+};
+
+export const c_evaluate_view = async (
+  schemaPath: string,
+  id: string,
+  page: { limit?: number; offset?: number; cursor?: string } = {},
+) => {
+  return invoke('c_evaluate_view', { schemaPath, id, ...page })
+    .then((v) => v as ViewEvaluation)
+    .catch(errorHandler);
+};
+
 export type BookReadResult = {
   book: IBookFromDb;
   // This error happens when file is read, but metadata parsing encountered error.
@@ -118,9 +498,74 @@ export const c_load_schema = async (path: string) => {
 export type DefaultSchema = {
   name: string;
   description: string;
+  icon?: string;
   schema_items: SchemaItem[];
 };
 
+// Bundled templates followed by user templates
+export type DefaultSchemaList = {
+  templates: DefaultSchema[];
+  error?: ErrorFromRust;
+};
+
+// Bundled templates first, then user templates. Broken templates are left out and listed in error.
 export const c_get_default_schemas = () => {
-  return invoke('c_get_default_schemas').then((v) => v as DefaultSchema[]);
+  return invoke('c_get_default_schemas').then((v) => v as DefaultSchemaList);
+};
+
+export const c_export_schema_template = (schemaPath: string, name: string, description: string) => {
+  return invoke('c_export_schema_template', { schemaPath, name, description })
+    .then((v) => v as DefaultSchema)
+    .catch(errorHandler);
+};
+
+export type SchemaPackageManifest = {
+  package_version: number;
+  schema_version: string;
+  name: string;
+  exported: string;
+  examples: string[];
+};
+
+export type SchemaPackageImportResult = {
+  schema: Schema;
+  // Paths of example notes that were written
+  examples: string[];
+};
+
+// Writes zip with schema.yaml, saved views and example notes to targetPath
+export const c_export_schema_package = (
+  schemaPath: string,
+  targetPath: string,
+  examplePaths: string[] = [],
+) => {
+  return invoke('c_export_schema_package', { schemaPath, targetPath, examplePaths })
+    .then((v) => v as SchemaPackageManifest)
+    .catch(errorHandler);
+};
+
+export const c_read_schema_package = (packagePath: string) => {
+  return invoke('c_read_schema_package', { packagePath })
+    .then((v) => v as SchemaPackageManifest)
+    .catch(errorHandler);
+};
+
+/**
+ * When overwrite is false and folder exists, returns error with 'SchemaImportOverwrite' action code.
+ */
+export const c_import_schema_package = (
+  packagePath: string,
+  folderName: string,
+  overwrite = false,
+) => {
+  return invoke('c_import_schema_package', { packagePath, folderName, overwrite })
+    .then((v) => v as SchemaPackageImportResult)
+    .catch(errorHandler);
+};
+
+// JSON Schema (draft 2020-12) for frontmatter of notes in schema folder. Written to targetPath when provided.
+export const c_get_json_schema = (schemaPath: string, targetPath?: string) => {
+  return invoke('c_get_json_schema', { schemaPath, targetPath })
+    .then((v) => v as Record<string, unknown>)
+    .catch(errorHandler);
 };
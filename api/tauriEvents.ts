@@ -4,6 +4,7 @@ import { markRaw } from 'vue';
 
 import ErrorToast from '~/components/Error/ErrorToast.vue';
 import type { IBookFromDb } from '~/api/schema';
+import type { FolderNode } from '~/api/tauriActions';
 
 export type ErrorActionCode =
   | 'FileSaveRetry'
@@ -12,7 +13,8 @@ export type ErrorActionCode =
   | 'InitOnceRetry'
   | 'PrepareCacheRetry'
   | 'WatchPathRetry'
-  | 'NoRootPath';
+  | 'NoRootPath'
+  | 'SchemaImportOverwrite';
 
 export type ErrorFromRust = {
   isError: boolean;
@@ -35,6 +37,40 @@ export type FolderEventEmit = {
   schema_path?: string;
 };
 
+export type ViewsChangedEmit = {
+  schemaPath: string;
+  // Views that should be evaluated again
+  viewIds: string[];
+  // views.yaml changed, for example after git pull. Reload list of views.
+  listChanged: boolean;
+};
+
+export type FolderCounts = {
+  path: string;
+  count: number;
+  total: number;
+};
+
+export type FolderTreeChangedEmit = {
+  schemaPath: string;
+  // Folders containing changed paths, up to schema folder
+  updated: FolderCounts[];
+  // New folders with all their subfolders
+  added: FolderNode[];
+  removed: string[];
+};
+
+export type ScanPhase = 'Discovering' | 'Folders' | 'Files' | 'Cleanup' | 'Done' | 'Cancelled';
+
+export type CacheProgress = {
+  phase: ScanPhase;
+  processed: number;
+  // Equals processed while discovering
+  total: number;
+  currentPath?: string;
+  errors: number;
+};
+
 type EventPayloads = {
   file_remove: string;
   file_add: IBookFromDb;
@@ -42,6 +78,9 @@ type EventPayloads = {
   folder_add: FolderEventEmit;
   folder_remove: FolderEventEmit;
   error_happened: ErrorFromRust;
+  cache_progress: CacheProgress;
+  views_changed: ViewsChangedEmit;
+  folder_tree_changed: FolderTreeChangedEmit;
 };
 
 export const rustErrorNotification = (
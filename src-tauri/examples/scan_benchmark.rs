@@ -0,0 +1,13 @@
+// Measures initial cache scan on a generated vault.
+// cargo run --release --features benchmark --example scan_benchmark -- [notes count, default 50000]
+fn main() {
+    let notes_count = std::env::args()
+        .nth(1)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(50_000);
+
+    if let Err(e) = tauri::async_runtime::block_on(app_lib::run_scan_benchmark(notes_count)) {
+        eprintln!("{}: {:?}", e.title, e.raw_error);
+        std::process::exit(1);
+    }
+}
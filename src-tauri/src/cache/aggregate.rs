@@ -0,0 +1,453 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{QueryBuilder, Sqlite};
+use std::time::Instant;
+
+use crate::schema::operations::get_schema_cached_safe;
+use crate::schema::types::{AttrKey, ComputedType, Schema};
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::dbconn::get_read_conn;
+use super::diagnostics::record_query;
+use super::filter::{push_order_by, DatesPairPart, Filter};
+use super::query::{column_value, count_files, FilesQuery};
+use super::search::{check_search, search_join};
+use super::tables::{find_table_names, TableNames};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum DateBucket {
+    Year,
+    Month,
+    Day,
+}
+
+// Note with collection is counted in group of every item it has.
+// For DatesPairCollection every read is used, finished date or started date when read is not finished.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GroupKey {
+    pub field: String,
+    // Dates are cut to "2024", "2024-03" or "2024-03-15"
+    pub date_bucket: Option<DateBucket>,
+    // Numbers are put into ranges of this width, key is start of range
+    pub width: Option<f64>,
+    // Only for DatesPairCollection
+    pub part: Option<DatesPairPart>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AggregateOp {
+    // Notes in group, field is not used
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Aggregate {
+    pub op: AggregateOp,
+    pub field: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub enum GroupOrder {
+    // Nulls last
+    #[default]
+    Key,
+    CountDesc,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AggregateOptions {
+    #[serde(default)]
+    pub group_by: Vec<GroupKey>,
+    #[serde(default)]
+    pub aggregates: Vec<Aggregate>,
+    pub filter: Option<Filter>,
+    pub search: Option<String>,
+    pub include_subfolders: Option<bool>,
+    #[serde(default)]
+    pub order: GroupOrder,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AggregateGroup {
+    // Same order as group_by, null for notes without value
+    pub keys: Vec<Value>,
+    pub count: i64,
+    // Same order as aggregates
+    pub values: Vec<Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AggregateResult {
+    pub groups: Vec<AggregateGroup>,
+    // Notes matching filter. Sum of group counts is larger when notes are in many groups.
+    pub total: i64,
+}
+
+#[derive(PartialEq)]
+enum ValueKind {
+    Text,
+    Date,
+    Number,
+}
+
+enum FieldSource {
+    Column(ValueKind),
+    Collection(ValueKind),
+    DatesPairs,
+}
+
+fn aggregate_err(info: &str) -> ErrorFromRust {
+    ErrorFromRust::new("Invalid query").info(info)
+}
+
+fn field_source(schema: &Schema, name: &str) -> Result<FieldSource, ErrorFromRust> {
+    let item = schema
+        .items
+        .iter()
+        .find(|i| i.name == name)
+        .ok_or_else(|| aggregate_err(&format!("Unknown field \"{}\"", name)))?;
+
+    Ok(match &item.value {
+        AttrKey::Text(_) | AttrKey::Image(_) => FieldSource::Column(ValueKind::Text),
+        AttrKey::Date(_) => FieldSource::Column(ValueKind::Date),
+        AttrKey::Number(_) => FieldSource::Column(ValueKind::Number),
+        AttrKey::Computed(settings) => match settings.as_ref().and_then(|s| s.returns.clone()) {
+            Some(ComputedType::Text) => FieldSource::Column(ValueKind::Text),
+            Some(ComputedType::Date) => FieldSource::Column(ValueKind::Date),
+            Some(ComputedType::Number) | None => FieldSource::Column(ValueKind::Number),
+        },
+        AttrKey::TextCollection(_) => FieldSource::Collection(ValueKind::Text),
+        AttrKey::DateCollection(_) => FieldSource::Collection(ValueKind::Date),
+        AttrKey::DatesPairCollection(_) => FieldSource::DatesPairs,
+    })
+}
+
+// Empty text means no value, so it goes to null group
+fn push_bucketed(
+    qb: &mut QueryBuilder<'_, Sqlite>,
+    value: &str,
+    kind: &ValueKind,
+    key: &GroupKey,
+) -> Result<(), ErrorFromRust> {
+    match (kind, &key.date_bucket, key.width) {
+        (ValueKind::Number, None, None) => {
+            qb.push(value);
+        }
+        (ValueKind::Number, None, Some(width)) => {
+            if !width.is_finite() || width <= 0.0 {
+                return Err(aggregate_err("Width of range must be positive"));
+            }
+            // CAST truncates toward zero, negative values need one step down to get floor
+            qb.push(format!("((CAST(({0}) / ", value))
+                .push_bind(width)
+                .push(format!(" AS INTEGER) - (({0}) < 0 AND ({0}) / ", value))
+                .push_bind(width)
+                .push(format!(" != CAST(({0}) / ", value))
+                .push_bind(width)
+                .push(" AS INTEGER))) * ")
+                .push_bind(width)
+                .push(")");
+        }
+        (ValueKind::Date, Some(bucket), None) => {
+            let len = match bucket {
+                DateBucket::Year => 4,
+                DateBucket::Month => 7,
+                DateBucket::Day => 10,
+            };
+            qb.push(format!("substr(NULLIF({}, ''), 1, {})", value, len));
+        }
+        (ValueKind::Text, None, None) | (ValueKind::Date, None, None) => {
+            qb.push(format!("NULLIF({}, '')", value));
+        }
+        (_, Some(_), _) => {
+            return Err(aggregate_err(&format!(
+                "Field \"{}\" is not a date, it can't be grouped by year, month or day",
+                key.field
+            )))
+        }
+        (_, _, Some(_)) => {
+            return Err(aggregate_err(&format!(
+                "Field \"{}\" is not a number, it can't be grouped into ranges",
+                key.field
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+// Collections are joined as (file id, key) pairs. DISTINCT keeps note once per group,
+// so sums and averages are not inflated by repeated items or many reads in the same year.
+fn push_group_join(
+    qb: &mut QueryBuilder<'_, Sqlite>,
+    t_info: &TableNames,
+    index: usize,
+    key: &GroupKey,
+    source: &FieldSource,
+) -> Result<(), ErrorFromRust> {
+    let side = t_info.field(&key.field)?;
+    let (value, kind) = match source {
+        FieldSource::Collection(kind) => (format!("{}.value", side), kind),
+        FieldSource::DatesPairs => (
+            match key.part {
+                Some(DatesPairPart::Started) => format!("{}.started", side),
+                Some(DatesPairPart::Finished) => format!("{}.finished", side),
+                None => format!("COALESCE({0}.finished, {0}.started)", side),
+            },
+            &ValueKind::Date,
+        ),
+        FieldSource::Column(_) => return Ok(()),
+    };
+
+    qb.push(format!(
+        " LEFT JOIN (SELECT DISTINCT {0}.id AS fid, ",
+        t_info.files_table
+    ));
+    push_bucketed(qb, &value, kind, key)?;
+    qb.push(format!(
+        " AS k FROM {0} JOIN {1} ON {1}.path = {0}.path) AS g{2} ON g{2}.fid = {1}.id",
+        side, t_info.files_table, index
+    ));
+
+    Ok(())
+}
+
+fn aggregate_expression(
+    schema: &Schema,
+    t_info: &TableNames,
+    aggregate: &Aggregate,
+) -> Result<String, ErrorFromRust> {
+    let field = match (&aggregate.op, &aggregate.field) {
+        (AggregateOp::Count, _) => return Ok(format!("COUNT(DISTINCT {}.id)", t_info.files_table)),
+        (_, Some(field)) => field,
+        (_, None) => return Err(aggregate_err("Aggregate needs a field")),
+    };
+
+    let kind = match field_source(schema, field)? {
+        FieldSource::Column(kind) => kind,
+        _ => {
+            return Err(aggregate_err(&format!(
+                "Field \"{}\" is a collection, use it for grouping",
+                field
+            )))
+        }
+    };
+
+    let column = format!("{}.{}", t_info.files_table, t_info.field(field)?);
+    let value = match kind {
+        ValueKind::Number => column,
+        ValueKind::Text | ValueKind::Date => format!("NULLIF({}, '')", column),
+    };
+
+    Ok(match (&aggregate.op, kind) {
+        (AggregateOp::Sum, ValueKind::Number) => format!("SUM({})", value),
+        (AggregateOp::Avg, ValueKind::Number) => format!("AVG({})", value),
+        (AggregateOp::Min, _) => format!("MIN({})", value),
+        (AggregateOp::Max, _) => format!("MAX({})", value),
+        _ => {
+            return Err(aggregate_err(&format!(
+                "Field \"{}\" is not a number, only min and max can be used",
+                field
+            )))
+        }
+    })
+}
+
+pub async fn aggregate_files(
+    query: &FilesQuery,
+    options: &AggregateOptions,
+) -> Result<AggregateResult, ErrorFromRust> {
+    let schema = &query.schema;
+
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let t_info = find_table_names(&mut db, schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
+    let files_table = &t_info.files_table;
+
+    if let Some(search) = &query.search {
+        check_search(&mut db, &t_info, search).await?;
+    }
+
+    let sources = options
+        .group_by
+        .iter()
+        .map(|k| field_source(schema, &k.field))
+        .collect::<Result<Vec<FieldSource>, ErrorFromRust>>()?;
+
+    let mut qb: QueryBuilder<'_, Sqlite> = QueryBuilder::new("SELECT ");
+
+    for (i, (key, source)) in options.group_by.iter().zip(sources.iter()).enumerate() {
+        match source {
+            FieldSource::Column(kind) => {
+                let column = format!("{}.{}", files_table, t_info.field(&key.field)?);
+                push_bucketed(&mut qb, &column, kind, key)?;
+            }
+            _ => {
+                qb.push(format!("g{}.k", i));
+            }
+        }
+        qb.push(format!(" AS key_{}, ", i));
+    }
+
+    qb.push(format!("COUNT(DISTINCT {}.id) AS group_count", files_table));
+    for (i, aggregate) in options.aggregates.iter().enumerate() {
+        qb.push(format!(
+            ", {} AS value_{}",
+            aggregate_expression(schema, &t_info, aggregate)?,
+            i
+        ));
+    }
+
+    qb.push(format!(" FROM {}", files_table));
+    if query.search.is_some() {
+        qb.push(format!(" {}", search_join(&t_info)));
+    }
+    for (i, (key, source)) in options.group_by.iter().zip(sources.iter()).enumerate() {
+        push_group_join(&mut qb, &t_info, i, key, source)?;
+    }
+
+    query.push_where(&mut qb, &t_info, &[], None)?;
+
+    if !options.group_by.is_empty() {
+        qb.push(" GROUP BY ");
+        let mut separated = qb.separated(", ");
+        for i in 0..options.group_by.len() {
+            separated.push(format!("key_{}", i));
+        }
+
+        let mut components: Vec<(String, bool)> = Vec::new();
+        if options.order == GroupOrder::CountDesc {
+            components.push(("group_count".to_string(), true));
+        }
+        for i in 0..options.group_by.len() {
+            components.push((format!("(key_{} IS NULL)", i), false));
+            components.push((format!("key_{}", i), false));
+        }
+        push_order_by(&mut qb, &components);
+    }
+
+    if let Some(limit) = options.limit {
+        qb.push(" LIMIT ").push_bind(limit as i64);
+    }
+
+    let sql = qb.sql().to_string();
+    let started = Instant::now();
+
+    let rows = qb.build().fetch_all(&mut *db).await.map_err(|e| {
+        ErrorFromRust::new("Error when grouping files").raw(format!("{}\n\n{}", e, sql))
+    })?;
+    record_query(&mut db, "aggregate", &sql, started.elapsed(), rows.len()).await;
+
+    let groups = rows
+        .iter()
+        .map(|row| AggregateGroup {
+            keys: (0..options.group_by.len())
+                .map(|i| column_value(row, &format!("key_{}", i)))
+                .collect(),
+            count: column_value(row, "group_count").as_i64().unwrap_or(0),
+            values: (0..options.aggregates.len())
+                .map(|i| column_value(row, &format!("value_{}", i)))
+                .collect(),
+        })
+        .collect();
+
+    Ok(AggregateResult {
+        groups,
+        total: count_files(query).await?,
+    })
+}
+
+pub async fn aggregate_files_in_path(
+    path: String,
+    options: AggregateOptions,
+) -> Result<AggregateResult, ErrorFromRust> {
+    let schema = get_schema_cached_safe(&path).await?;
+
+    let query = FilesQuery::new(schema)
+        .in_folder(&path, options.include_subfolders.unwrap_or(true))
+        .filter(options.filter.clone())
+        .search(options.search.clone());
+
+    aggregate_files(&query, &options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Connection, Row, SqliteConnection};
+
+    fn key(date_bucket: Option<DateBucket>, width: Option<f64>) -> GroupKey {
+        GroupKey {
+            field: "field".to_string(),
+            date_bucket,
+            width,
+            part: None,
+        }
+    }
+
+    async fn bucket(value: &str, kind: ValueKind, key: &GroupKey) -> Option<String> {
+        let mut db = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        let mut qb: QueryBuilder<'_, Sqlite> = QueryBuilder::new("SELECT CAST(");
+        push_bucketed(&mut qb, value, &kind, key).unwrap();
+        qb.push(" AS TEXT) AS k");
+        qb.build().fetch_one(&mut db).await.unwrap().get("k")
+    }
+
+    #[tokio::test]
+    async fn number_ranges_start_at_floor() {
+        let by_ten = key(None, Some(10.0));
+        assert_eq!(
+            bucket("25", ValueKind::Number, &by_ten).await.unwrap(),
+            "20.0"
+        );
+        assert_eq!(
+            bucket("20", ValueKind::Number, &by_ten).await.unwrap(),
+            "20.0"
+        );
+        assert_eq!(
+            bucket("-5", ValueKind::Number, &by_ten).await.unwrap(),
+            "-10.0"
+        );
+        assert_eq!(
+            bucket("-10", ValueKind::Number, &by_ten).await.unwrap(),
+            "-10.0"
+        );
+        assert_eq!(bucket("NULL", ValueKind::Number, &by_ten).await, None);
+    }
+
+    #[tokio::test]
+    async fn dates_are_cut_to_bucket() {
+        let date = "'2024-03-15'";
+        let year = key(Some(DateBucket::Year), None);
+        let month = key(Some(DateBucket::Month), None);
+        assert_eq!(bucket(date, ValueKind::Date, &year).await.unwrap(), "2024");
+        assert_eq!(
+            bucket(date, ValueKind::Date, &month).await.unwrap(),
+            "2024-03"
+        );
+        // Empty text goes to null group
+        assert_eq!(bucket("''", ValueKind::Date, &year).await, None);
+        assert_eq!(bucket("''", ValueKind::Text, &key(None, None)).await, None);
+    }
+
+    #[test]
+    fn bucket_must_match_field_type() {
+        let mut qb: QueryBuilder<'_, Sqlite> = QueryBuilder::new("");
+        let year = key(Some(DateBucket::Year), None);
+        assert!(push_bucketed(&mut qb, "v", &ValueKind::Number, &year).is_err());
+        assert!(push_bucketed(&mut qb, "v", &ValueKind::Text, &key(None, Some(5.0))).is_err());
+        assert!(push_bucketed(&mut qb, "v", &ValueKind::Number, &key(None, Some(0.0))).is_err());
+        assert!(
+            push_bucketed(&mut qb, "v", &ValueKind::Number, &key(None, Some(f64::NAN))).is_err()
+        );
+    }
+}
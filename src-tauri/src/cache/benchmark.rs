@@ -0,0 +1,132 @@
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::schema::operations::load_schema;
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::dbconn::db_setup_at_path;
+use super::tables::create_db_tables_for_all_schemas;
+use super::write::{cache_files_and_folders, default_parse_workers};
+
+const BENCHMARK_FOLDER: &str = "notes_scan_benchmark";
+const SUBFOLDERS: usize = 50;
+
+const SCHEMA_YAML: &str = "name: Books
+version: '1.0'
+items:
+  - name: title
+    value:
+      type: Text
+  - name: author
+    value:
+      type: Text
+  - name: year
+    value:
+      type: Number
+  - name: myRating
+    value:
+      type: Number
+  - name: tags
+    value:
+      type: TextCollection
+  - name: read
+    value:
+      type: DatesPairCollection
+internal_path: ''
+internal_name: ''
+";
+
+fn io_err(e: std::io::Error) -> ErrorFromRust {
+    ErrorFromRust::new("Error when generating benchmark vault").raw(e)
+}
+
+fn generate_vault(schema_folder: &Path, notes_count: usize) -> Result<(), ErrorFromRust> {
+    create_dir_all(schema_folder).map_err(io_err)?;
+    write(schema_folder.join("schema.yaml"), SCHEMA_YAML).map_err(io_err)?;
+
+    for i in 0..notes_count {
+        let folder = schema_folder.join(format!("folder_{}", i % SUBFOLDERS));
+        if i < SUBFOLDERS {
+            create_dir_all(&folder).map_err(io_err)?;
+        }
+
+        let note = format!(
+            "---
+title: Note number {i}
+author: Author {author}
+year: {year}
+myRating: {rating}
+tags:
+  - tag{tag_a}
+  - tag{tag_b}
+read:
+  - started: 2023-0{month}-01
+    finished: 2023-0{month}-20
+---
+
+Body of note {i}. {filler}
+",
+            author = i % 997,
+            year = 1900 + i % 124,
+            rating = i % 6,
+            tag_a = i % 13,
+            tag_b = i % 29,
+            month = 1 + i % 9,
+            filler = "Lorem ipsum dolor sit amet. ".repeat(20),
+        );
+
+        write(folder.join(format!("note_{}.md", i)), note).map_err(io_err)?;
+    }
+
+    Ok(())
+}
+
+async fn timed_scan(vault: &Path, workers: usize) -> Result<Duration, ErrorFromRust> {
+    let start = Instant::now();
+    cache_files_and_folders(vault, workers, |_| ()).await?;
+    Ok(start.elapsed())
+}
+
+// Generates vault in temp dir and compares full scan with one parse worker and with default count.
+// Run with: cargo run --release --features benchmark --example scan_benchmark -- 50000
+pub async fn run_scan_benchmark(notes_count: usize) -> Result<(), ErrorFromRust> {
+    let vault = std::env::temp_dir().join(BENCHMARK_FOLDER);
+    if vault.exists() {
+        remove_dir_all(&vault).map_err(io_err)?;
+    }
+
+    let schema_folder = vault.join("books");
+
+    println!("Generating {} notes in {}", notes_count, vault.display());
+    let start = Instant::now();
+    generate_vault(&schema_folder, notes_count)?;
+    println!("Generated in {:?}", start.elapsed());
+
+    load_schema(schema_folder).await?;
+
+    let mut worker_counts = vec![1, default_parse_workers()];
+    worker_counts.dedup();
+
+    for workers in worker_counts {
+        db_setup_at_path(&vault.join(format!("cache_{}.db", workers))).await?;
+        create_db_tables_for_all_schemas()
+            .await
+            .map_err(|e| ErrorFromRust::new("Error when creating tables").raw(e))?;
+
+        let full = timed_scan(&vault, workers).await?;
+        let unchanged = timed_scan(&vault, workers).await?;
+
+        println!(
+            "{} worker(s): full scan {:?} ({:.0} notes/s), rescan without changes {:?}",
+            workers,
+            full,
+            notes_count as f64 / full.as_secs_f64(),
+            unchanged
+        );
+    }
+
+    remove_dir_all(&vault).map_err(io_err)?;
+
+    Ok(())
+}
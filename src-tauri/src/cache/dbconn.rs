@@ -1,29 +1,251 @@
-use once_cell::sync::OnceCell;
+use std::fs::{create_dir_all, remove_file};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
 
-use sqlx::{sqlite::SqliteConnectOptions, Connection, SqliteConnection};
+use sqlx::{
+    pool::PoolConnection,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+    Connection, Row, Sqlite, SqliteConnection, SqlitePool,
+};
 use tokio::sync::Mutex;
 
-static DB_CONNECTION: OnceCell<Mutex<SqliteConnection>> = OnceCell::new();
+use crate::utils::{errorhandling::ErrorFromRust, global_app::get_app_data_dir, hash::stable_hash};
 
-pub fn get_db_conn() -> &'static Mutex<SqliteConnection> {
-    DB_CONNECTION
-        .get()
-        .expect("Database connection not initialized")
+use super::quick_open::clear_quick_open_index;
+use super::tables::{clear_table_names_cache, create_meta_tables};
+
+// Bump when cache tables change in incompatible way. Cache with other version is deleted and rebuilt.
+pub const CACHE_FORMAT_VERSION: i64 = 4;
+
+const CACHE_FOLDER: &str = "cache";
+
+// SQLite allows only one writer anyway, a single connection makes writers queue in pool instead of failing with SQLITE_BUSY
+const WRITE_CONNECTIONS: u32 = 1;
+const READ_CONNECTIONS: u32 = 4;
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// With WAL readers see last committed state and are not blocked by indexing in progress
+#[derive(Clone)]
+struct DbPools {
+    read: SqlitePool,
+    write: SqlitePool,
 }
 
-pub async fn db_setup() -> Result<(), sqlx::Error> {
-    if DB_CONNECTION.get().is_some() {
-        return Ok(());
+static DB_POOLS: RwLock<Option<DbPools>> = RwLock::new(None);
+
+// Vault id of currently opened cache, also makes setup and deletion run one at a time
+static CURRENT_VAULT: Mutex<Option<String>> = Mutex::const_new(None);
+
+// Pools are missing before first db_setup and while cache is being deleted
+fn get_pools() -> Result<DbPools, sqlx::Error> {
+    DB_POOLS
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or(sqlx::Error::PoolClosed)
+}
+
+pub async fn get_read_conn() -> Result<PoolConnection<Sqlite>, sqlx::Error> {
+    get_pools()?.read.acquire().await
+}
+
+pub async fn get_write_conn() -> Result<PoolConnection<Sqlite>, sqlx::Error> {
+    get_pools()?.write.acquire().await
+}
+
+// Stable as long as vault stays in the same place
+pub fn get_vault_id(root_path: &str) -> String {
+    let canonical = Path::new(root_path)
+        .canonicalize()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or(root_path.to_string());
+
+    stable_hash(canonical.as_bytes())
+}
+
+pub fn get_cache_db_path(vault_id: &str) -> Result<PathBuf, ErrorFromRust> {
+    Ok(get_app_data_dir()?
+        .join(CACHE_FOLDER)
+        .join(format!("{}.db", vault_id)))
+}
+
+fn remove_db_files(db_path: &Path) -> std::io::Result<()> {
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let p = PathBuf::from(format!("{}{}", db_path.to_string_lossy(), suffix));
+        if p.exists() {
+            remove_file(p)?;
+        }
     }
+    Ok(())
+}
+
+// Checks cache format version and creates meta tables, cache with other version is deleted
+async fn prepare_db_file(db_path: &Path) -> Result<(), sqlx::Error> {
     let options = SqliteConnectOptions::new()
-        .filename("files.db")
+        .filename(db_path)
         .create_if_missing(true);
 
-    let conn = SqliteConnection::connect_with(&options).await?;
+    let mut conn = SqliteConnection::connect_with(&options).await?;
+
+    let version: i64 = sqlx::query("PRAGMA user_version")
+        .fetch_one(&mut conn)
+        .await?
+        .get(0);
+
+    if version != CACHE_FORMAT_VERSION {
+        conn.close().await?;
+        remove_db_files(db_path)?;
+
+        conn = SqliteConnection::connect_with(&options).await?;
+        sqlx::query(&format!("PRAGMA user_version = {}", CACHE_FORMAT_VERSION))
+            .execute(&mut conn)
+            .await?;
+    }
+
+    create_meta_tables(&mut conn).await?;
+
+    conn.close().await
+}
+
+async fn open_pools(db_path: &Path) -> Result<DbPools, sqlx::Error> {
+    prepare_db_file(db_path).await?;
+
+    let write_options = SqliteConnectOptions::new()
+        .filename(db_path)
+        .journal_mode(SqliteJournalMode::Wal)
+        .synchronous(SqliteSynchronous::Normal)
+        .busy_timeout(BUSY_TIMEOUT)
+        .with_regexp();
+
+    let write = SqlitePoolOptions::new()
+        .max_connections(WRITE_CONNECTIONS)
+        .connect_with(write_options)
+        .await?;
+
+    // Journal mode is stored in db file, so it's already WAL for readers
+    let read_options = SqliteConnectOptions::new()
+        .filename(db_path)
+        .read_only(true)
+        .busy_timeout(BUSY_TIMEOUT)
+        // Filters use REGEXP for case insensitive matching, SQLite lower() folds only ASCII
+        .with_regexp();
+
+    let read = SqlitePoolOptions::new()
+        .max_connections(READ_CONNECTIONS)
+        .connect_with(read_options)
+        .await?;
 
-    DB_CONNECTION
-        .set(Mutex::new(conn))
-        .expect("Database connection already initialized");
+    Ok(DbPools { read, write })
+}
+
+async fn close_pools() {
+    let old = DB_POOLS.write().unwrap().take();
+    if let Some(pools) = old {
+        pools.write.close().await;
+        pools.read.close().await;
+    }
+}
+
+fn db_err(e: impl ToString) -> ErrorFromRust {
+    ErrorFromRust::new("Error on db setup")
+        .info("This is a critical error. Report bug")
+        .raw(e)
+}
+
+// Opens cache for vault, replacing cache of previously opened vault if needed
+pub async fn db_setup(root_path: &str) -> Result<(), ErrorFromRust> {
+    let vault_id = get_vault_id(root_path);
+
+    let mut current = CURRENT_VAULT.lock().await;
+    if current.as_deref() == Some(vault_id.as_str()) {
+        return Ok(());
+    }
+
+    let db_path = get_cache_db_path(&vault_id)?;
+    if let Some(parent) = db_path.parent() {
+        create_dir_all(parent).map_err(db_err)?;
+    }
+
+    replace_pools(&db_path).await?;
+    *current = Some(vault_id);
+
+    Ok(())
+}
+
+async fn replace_pools(db_path: &Path) -> Result<(), ErrorFromRust> {
+    let pools = open_pools(db_path).await.map_err(db_err)?;
+
+    close_pools().await;
+    *DB_POOLS.write().unwrap() = Some(pools);
+
+    clear_table_names_cache();
+    clear_quick_open_index();
+
+    Ok(())
+}
+
+// Opens cache file that doesn't belong to a vault, used by benchmarks
+#[cfg(feature = "benchmark")]
+pub async fn db_setup_at_path(db_path: &Path) -> Result<(), ErrorFromRust> {
+    let mut current = CURRENT_VAULT.lock().await;
+
+    replace_pools(db_path).await?;
+    *current = None;
+
+    Ok(())
+}
+
+// Deletes cache files of a vault. If vault is currently opened, it gets a new empty cache.
+pub async fn delete_vault_cache(root_path: &str) -> Result<(), ErrorFromRust> {
+    let vault_id = get_vault_id(root_path);
+    let db_path = get_cache_db_path(&vault_id)?;
+
+    let current = CURRENT_VAULT.lock().await;
+
+    if current.as_deref() != Some(vault_id.as_str()) {
+        return remove_db_files(&db_path).map_err(|e| {
+            ErrorFromRust::new("Error when deleting cache")
+                .info(&db_path.to_string_lossy())
+                .raw(e)
+        });
+    }
+
+    // Connections must be closed before files are removed, queries arriving meanwhile get PoolClosed error
+    close_pools().await;
+
+    remove_db_files(&db_path).map_err(|e| {
+        ErrorFromRust::new("Error when deleting cache")
+            .info(&db_path.to_string_lossy())
+            .raw(e)
+    })?;
+
+    let pools = open_pools(&db_path).await.map_err(db_err)?;
+    *DB_POOLS.write().unwrap() = Some(pools);
+    clear_table_names_cache();
+    clear_quick_open_index();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vault_id_is_same_for_same_folder() {
+        let root = std::env::temp_dir().join(format!("vault_{}", std::process::id()));
+        let other = root.join("other");
+        std::fs::create_dir_all(&other).unwrap();
+
+        let id = get_vault_id(&root.to_string_lossy());
+        let dotted = get_vault_id(&other.join("..").to_string_lossy());
+        let other_id = get_vault_id(&other.to_string_lossy());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(id, dotted);
+        assert_ne!(id, other_id);
+        // Cache file name is made from id
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqliteConnection};
+
+// Oldest queries are dropped when nobody reads them
+const MAX_RECORDED_QUERIES: usize = 100;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static RECORDED: Mutex<VecDeque<QueryDiagnostics>> = Mutex::new(VecDeque::new());
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QueryPlanStep {
+    pub id: i64,
+    // 0 for top level steps
+    pub parent: i64,
+    // "SCAN s1_files" reads whole table, "SEARCH s1_files USING INDEX ..." uses index
+    pub detail: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QueryDiagnostics {
+    // Which part of app generated the query: "files", "count", "aggregate", "field values", "tags",
+    // "folder tree", "folder counts", "folder lookup", "verify" or "repair".
+    // Saved views are evaluated with files, count and aggregate queries.
+    pub source: String,
+    pub sql: String,
+    pub plan: Vec<QueryPlanStep>,
+    pub elapsed_ms: f64,
+    pub rows: usize,
+}
+
+// Off by default, explaining every query is not free
+pub fn set_query_diagnostics(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        RECORDED.lock().unwrap().clear();
+    }
+}
+
+// Queries recorded since last call, oldest first
+pub fn take_query_diagnostics() -> Vec<QueryDiagnostics> {
+    RECORDED.lock().unwrap().drain(..).collect()
+}
+
+// Called after query is executed, so explaining does not count into its time.
+// Plan is made without bound values, SQLite treats them as NULL and they rarely change the plan.
+pub async fn record_query(
+    db: &mut SqliteConnection,
+    source: &str,
+    sql: &str,
+    elapsed: Duration,
+    rows: usize,
+) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let plan = match sqlx::query(&format!("EXPLAIN QUERY PLAN {}", sql))
+        .fetch_all(&mut *db)
+        .await
+    {
+        Ok(steps) => steps
+            .iter()
+            .map(|s| QueryPlanStep {
+                id: s.get("id"),
+                parent: s.get("parent"),
+                detail: s.get("detail"),
+            })
+            .collect(),
+        Err(e) => vec![QueryPlanStep {
+            id: 0,
+            parent: 0,
+            detail: format!("Query plan is not available: {}", e),
+        }],
+    };
+
+    let mut recorded = RECORDED.lock().unwrap();
+    if recorded.len() >= MAX_RECORDED_QUERIES {
+        recorded.pop_front();
+    }
+    recorded.push_back(QueryDiagnostics {
+        source: source.to_string(),
+        sql: sql.to_string(),
+        plan,
+        elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+        rows,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Connection;
+
+    #[tokio::test]
+    async fn records_plans_only_when_enabled() {
+        let mut db = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE files (id INTEGER PRIMARY KEY, f1 TEXT)")
+            .execute(&mut db)
+            .await
+            .unwrap();
+        sqlx::query("CREATE INDEX files_f1 ON files (f1)")
+            .execute(&mut db)
+            .await
+            .unwrap();
+        let sql = "SELECT id FROM files WHERE f1 = ?";
+
+        record_query(&mut db, "files", sql, Duration::ZERO, 0).await;
+        assert!(!take_query_diagnostics().iter().any(|r| r.sql == sql));
+
+        set_query_diagnostics(true);
+        for _ in 0..MAX_RECORDED_QUERIES + 5 {
+            record_query(&mut db, "files", sql, Duration::from_millis(2), 1).await;
+        }
+        let recorded = take_query_diagnostics();
+        set_query_diagnostics(false);
+
+        assert_eq!(recorded.len(), MAX_RECORDED_QUERIES);
+        // Other tests running at the same time can record their queries too
+        let ours = recorded.iter().find(|r| r.sql == sql).unwrap();
+        assert!(ours.plan[0].detail.contains("INDEX files_f1"));
+        assert_eq!(ours.elapsed_ms, 2.0);
+    }
+}
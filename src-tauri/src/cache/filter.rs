@@ -0,0 +1,668 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{QueryBuilder, Sqlite};
+
+use crate::schema::types::{AttrKey, ComputedType, Schema};
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::tables::TableNames;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+    // Case insensitive substring, not for number fields
+    Includes,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum FilterValue {
+    Number(f64),
+    Text(String),
+}
+
+// Filter tree sent by frontend. Collection fields match when any of their items matches.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum Filter {
+    And {
+        filters: Vec<Filter>,
+    },
+    Or {
+        filters: Vec<Filter>,
+    },
+    Not {
+        filter: Box<Filter>,
+    },
+    Compare {
+        field: String,
+        op: CompareOp,
+        value: FilterValue,
+    },
+    // Collection has this exact item
+    Contains {
+        field: String,
+        value: String,
+    },
+    // Collection has at least one of items
+    Any {
+        field: String,
+        values: Vec<String>,
+    },
+    // Collection has every one of items
+    All {
+        field: String,
+        values: Vec<String>,
+    },
+    // Inclusive, open ended when from or to is not set. For DatesPairCollection
+    // matches when any read overlaps the range, read without one of dates is treated as a single day.
+    // With part set only that date of reads is checked.
+    DateRange {
+        field: String,
+        from: Option<String>,
+        to: Option<String>,
+        #[serde(default)]
+        part: Option<DatesPairPart>,
+    },
+    IsNull {
+        field: String,
+    },
+    // Attribute is not set, is empty text or collection without items
+    IsEmpty {
+        field: String,
+    },
+    IsNotEmpty {
+        field: String,
+    },
+    // Full text search in text attributes and body, FTS5 query syntax
+    Search {
+        query: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum DatesPairPart {
+    Started,
+    Finished,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SortKey {
+    pub field: String,
+    pub direction: SortDirection,
+}
+
+enum FieldKind {
+    Text,
+    Number,
+    Collection,
+    DatesPairs,
+}
+
+struct FieldRef {
+    kind: FieldKind,
+    ident: String,
+}
+
+fn filter_err(info: &str) -> ErrorFromRust {
+    ErrorFromRust::new("Invalid query").info(info)
+}
+
+fn resolve_field(
+    schema: &Schema,
+    t_info: &TableNames,
+    name: &str,
+) -> Result<FieldRef, ErrorFromRust> {
+    let item = schema
+        .items
+        .iter()
+        .find(|i| i.name == name)
+        .ok_or_else(|| filter_err(&format!("Unknown field \"{}\"", name)))?;
+
+    let kind = match &item.value {
+        AttrKey::Text(_) | AttrKey::Date(_) | AttrKey::Image(_) => FieldKind::Text,
+        AttrKey::Number(_) => FieldKind::Number,
+        AttrKey::Computed(settings) => match settings.as_ref().and_then(|s| s.returns.clone()) {
+            Some(ComputedType::Text) | Some(ComputedType::Date) => FieldKind::Text,
+            Some(ComputedType::Number) | None => FieldKind::Number,
+        },
+        AttrKey::TextCollection(_) | AttrKey::DateCollection(_) => FieldKind::Collection,
+        AttrKey::DatesPairCollection(_) => FieldKind::DatesPairs,
+    };
+
+    Ok(FieldRef {
+        kind,
+        ident: t_info.field(name)?.to_string(),
+    })
+}
+
+fn push_value(qb: &mut QueryBuilder<'_, Sqlite>, value: &FilterValue, numeric: bool) {
+    match (value, numeric) {
+        (FilterValue::Number(n), _) => qb.push_bind(*n),
+        // Numbers stored as REAL never equal to text, so numeric text is converted
+        (FilterValue::Text(t), true) => match t.trim().parse::<f64>() {
+            Ok(n) => qb.push_bind(n),
+            Err(_) => qb.push_bind(t.clone()),
+        },
+        (FilterValue::Text(t), false) => qb.push_bind(t.clone()),
+    };
+}
+
+fn push_compare(
+    qb: &mut QueryBuilder<'_, Sqlite>,
+    column: &str,
+    op: &CompareOp,
+    value: &FilterValue,
+    numeric: bool,
+) {
+    if *op == CompareOp::Includes {
+        let text = match value {
+            FilterValue::Number(n) => n.to_string(),
+            FilterValue::Text(t) => t.clone(),
+        };
+        // Case insensitive for any script, "(?i)" folds Unicode case
+        qb.push(format!("{} REGEXP ", column))
+            .push_bind(format!("(?i){}", regex::escape(&text)));
+        return;
+    }
+
+    let sql_op = match op {
+        CompareOp::Eq => "=",
+        CompareOp::Ne => "!=",
+        CompareOp::Lt => "<",
+        CompareOp::Lte => "<=",
+        CompareOp::Gt => ">",
+        CompareOp::Gte => ">=",
+        CompareOp::Includes => unreachable!(),
+    };
+
+    qb.push(format!("{} {} ", column, sql_op));
+    push_value(qb, value, numeric);
+}
+
+fn push_exists(qb: &mut QueryBuilder<'_, Sqlite>, side_table: &str, files_table: &str) {
+    qb.push(format!(
+        "EXISTS (SELECT 1 FROM {} WHERE {}.path = {}.path AND ",
+        side_table, side_table, files_table
+    ));
+}
+
+fn push_date_range(
+    qb: &mut QueryBuilder<'_, Sqlite>,
+    start_column: &str,
+    end_column: &str,
+    from: &Option<String>,
+    to: &Option<String>,
+) {
+    qb.push("(1");
+    if let Some(from) = from {
+        qb.push(format!(" AND {} >= ", end_column))
+            .push_bind(from.clone());
+    }
+    if let Some(to) = to {
+        qb.push(format!(" AND {} <= ", start_column))
+            .push_bind(to.clone());
+    }
+    qb.push(")");
+}
+
+pub fn push_filter(
+    qb: &mut QueryBuilder<'_, Sqlite>,
+    filter: &Filter,
+    schema: &Schema,
+    t_info: &TableNames,
+) -> Result<(), ErrorFromRust> {
+    let files_table = &t_info.files_table;
+
+    match filter {
+        Filter::And { filters } | Filter::Or { filters } => {
+            let (joiner, empty) = match filter {
+                Filter::And { .. } => (" AND ", "1"),
+                _ => (" OR ", "0"),
+            };
+
+            if filters.is_empty() {
+                qb.push(empty);
+                return Ok(());
+            }
+
+            qb.push("(");
+            for (i, f) in filters.iter().enumerate() {
+                if i > 0 {
+                    qb.push(joiner);
+                }
+                push_filter(qb, f, schema, t_info)?;
+            }
+            qb.push(")");
+        }
+        Filter::Not { filter } => {
+            // Comparison with missing value is NULL, note without the field matches negation
+            qb.push("NOT COALESCE((");
+            push_filter(qb, filter, schema, t_info)?;
+            qb.push("), 0)");
+        }
+        Filter::Compare { field, op, value } => {
+            let f = resolve_field(schema, t_info, field)?;
+            // Stored text of number ("5.0") is not what user sees, substring of it means nothing
+            if *op == CompareOp::Includes && matches!(f.kind, FieldKind::Number) {
+                return Err(filter_err(&format!(
+                    "Field \"{}\" is a number, compare it with =, < or > instead",
+                    field
+                )));
+            }
+            match f.kind {
+                FieldKind::Text | FieldKind::Number => {
+                    let column = format!("{}.{}", files_table, f.ident);
+                    push_compare(qb, &column, op, value, matches!(f.kind, FieldKind::Number));
+                }
+                FieldKind::Collection => {
+                    push_exists(qb, &f.ident, files_table);
+                    push_compare(qb, &format!("{}.value", f.ident), op, value, false);
+                    qb.push(")");
+                }
+                FieldKind::DatesPairs => {
+                    return Err(filter_err(&format!(
+                        "Field \"{}\" can be filtered only by date range",
+                        field
+                    )));
+                }
+            }
+        }
+        Filter::Contains { field, value } => {
+            push_filter(
+                qb,
+                &Filter::Any {
+                    field: field.clone(),
+                    values: vec![value.clone()],
+                },
+                schema,
+                t_info,
+            )?;
+        }
+        Filter::Any { field, values } | Filter::All { field, values } => {
+            let f = resolve_field(schema, t_info, field)?;
+            if !matches!(f.kind, FieldKind::Collection) {
+                return Err(filter_err(&format!(
+                    "Field \"{}\" is not a collection",
+                    field
+                )));
+            }
+
+            let mut distinct = values.clone();
+            distinct.sort();
+            distinct.dedup();
+
+            if distinct.is_empty() {
+                // Nothing to look for: any of nothing is false, all of nothing is true
+                qb.push(if matches!(filter, Filter::All { .. }) {
+                    "1"
+                } else {
+                    "0"
+                });
+                return Ok(());
+            }
+
+            let is_all = matches!(filter, Filter::All { .. });
+            if is_all {
+                qb.push(format!(
+                    "(SELECT COUNT(DISTINCT {}.value) FROM {} WHERE {}.path = {}.path AND ",
+                    f.ident, f.ident, f.ident, files_table
+                ));
+            } else {
+                push_exists(qb, &f.ident, files_table);
+            }
+
+            qb.push(format!("{}.value IN (", f.ident));
+            let mut separated = qb.separated(", ");
+            for v in distinct.iter() {
+                separated.push_bind(v.clone());
+            }
+            separated.push_unseparated(")");
+
+            if is_all {
+                qb.push(format!(") = {}", distinct.len()));
+            } else {
+                qb.push(")");
+            }
+        }
+        Filter::DateRange {
+            field,
+            from,
+            to,
+            part,
+        } => {
+            let f = resolve_field(schema, t_info, field)?;
+            if part.is_some() && !matches!(f.kind, FieldKind::DatesPairs) {
+                return Err(filter_err(&format!(
+                    "Field \"{}\" has no started and finished dates",
+                    field
+                )));
+            }
+
+            match f.kind {
+                FieldKind::Text => {
+                    let column = format!("{}.{}", files_table, f.ident);
+                    push_date_range(qb, &column, &column, from, to);
+                }
+                FieldKind::Collection => {
+                    let column = format!("{}.value", f.ident);
+                    push_exists(qb, &f.ident, files_table);
+                    push_date_range(qb, &column, &column, from, to);
+                    qb.push(")");
+                }
+                FieldKind::DatesPairs => {
+                    let (start, end) = match part {
+                        Some(DatesPairPart::Started) => {
+                            let c = format!("{}.started", f.ident);
+                            (c.clone(), c)
+                        }
+                        Some(DatesPairPart::Finished) => {
+                            let c = format!("{}.finished", f.ident);
+                            (c.clone(), c)
+                        }
+                        None => (
+                            format!("COALESCE({0}.started, {0}.finished)", f.ident),
+                            format!("COALESCE({0}.finished, {0}.started)", f.ident),
+                        ),
+                    };
+                    push_exists(qb, &f.ident, files_table);
+                    push_date_range(qb, &start, &end, from, to);
+                    qb.push(")");
+                }
+                FieldKind::Number => {
+                    return Err(filter_err(&format!("Field \"{}\" is not a date", field)));
+                }
+            }
+        }
+        Filter::Search { query } => {
+            qb.push(format!(
+                "{0}.id IN (SELECT rowid FROM {1} WHERE {1} MATCH ",
+                files_table, t_info.fts_table
+            ))
+            .push_bind(query.clone())
+            .push(")");
+        }
+        Filter::IsNull { field } => {
+            let f = resolve_field(schema, t_info, field)?;
+            match f.kind {
+                FieldKind::Text | FieldKind::Number => {
+                    qb.push(format!("{}.{} IS NULL", files_table, f.ident));
+                }
+                FieldKind::Collection | FieldKind::DatesPairs => {
+                    qb.push("NOT ");
+                    push_exists(qb, &f.ident, files_table);
+                    qb.push("1)");
+                }
+            }
+        }
+        Filter::IsEmpty { field } | Filter::IsNotEmpty { field } => {
+            let f = resolve_field(schema, t_info, field)?;
+            if matches!(filter, Filter::IsEmpty { .. }) {
+                qb.push("NOT ");
+            }
+            match f.kind {
+                FieldKind::Text => {
+                    qb.push(format!("(IFNULL({}.{}, '') != '')", files_table, f.ident));
+                }
+                FieldKind::Number => {
+                    qb.push(format!("({}.{} IS NOT NULL)", files_table, f.ident));
+                }
+                FieldKind::Collection => {
+                    push_exists(qb, &f.ident, files_table);
+                    qb.push(format!("{}.value != '')", f.ident));
+                }
+                FieldKind::DatesPairs => {
+                    push_exists(qb, &f.ident, files_table);
+                    qb.push("1)");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Collections are sorted by first item, reads by the latest date
+pub fn sort_expression(
+    schema: &Schema,
+    t_info: &TableNames,
+    key: &SortKey,
+) -> Result<String, ErrorFromRust> {
+    let f = resolve_field(schema, t_info, &key.field)?;
+    let files_table = &t_info.files_table;
+
+    Ok(match f.kind {
+        FieldKind::Text | FieldKind::Number => format!("{}.{}", files_table, f.ident),
+        FieldKind::Collection => format!(
+            "(SELECT {0}.value FROM {0} WHERE {0}.path = {1}.path ORDER BY {0}.ind LIMIT 1)",
+            f.ident, files_table
+        ),
+        FieldKind::DatesPairs => format!(
+            "(SELECT MAX(COALESCE({0}.finished, {0}.started)) FROM {0} WHERE {0}.path = {1}.path)",
+            f.ident, files_table
+        ),
+    })
+}
+
+// Order is expressed as list of (expression, descending). Nulls always go last,
+// path is the final key so order is total and keyset pagination is stable.
+pub fn order_components(sort_exprs: &[(String, bool)], files_table: &str) -> Vec<(String, bool)> {
+    let mut res: Vec<(String, bool)> = Vec::new();
+    for (expr, desc) in sort_exprs {
+        res.push((format!("({} IS NULL)", expr), false));
+        res.push((expr.clone(), *desc));
+    }
+    res.push((format!("{}.path", files_table), false));
+    res
+}
+
+pub fn push_order_by(qb: &mut QueryBuilder<'_, Sqlite>, components: &[(String, bool)]) {
+    qb.push(" ORDER BY ");
+    let mut separated = qb.separated(", ");
+    for (expr, desc) in components {
+        separated.push(format!("{} {}", expr, if *desc { "DESC" } else { "ASC" }));
+    }
+}
+
+// Cursor holds values of order components of the last row on page
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Cursor {
+    values: Vec<Value>,
+}
+
+pub fn encode_cursor(values: Vec<Value>) -> String {
+    serde_json::to_string(&Cursor { values }).unwrap_or_default()
+}
+
+pub fn decode_cursor(cursor: &str, components: usize) -> Result<Vec<Value>, ErrorFromRust> {
+    let c: Cursor =
+        serde_json::from_str(cursor).map_err(|e| filter_err("Cursor is malformed").raw(e))?;
+
+    if c.values.len() != components {
+        return Err(filter_err("Cursor does not belong to this sorting"));
+    }
+
+    Ok(c.values)
+}
+
+fn push_json_value(qb: &mut QueryBuilder<'_, Sqlite>, value: &Value) {
+    match value {
+        Value::Number(n) => {
+            qb.push_bind(n.as_f64());
+        }
+        Value::String(s) => {
+            qb.push_bind(s.clone());
+        }
+        _ => {
+            qb.push_bind(Option::<String>::None);
+        }
+    }
+}
+
+// Rows strictly after cursor: (c1 > v1) OR (c1 IS v1 AND c2 > v2) OR ...
+// Comparison with NULL is never true, null groups are handled by "IS NULL" components.
+pub fn push_after_cursor(
+    qb: &mut QueryBuilder<'_, Sqlite>,
+    components: &[(String, bool)],
+    values: &[Value],
+) {
+    qb.push("(");
+    for i in 0..components.len() {
+        if i > 0 {
+            qb.push(" OR ");
+        }
+        qb.push("(");
+        for j in 0..i {
+            qb.push(format!("{} IS ", components[j].0));
+            push_json_value(qb, &values[j]);
+            qb.push(" AND ");
+        }
+        let (expr, desc) = &components[i];
+        qb.push(format!("{} {} ", expr, if *desc { "<" } else { ">" }));
+        push_json_value(qb, &values[i]);
+        qb.push(")");
+    }
+    qb.push(")");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::types::SchemaItem;
+    use sqlx::sqlite::SqliteConnectOptions;
+    use sqlx::{ConnectOptions, Row};
+    use std::collections::HashMap;
+
+    fn schema() -> (Schema, TableNames) {
+        let item = |name: &str, value: AttrKey| SchemaItem {
+            name: name.to_string(),
+            value,
+        };
+        let schema = Schema {
+            name: "Books".to_string(),
+            version: "1.0".to_string(),
+            icon: None,
+            items: vec![
+                item("author", AttrKey::Text(None)),
+                item("rating", AttrKey::Number(None)),
+                item("tags", AttrKey::TextCollection(None)),
+            ],
+            internal_path: "".to_string(),
+            internal_name: "".to_string(),
+        };
+        let t_info = TableNames {
+            files_table: "files".to_string(),
+            folders_table: "folders".to_string(),
+            fts_table: "fts".to_string(),
+            fields: HashMap::from([
+                ("author".to_string(), "f1".to_string()),
+                ("rating".to_string(), "f2".to_string()),
+                ("tags".to_string(), "f3".to_string()),
+            ]),
+        };
+        (schema, t_info)
+    }
+
+    async fn matching(filter: Filter) -> Vec<String> {
+        let mut db = SqliteConnectOptions::new()
+            .filename(":memory:")
+            .with_regexp()
+            .connect()
+            .await
+            .unwrap();
+
+        for q in [
+            "CREATE TABLE files (id INTEGER PRIMARY KEY, path TEXT, f1 TEXT, f2 REAL)",
+            "CREATE TABLE f3 (path TEXT, ind INTEGER, value TEXT)",
+            "INSERT INTO files (path, f1, f2) VALUES
+                ('a', 'Лев Толстой', 20), ('b', 'Émile Zola', 5),
+                ('c', 'Ursula K. Le Guin', NULL), ('d', NULL, 10)",
+            "INSERT INTO f3 (path, ind, value) VALUES ('a', 0, 'Классика'), ('c', 0, 'SciFi')",
+        ] {
+            sqlx::query(q).execute(&mut db).await.unwrap();
+        }
+
+        let (schema, t_info) = schema();
+        let mut qb: QueryBuilder<'_, Sqlite> = QueryBuilder::new("SELECT path FROM files WHERE ");
+        push_filter(&mut qb, &filter, &schema, &t_info).unwrap();
+        qb.push(" ORDER BY path");
+
+        qb.build()
+            .fetch_all(&mut db)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get("path"))
+            .collect()
+    }
+
+    fn includes(field: &str, value: &str) -> Filter {
+        Filter::Compare {
+            field: field.to_string(),
+            op: CompareOp::Includes,
+            value: FilterValue::Text(value.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn includes_ignores_case_of_any_script() {
+        assert_eq!(matching(includes("author", "толстой")).await, vec!["a"]);
+        assert_eq!(matching(includes("author", "ÉMILE")).await, vec!["b"]);
+        assert_eq!(matching(includes("author", "k. le")).await, vec!["c"]);
+        // Regex syntax in value is matched literally
+        assert!(matching(includes("author", ".*")).await.is_empty());
+        assert_eq!(matching(includes("tags", "КЛАСС")).await, vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn negation_includes_notes_without_field() {
+        let above_fifteen = Filter::Compare {
+            field: "rating".to_string(),
+            op: CompareOp::Gt,
+            value: FilterValue::Number(15.0),
+        };
+        assert_eq!(matching(above_fifteen.clone()).await, vec!["a"]);
+        assert_eq!(
+            matching(Filter::Not {
+                filter: Box::new(above_fifteen)
+            })
+            .await,
+            vec!["b", "c", "d"]
+        );
+    }
+
+    #[tokio::test]
+    async fn includes_is_rejected_for_numbers() {
+        // Stored as 10.0, 20.0 and 5.0, "0" would match all of them
+        let (schema, t_info) = schema();
+        for value in [FilterValue::Text("0".to_string()), FilterValue::Number(0.0)] {
+            let mut qb: QueryBuilder<'_, Sqlite> = QueryBuilder::new("");
+            let filter = Filter::Compare {
+                field: "rating".to_string(),
+                op: CompareOp::Includes,
+                value,
+            };
+            assert!(push_filter(&mut qb, &filter, &schema, &t_info).is_err());
+        }
+
+        let exactly = |n: f64| Filter::Compare {
+            field: "rating".to_string(),
+            op: CompareOp::Eq,
+            value: FilterValue::Number(n),
+        };
+        assert_eq!(matching(exactly(5.0)).await, vec!["b"]);
+        assert_eq!(matching(exactly(10.0)).await, vec!["d"]);
+        assert!(matching(exactly(0.0)).await.is_empty());
+    }
+}
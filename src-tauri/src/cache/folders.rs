@@ -0,0 +1,420 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqliteConnection};
+
+use crate::schema::operations::{get_all_schemas_cached, get_schema_cached};
+use crate::schema::types::Schema;
+use crate::utils::changes::ChangedPath;
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::dbconn::get_read_conn;
+use super::diagnostics::record_query;
+use super::tables::find_table_names;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FolderNode {
+    pub path: String,
+    pub name: String,
+    pub schema_path: String,
+    // Notes directly in this folder
+    pub count: i64,
+    // Notes in this folder and all its subfolders
+    pub total: i64,
+    pub children: Vec<FolderNode>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FolderCounts {
+    pub path: String,
+    pub count: i64,
+    pub total: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FolderTreeChangedEmit {
+    #[serde(rename = "schemaPath")]
+    pub schema_path: String,
+    // New counts of folders containing changed paths, up to schema folder
+    pub updated: Vec<FolderCounts>,
+    // Folders that appeared, with all their subfolders
+    pub added: Vec<FolderNode>,
+    pub removed: Vec<String>,
+}
+
+fn parent_str(path: &str) -> Option<String> {
+    Path::new(path)
+        .parent()
+        .map(|p| p.to_string_lossy().to_string())
+}
+
+// Folders of schema with direct counts, total and children are filled by link_folders.
+// With under set only that folder and folders inside of it.
+async fn load_folders(
+    db: &mut SqliteConnection,
+    schema: &Schema,
+    under: Option<&str>,
+) -> Result<Vec<FolderNode>, ErrorFromRust> {
+    let t_info = match find_table_names(db, schema).await {
+        Ok(t) => t,
+        Err(sqlx::Error::RowNotFound) => return Ok(Vec::new()),
+        Err(e) => return Err(ErrorFromRust::new("Error when getting table names").raw(e)),
+    };
+
+    let prefix = under.map(|u| format!("{}{}", u, MAIN_SEPARATOR));
+    let in_prefix = "(?1 IS NULL OR path = ?2 OR substr(path, 1, length(?1)) = ?1)";
+
+    let sql = format!(
+        "SELECT path, name FROM {} WHERE {}",
+        t_info.folders_table, in_prefix
+    );
+    let started = Instant::now();
+    let folders = sqlx::query(&sql)
+        .bind(&prefix)
+        .bind(under)
+        .fetch_all(&mut *db)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error getting folder list").raw(e))?;
+    record_query(db, "folder tree", &sql, started.elapsed(), folders.len()).await;
+
+    let sql = format!(
+        "SELECT path FROM {} WHERE {}",
+        t_info.files_table, in_prefix
+    );
+    let started = Instant::now();
+    let files = sqlx::query(&sql)
+        .bind(&prefix)
+        .bind(under)
+        .fetch_all(&mut *db)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when counting notes").raw(e))?;
+    record_query(db, "folder tree", &sql, started.elapsed(), files.len()).await;
+
+    let mut counts: HashMap<String, i64> = HashMap::new();
+    for row in files.iter() {
+        if let Some(parent) = parent_str(row.get("path")) {
+            *counts.entry(parent).or_default() += 1;
+        }
+    }
+
+    Ok(folders
+        .iter()
+        .map(|row| {
+            let path: String = row.get("path");
+            FolderNode {
+                count: counts.get(&path).copied().unwrap_or(0),
+                total: 0,
+                name: row.get("name"),
+                schema_path: schema.internal_path.clone(),
+                children: Vec::new(),
+                path,
+            }
+        })
+        .collect())
+}
+
+fn fill_totals(node: &mut FolderNode) -> i64 {
+    node.children.sort_by_key(|c| c.name.to_lowercase());
+    node.total = node.count + node.children.iter_mut().map(fill_totals).sum::<i64>();
+    node.total
+}
+
+// Folders whose parent is not in the list become roots
+fn link_folders(mut folders: Vec<FolderNode>) -> Vec<FolderNode> {
+    // Deepest first, so children are complete when they are moved to parent
+    folders.sort_by(|a, b| b.path.cmp(&a.path));
+
+    let mut by_path: HashMap<String, FolderNode> = folders
+        .iter()
+        .map(|f| (f.path.clone(), f.clone()))
+        .collect();
+
+    let mut roots: Vec<FolderNode> = Vec::new();
+    for folder in folders.iter() {
+        let Some(node) = by_path.remove(&folder.path) else {
+            continue;
+        };
+        match parent_str(&node.path).and_then(|p| by_path.get_mut(&p)) {
+            Some(parent) => parent.children.push(node),
+            None => roots.push(node),
+        }
+    }
+
+    for root in roots.iter_mut() {
+        fill_totals(root);
+    }
+    roots.sort_by(|a, b| a.path.cmp(&b.path));
+    roots
+}
+
+// Folders of all schemas, schema folders are roots. Schema inside of another schema folder
+// is shown where it is on disk.
+pub async fn get_folder_tree() -> Result<Vec<FolderNode>, ErrorFromRust> {
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let mut folders: Vec<FolderNode> = Vec::new();
+    for schema in get_all_schemas_cached().await {
+        folders.extend(load_folders(&mut db, &schema, None).await?);
+    }
+
+    Ok(link_folders(folders))
+}
+
+async fn folder_counts(
+    db: &mut SqliteConnection,
+    schema: &Schema,
+    path: &str,
+) -> Result<FolderCounts, ErrorFromRust> {
+    let t_info = find_table_names(db, schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
+
+    let sql = format!(
+        "SELECT COUNT(*) AS total,
+        COALESCE(SUM(instr(substr(path, length(?1) + 1), ?2) = 0), 0) AS count
+        FROM {} WHERE substr(path, 1, length(?1)) = ?1",
+        t_info.files_table
+    );
+    let started = Instant::now();
+    let row = sqlx::query(&sql)
+        .bind(format!("{}{}", path, MAIN_SEPARATOR))
+        .bind(MAIN_SEPARATOR.to_string())
+        .fetch_one(&mut *db)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when counting notes").raw(e))?;
+    record_query(db, "folder counts", &sql, started.elapsed(), 1).await;
+
+    Ok(FolderCounts {
+        path: path.to_string(),
+        count: row.get("count"),
+        total: row.get("total"),
+    })
+}
+
+// Removed folder can't be checked with is_dir, so watcher asks cache before removing it.
+// Folder names may have dots, extension does not tell folder from file.
+pub async fn is_cached_folder(path: &Path) -> Result<bool, ErrorFromRust> {
+    let path_str = path.to_string_lossy().to_string();
+    let Some(schema) = get_schema_cached(&path_str).await else {
+        return Ok(false);
+    };
+
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let t_info = match find_table_names(&mut db, &schema).await {
+        Ok(t) => t,
+        Err(sqlx::Error::RowNotFound) => return Ok(false),
+        Err(e) => return Err(ErrorFromRust::new("Error when getting table names").raw(e)),
+    };
+
+    let sql = format!("SELECT 1 FROM {} WHERE path = ?", t_info.folders_table);
+    let started = Instant::now();
+    let found = sqlx::query(&sql)
+        .bind(&path_str)
+        .fetch_optional(&mut *db)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when reading folders").raw(e))?;
+    record_query(
+        &mut db,
+        "folder lookup",
+        &sql,
+        started.elapsed(),
+        usize::from(found.is_some()),
+    )
+    .await;
+
+    Ok(found.is_some())
+}
+
+// Called with paths of schema reported by watcher since last emit, after cache is updated.
+// Every folder is counted once, however many notes changed in it.
+pub async fn folder_tree_changed_by(
+    schema: &Schema,
+    changes: &[&ChangedPath],
+) -> Result<Option<FolderTreeChangedEmit>, ErrorFromRust> {
+    // Last event for path wins, folder can be removed and created again
+    let mut paths: BTreeMap<&Path, bool> = BTreeMap::new();
+    for change in changes.iter().filter(|c| c.tree_changed) {
+        let is_note = change.path.extension().is_some_and(|e| e == "md");
+        if change.is_folder || is_note {
+            paths.insert(&change.path, change.is_folder);
+        }
+    }
+    if paths.is_empty() {
+        return Ok(None);
+    }
+
+    let (added_paths, removed) = changed_folders(&paths);
+
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let mut added: Vec<FolderNode> = Vec::new();
+    for folder in added_paths.iter() {
+        let folders = load_folders(&mut db, schema, Some(&folder.to_string_lossy())).await?;
+        added.extend(link_folders(folders).into_iter().next());
+    }
+
+    let mut updated: Vec<FolderCounts> = Vec::new();
+    for folder in folders_to_count(&paths, &added_paths, &schema.internal_path) {
+        // Folder containing removed path may be removed too
+        if folder.is_dir() {
+            updated.push(folder_counts(&mut db, schema, &folder.to_string_lossy()).await?);
+        }
+    }
+
+    if updated.is_empty() && added.is_empty() && removed.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(FolderTreeChangedEmit {
+        schema_path: schema.internal_path.clone(),
+        updated,
+        added,
+        removed,
+    }))
+}
+
+// Topmost added and removed folders, subfolders come with them
+fn changed_folders(paths: &BTreeMap<&Path, bool>) -> (Vec<PathBuf>, Vec<String>) {
+    let folders: Vec<&Path> = paths
+        .iter()
+        .filter(|(_, is_folder)| **is_folder)
+        .map(|(p, _)| *p)
+        .collect();
+
+    let mut added: Vec<PathBuf> = Vec::new();
+    let mut removed: Vec<String> = Vec::new();
+    for folder in folders.iter() {
+        let exists = folder.is_dir();
+        let nested = folders
+            .iter()
+            .any(|f| f != folder && folder.starts_with(f) && f.is_dir() == exists);
+        match (exists, nested) {
+            (_, true) => (),
+            (true, false) => added.push(folder.to_path_buf()),
+            (false, false) => removed.push(folder.to_string_lossy().to_string()),
+        }
+    }
+
+    (added, removed)
+}
+
+// Folders from parents of changed paths up to schema folder. Added folders are sent
+// with counts of whole subtree, so folders inside of them are skipped.
+fn folders_to_count(
+    paths: &BTreeMap<&Path, bool>,
+    added: &[PathBuf],
+    schema_path: &str,
+) -> BTreeSet<PathBuf> {
+    let mut folders: BTreeSet<PathBuf> = BTreeSet::new();
+    for path in paths.keys() {
+        for folder in path.ancestors().skip(1) {
+            if !folder.starts_with(schema_path) {
+                break;
+            }
+            if !added.iter().any(|a| folder.starts_with(a)) {
+                folders.insert(folder.to_path_buf());
+            }
+        }
+    }
+    folders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(path: &str, count: i64) -> FolderNode {
+        FolderNode {
+            path: path.to_string(),
+            name: Path::new(path)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+            schema_path: "/v/books".to_string(),
+            count,
+            total: 0,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn link_folders_nests_children_and_sums_totals() {
+        let roots = link_folders(vec![
+            folder("/v/books/b", 1),
+            folder("/v/books", 2),
+            folder("/v/books/a/deep", 4),
+            folder("/v/books/A2", 8),
+            folder("/v/books/a", 16),
+        ]);
+
+        assert_eq!(roots.len(), 1);
+        let root = &roots[0];
+        assert_eq!(root.total, 31);
+        let names: Vec<&str> = root.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "A2", "b"]);
+        assert_eq!(root.children[0].total, 20);
+        assert_eq!(root.children[0].children[0].path, "/v/books/a/deep");
+    }
+
+    #[test]
+    fn link_folders_keeps_folders_without_parent_as_roots() {
+        let roots = link_folders(vec![
+            folder("/v/movies", 1),
+            folder("/v/books/read", 2),
+            folder("/v/books/read/2024", 3),
+        ]);
+
+        let paths: Vec<&str> = roots.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["/v/books/read", "/v/movies"]);
+        assert_eq!(roots[0].total, 5);
+        assert_eq!(roots[1].total, 1);
+    }
+
+    #[test]
+    fn folders_to_count_counts_each_ancestor_once() {
+        let paths: BTreeMap<&Path, bool> = BTreeMap::from([
+            (Path::new("/v/books/read/a.md"), false),
+            (Path::new("/v/books/read/b.md"), false),
+            (Path::new("/v/books/new/sub/c.md"), false),
+            (Path::new("/v/books/new"), true),
+            (Path::new("/v/books/other.md"), false),
+        ]);
+
+        let folders = folders_to_count(&paths, &[PathBuf::from("/v/books/new")], "/v/books");
+        let folders: Vec<&str> = folders.iter().map(|f| f.to_str().unwrap()).collect();
+        assert_eq!(folders, vec!["/v/books", "/v/books/read"]);
+    }
+
+    #[test]
+    fn changed_folders_keeps_topmost() {
+        let root = std::env::temp_dir().join(format!("folders_{}", std::process::id()));
+        let added = root.join("added");
+        std::fs::create_dir_all(added.join("sub")).unwrap();
+        let removed = root.join("removed");
+        let (added_sub, removed_sub) = (added.join("sub"), removed.join("sub"));
+        let note = root.join("note.md");
+
+        let paths: BTreeMap<&Path, bool> = BTreeMap::from([
+            (added.as_path(), true),
+            (added_sub.as_path(), true),
+            (removed.as_path(), true),
+            (removed_sub.as_path(), true),
+            (note.as_path(), false),
+        ]);
+        let result = changed_folders(&paths);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(result.0, vec![added.clone()]);
+        assert_eq!(result.1, vec![removed.to_string_lossy().to_string()]);
+    }
+}
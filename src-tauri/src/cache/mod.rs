@@ -1,4 +1,16 @@
+pub mod aggregate;
+#[cfg(feature = "benchmark")]
+pub mod benchmark;
 pub mod tables;
 pub mod dbconn;
+pub mod diagnostics;
+pub mod filter;
+pub mod folders;
 pub mod query;
+pub mod query_parser;
+pub mod quick_open;
+pub mod search;
+pub mod tags;
+pub mod values;
+pub mod verify;
 pub mod write;
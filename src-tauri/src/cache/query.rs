@@ -1,14 +1,25 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 use sqlx::sqlite::SqliteRow;
-use sqlx::Row;
+use sqlx::{QueryBuilder, Row, Sqlite, TypeInfo, ValueRef};
 use std::collections::HashMap;
+use std::path::MAIN_SEPARATOR;
+use std::time::Instant;
 
 use crate::schema::operations::get_schema_cached_safe;
-use crate::schema::types::{AttrKey, AttrValue, DateRead, Schema};
+use crate::schema::types::{AttrKey, AttrValue, ComputedType, DateRead, Schema};
 use crate::utils::errorhandling::ErrorFromRust;
 
-use super::dbconn::get_db_conn;
-use super::tables::get_table_names;
+use super::dbconn::get_read_conn;
+use super::diagnostics::record_query;
+use super::filter::{
+    decode_cursor, encode_cursor, order_components, push_after_cursor, push_filter, push_order_by,
+    sort_expression, Filter, SortDirection, SortKey,
+};
+use super::search::{
+    check_search, parse_snippet, rank_expression, search_join, snippet_expression, SearchHit,
+};
+use super::tables::{find_table_names, TableNames};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BookFromDb {
@@ -18,6 +29,9 @@ pub struct BookFromDb {
     pub modified: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub markdown: Option<String>,
+    // Hash of file content that was parsed, only used by cache
+    #[serde(skip)]
+    pub hash: Option<String>,
 
     pub attrs: HashMap<String, AttrValue>,
 }
@@ -29,80 +43,291 @@ impl Default for BookFromDb {
             modified: None,
             path: None,
             markdown: None,
+            hash: None,
         }
     }
 }
 
-pub async fn get_files_abstact(
-    where_clause: String,
-    schema: Schema,
-) -> Result<Vec<BookFromDb>, ErrorFromRust> {
-    let mut db = get_db_conn().lock().await;
+// Conditions are joined with AND. Values are always bound, never formatted into SQL.
+#[derive(Clone, Debug)]
+pub enum QueryCondition {
+    // Files directly in folder, or also in all its subfolders
+    InFolder {
+        path: String,
+        include_subfolders: bool,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct FilesQuery {
+    pub schema: Schema,
+    pub conditions: Vec<QueryCondition>,
+    pub filter: Option<Filter>,
+    pub sort: Vec<SortKey>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    // Returned as next_cursor of previous page, can't be combined with offset
+    pub cursor: Option<String>,
+    // Full text search, results are ordered by relevance unless sort is set
+    pub search: Option<String>,
+}
+
+impl FilesQuery {
+    pub fn new(schema: Schema) -> Self {
+        FilesQuery {
+            schema,
+            conditions: Vec::new(),
+            filter: None,
+            sort: Vec::new(),
+            limit: None,
+            offset: None,
+            cursor: None,
+            search: None,
+        }
+    }
+
+    pub fn in_folder(mut self, path: &str, include_subfolders: bool) -> Self {
+        self.conditions.push(QueryCondition::InFolder {
+            path: path.to_string(),
+            include_subfolders,
+        });
+        self
+    }
+
+    pub fn filter(mut self, filter: Option<Filter>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn sort(mut self, sort: Vec<SortKey>) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    pub fn search(mut self, search: Option<String>) -> Self {
+        self.search = search.filter(|s| !s.trim().is_empty());
+        self
+    }
+
+    pub fn page(mut self, limit: Option<u32>, offset: Option<u32>, cursor: Option<String>) -> Self {
+        self.limit = limit;
+        self.offset = offset;
+        self.cursor = cursor;
+        self
+    }
+
+    pub fn push_where(
+        &self,
+        qb: &mut QueryBuilder<'_, Sqlite>,
+        t_info: &TableNames,
+        components: &[(String, bool)],
+        after: Option<&[Value]>,
+    ) -> Result<(), ErrorFromRust> {
+        let files_table = &t_info.files_table;
+        let mut clauses = 0;
+
+        for condition in self.conditions.iter() {
+            push_clause_start(qb, &mut clauses);
+
+            match condition {
+                QueryCondition::InFolder {
+                    path,
+                    include_subfolders,
+                } => {
+                    // Prefix ends with separator, so "books" doesn't match "books old".
+                    // substr is used instead of LIKE, where "_" and "%" in folder names are wildcards.
+                    let prefix = folder_prefix(path);
+
+                    qb.push(format!("substr({}.path, 1, length(", files_table))
+                        .push_bind(prefix.clone())
+                        .push(")) = ")
+                        .push_bind(prefix.clone());
+
+                    if !include_subfolders {
+                        qb.push(format!(" AND instr(substr({}.path, length(", files_table))
+                            .push_bind(prefix)
+                            .push(") + 1), ")
+                            .push_bind(MAIN_SEPARATOR.to_string())
+                            .push(") = 0");
+                    }
+                }
+            }
+        }
 
-    let t_info = get_table_names(schema.internal_name.clone());
-    let files_table = t_info.files_table;
-    let table_prefix = t_info.table_prefix;
+        if let Some(filter) = &self.filter {
+            push_clause_start(qb, &mut clauses);
+            push_filter(qb, filter, &self.schema, t_info)?;
+        }
+
+        // Full text table is joined when search is set
+        if let Some(search) = &self.search {
+            push_clause_start(qb, &mut clauses);
+            qb.push(format!("{} MATCH ", t_info.fts_table))
+                .push_bind(search.clone());
+        }
+
+        if let Some(values) = after {
+            push_clause_start(qb, &mut clauses);
+            push_after_cursor(qb, components, values);
+        }
+
+        Ok(())
+    }
+}
+
+fn push_clause_start(qb: &mut QueryBuilder<'_, Sqlite>, clauses: &mut usize) {
+    qb.push(if *clauses == 0 { " WHERE " } else { " AND " });
+    *clauses += 1;
+}
+
+fn folder_prefix(path: &str) -> String {
+    format!("{}{}", path.trim_end_matches(['/', '\\']), MAIN_SEPARATOR)
+}
+
+pub async fn get_files_abstact(query: &FilesQuery) -> Result<Vec<BookFromDb>, ErrorFromRust> {
+    Ok(get_files_page(query).await?.books)
+}
+
+pub struct FilesPage {
+    pub books: Vec<BookFromDb>,
+    // Set when limit was used and there are more files after this page
+    pub next_cursor: Option<String>,
+    // In the same order as books, empty without search
+    pub search_hits: Vec<SearchHit>,
+}
+
+pub async fn get_files_page(query: &FilesQuery) -> Result<FilesPage, ErrorFromRust> {
+    let schema = &query.schema;
+
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let t_info = find_table_names(&mut db, schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
+    let files_table = t_info.files_table.clone();
 
     let mut joins: Vec<String> = Vec::new();
     let mut selects: Vec<String> = Vec::new();
 
     for schema_i in schema.items.clone() {
-        let columm_name = schema_i.name.to_owned();
-        let table_name = format!("{}{}", table_prefix, columm_name);
+        let ident = t_info.field(&schema_i.name)?.to_string();
         match schema_i.value {
+            // Items are aggregated to JSON array, so commas or any other characters in values survive.
+            // Files without items get NULL from LEFT JOIN.
             AttrKey::TextCollection(_) | AttrKey::DateCollection(_) => {
-                selects.push(columm_name.clone());
+                selects.push(ident.clone());
                 joins.push(format!(
                     "LEFT JOIN
-                    (SELECT {}.path as {}_path, GROUP_CONCAT({}.value, ',') 
-                    AS {} FROM {} GROUP BY {}.path) 
-                    ON {}.path = {}_path",
-                    table_name,
-                    columm_name,
-                    table_name,
-                    columm_name,
-                    table_name,
-                    table_name,
-                    files_table,
-                    columm_name
+                    (SELECT {0}.path AS {0}_path, json_group_array({0}.value ORDER BY {0}.ind)
+                    AS {0} FROM {0} GROUP BY {0}.path)
+                    ON {1}.path = {0}_path",
+                    ident, files_table
                 ));
             }
             AttrKey::DatesPairCollection(_) => {
-                selects.push(columm_name.clone());
+                selects.push(ident.clone());
                 joins.push(format!(
-                    "LEFT JOIN 
-                    (SELECT {}.path as {}_path, 
-                    GROUP_CONCAT(IFNULL({}.started,'') || '|' || IFNULL({}.finished,''), ',') 
-                    AS {} FROM {} GROUP BY {}.path)
-                    ON {}.path = {}_path",
-                    table_name,
-                    columm_name,
-                    table_name,
-                    table_name,
-                    columm_name,
-                    table_name,
-                    table_name,
-                    files_table,
-                    columm_name,
+                    "LEFT JOIN
+                    (SELECT {0}.path AS {0}_path,
+                    json_group_array(json_object('started', {0}.started, 'finished', {0}.finished)
+                    ORDER BY {0}.ind)
+                    AS {0} FROM {0} GROUP BY {0}.path)
+                    ON {1}.path = {0}_path",
+                    ident, files_table
                 ));
             }
-            AttrKey::Text(_) | AttrKey::Number(_) | AttrKey::Image(_) | AttrKey::Date(_) => {
-                selects.push(columm_name)
-            }
+            AttrKey::Text(_)
+            | AttrKey::Number(_)
+            | AttrKey::Image(_)
+            | AttrKey::Date(_)
+            | AttrKey::Computed(_) => selects.push(format!("{}.{}", files_table, ident)),
         }
     }
 
-    let q = format!(
-        "SELECT path, modified, {} FROM {} {} {}",
-        selects.join(", "),
+    if let Some(search) = &query.search {
+        check_search(&mut db, &t_info, search).await?;
+
+        joins.insert(0, search_join(&t_info));
+        selects.push(format!("{} AS search_rank", rank_expression(&t_info)));
+        selects.push(format!("{} AS search_snippet", snippet_expression(&t_info)));
+    }
+
+    let mut sort_exprs = query
+        .sort
+        .iter()
+        .map(|k| {
+            Ok((
+                sort_expression(schema, &t_info, k)?,
+                k.direction == SortDirection::Desc,
+            ))
+        })
+        .collect::<Result<Vec<(String, bool)>, ErrorFromRust>>()?;
+
+    if query.search.is_some() && sort_exprs.is_empty() {
+        sort_exprs.push((rank_expression(&t_info), false));
+    }
+    let components = order_components(&sort_exprs, &files_table);
+
+    // Values of order components are selected too, last row of page becomes the cursor
+    for (i, (expr, _)) in components.iter().enumerate() {
+        selects.push(format!("{} AS sort_{}", expr, i));
+    }
+
+    let after = match &query.cursor {
+        Some(_) if query.offset.is_some() => {
+            return Err(ErrorFromRust::new("Invalid query").info("Use either cursor or offset"));
+        }
+        Some(cursor) => Some(decode_cursor(cursor, components.len())?),
+        None => None,
+    };
+
+    let mut qb: QueryBuilder<'_, Sqlite> = QueryBuilder::new(format!(
+        "SELECT {}.path AS path, modified{} FROM {} {}",
+        files_table,
+        selects
+            .iter()
+            .map(|c| format!(", {}", c))
+            .collect::<String>(),
         files_table,
         joins.join(" "),
-        where_clause
-    );
+    ));
+
+    query.push_where(&mut qb, &t_info, &components, after.as_deref())?;
+    push_order_by(&mut qb, &components);
+
+    // One extra row tells if there is a next page
+    match (query.limit, query.offset) {
+        (Some(limit), _) => {
+            qb.push(" LIMIT ").push_bind(limit as i64 + 1);
+        }
+        (None, Some(_)) => {
+            qb.push(" LIMIT -1");
+        }
+        (None, None) => (),
+    }
+    if let Some(offset) = query.offset {
+        qb.push(" OFFSET ").push_bind(offset as i64);
+    }
 
-    let res = sqlx::query(&q).fetch_all(&mut *db).await.map_err(|e| {
-        ErrorFromRust::new("Error when getting files").raw(format!("{}\n\n{}", e.to_string(), q))
+    let sql = qb.sql().to_string();
+    let started = Instant::now();
+
+    let mut res = qb.build().fetch_all(&mut *db).await.map_err(|e| {
+        ErrorFromRust::new("Error when getting files").raw(format!("{}\n\n{}", e, sql))
     })?;
+    record_query(&mut db, "files", &sql, started.elapsed(), res.len()).await;
+
+    let mut next_cursor = None;
+    if let Some(limit) = query.limit {
+        if res.len() > limit as usize {
+            res.truncate(limit as usize);
+            next_cursor = res
+                .last()
+                .map(|row| encode_cursor(cursor_values(row, components.len())));
+        }
+    }
 
     let result_iter: Vec<BookFromDb> = res
         .iter()
@@ -111,78 +336,154 @@ pub async fn get_files_abstact(
 
             for schema_i in schema.items.clone().iter() {
                 let name = schema_i.name.to_owned();
+                let ident = t_info.field(&name)?;
                 match schema_i.value {
+                    // Attributes missing in file are NULL and left out, same as when file is read
                     AttrKey::Text(_) => {
-                        let v = row.get(&*name);
-                        hm.insert(name, AttrValue::Text(v));
+                        if let Some(v) = row.get(ident) {
+                            hm.insert(name, AttrValue::Text(v));
+                        }
                     }
                     AttrKey::Date(_) => {
-                        let v = row.get(&*name);
-                        hm.insert(name, AttrValue::Date(v));
+                        if let Some(v) = row.get(ident) {
+                            hm.insert(name, AttrValue::Date(v));
+                        }
                     }
                     AttrKey::Image(_) => {
-                        let v = row.get(&*name);
-                        hm.insert(name, AttrValue::Image(v));
+                        if let Some(v) = row.get(ident) {
+                            hm.insert(name, AttrValue::Image(v));
+                        }
                     }
 
                     AttrKey::Number(_) => {
-                        let v = row.get(&*name);
-                        hm.insert(name, AttrValue::Number(v));
+                        if let Some(v) = row.get(ident) {
+                            hm.insert(name, AttrValue::Number(v));
+                        }
+                    }
+                    AttrKey::Computed(ref settings) => {
+                        match settings.as_ref().and_then(|s| s.returns.clone()) {
+                            Some(ComputedType::Text) => {
+                                if let Some(v) = row.get(ident) {
+                                    hm.insert(name, AttrValue::Text(v));
+                                }
+                            }
+                            Some(ComputedType::Date) => {
+                                if let Some(v) = row.get(ident) {
+                                    hm.insert(name, AttrValue::Date(v));
+                                }
+                            }
+                            Some(ComputedType::Number) | None => {
+                                if let Some(v) = row.get(ident) {
+                                    hm.insert(name, AttrValue::Number(v));
+                                }
+                            }
+                        }
                     }
                     AttrKey::TextCollection(_) => {
-                        let v: String = row.get(&*name);
-                        hm.insert(
-                            name,
-                            AttrValue::TextCollection(
-                                v.split(",").map(|s| s.to_string()).collect(),
-                            ),
-                        );
+                        let v = json_collection(row, ident)?;
+                        hm.insert(name, AttrValue::TextCollection(v));
                     }
                     AttrKey::DateCollection(_) => {
-                        let v: String = row.get(&*name);
-                        hm.insert(
-                            name,
-                            AttrValue::DateCollection(
-                                v.split(",").map(|s| s.to_string()).collect(),
-                            ),
-                        );
+                        let v = json_collection(row, ident)?;
+                        hm.insert(name, AttrValue::DateCollection(v));
                     }
                     AttrKey::DatesPairCollection(_) => {
-                        let v: String = row.get(&*name);
-                        hm.insert(
-                            name,
-                            AttrValue::DatesPairCollection(
-                                v.split(',')
-                                    .map(|dd| {
-                                        let mut parts = dd.split('|');
-                                        DateRead {
-                                            started: parts
-                                                .next()
-                                                .filter(|s| !s.is_empty())
-                                                .map(String::from),
-                                            finished: parts
-                                                .next()
-                                                .filter(|f| !f.is_empty())
-                                                .map(String::from),
-                                        }
-                                    })
-                                    .collect(),
-                            ),
-                        );
+                        let v: Vec<DateRead> = json_collection(row, ident)?;
+                        hm.insert(name, AttrValue::DatesPairCollection(v));
                     }
                 }
             }
 
-            BookFromDb {
+            Ok(BookFromDb {
                 attrs: hm,
                 path: Some(row.get("path")),
                 modified: Some(row.get("modified")),
                 ..Default::default()
-            }
+            })
         })
-        .collect();
+        .collect::<Result<Vec<BookFromDb>, ErrorFromRust>>()?;
+
+    let search_hits = match query.search {
+        Some(_) => res
+            .iter()
+            .map(|row| SearchHit {
+                path: row.get("path"),
+                score: -row.get::<f64, _>("search_rank"),
+                snippet: parse_snippet(&row.get::<String, _>("search_snippet")),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    Ok(FilesPage {
+        books: result_iter,
+        next_cursor,
+        search_hits,
+    })
+}
+
+fn json_collection<T: DeserializeOwned>(
+    row: &SqliteRow,
+    column: &str,
+) -> Result<Vec<T>, ErrorFromRust> {
+    match row.get::<Option<String>, _>(column) {
+        Some(json) => serde_json::from_str(&json).map_err(|e| {
+            ErrorFromRust::new("Error when reading collection from cache")
+                .info(column)
+                .raw(e)
+        }),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn cursor_values(row: &SqliteRow, count: usize) -> Vec<Value> {
+    (0..count)
+        .map(|i| column_value(row, &format!("sort_{}", i)))
+        .collect()
+}
+
+// Computed columns have no declared type, type of value decides how it's read
+pub fn column_value(row: &SqliteRow, column: &str) -> Value {
+    let kind = match row.try_get_raw(column) {
+        Ok(raw) if !raw.is_null() => raw.type_info().name().to_string(),
+        _ => return Value::Null,
+    };
+
+    match kind.as_str() {
+        "INTEGER" => Value::from(row.get::<i64, _>(column)),
+        "REAL" => Value::from(row.get::<f64, _>(column)),
+        _ => Value::from(row.get::<String, _>(column)),
+    }
+}
+
+// Number of files matching conditions and filter, pagination is ignored
+pub async fn count_files(query: &FilesQuery) -> Result<i64, ErrorFromRust> {
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let t_info = find_table_names(&mut db, &query.schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
+
+    let mut qb: QueryBuilder<'_, Sqlite> = QueryBuilder::new(format!(
+        "SELECT COUNT(*) AS total FROM {} {}",
+        t_info.files_table,
+        match query.search {
+            Some(_) => search_join(&t_info),
+            None => String::new(),
+        }
+    ));
+    query.push_where(&mut qb, &t_info, &[], None)?;
+    let sql = qb.sql().to_string();
+    let started = Instant::now();
+
+    let row = qb.build().fetch_one(&mut *db).await.map_err(|e| {
+        ErrorFromRust::new("Error when counting files").raw(format!("{}\n\n{}", e, sql))
+    })?;
+    record_query(&mut db, "count", &sql, started.elapsed(), 1).await;
 
-    Ok(result_iter)
+    Ok(row.get("total"))
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -191,19 +492,15 @@ pub struct BookListGetResult {
     pub books: Vec<BookFromDb>,
 }
 
-pub async fn get_files_by_path(path: String) -> Result<BookListGetResult, ErrorFromRust> {
+pub async fn get_files_by_path(
+    path: String,
+    include_subfolders: bool,
+) -> Result<BookListGetResult, ErrorFromRust> {
     let schema = get_schema_cached_safe(&path).await?;
 
-    let t_info = get_table_names(schema.internal_name.clone());
-
-    let files = get_files_abstact(
-        format!(
-            "WHERE {}.path LIKE concat('%', '{}', '%') GROUP BY {}.path",
-            t_info.files_table, path, t_info.files_table
-        ),
-        schema.clone(),
-    )
-    .await?;
+    let files =
+        get_files_abstact(&FilesQuery::new(schema.clone()).in_folder(&path, include_subfolders))
+            .await?;
 
     return Ok(BookListGetResult {
         schema: schema,
@@ -211,24 +508,63 @@ pub async fn get_files_by_path(path: String) -> Result<BookListGetResult, ErrorF
     });
 }
 
-pub async fn get_all_tags() -> Result<Vec<String>, sqlx::Error> {
-    let mut db = get_db_conn().lock().await;
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FilesQueryOptions {
+    pub filter: Option<Filter>,
+    #[serde(default)]
+    pub sort: Vec<SortKey>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub cursor: Option<String>,
+    pub include_subfolders: Option<bool>,
+    pub search: Option<String>,
+}
 
-    let res = sqlx::query("SELECT DISTINCT value FROM tags")
-        .fetch_all(&mut *db)
-        .await?;
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FilesQueryResult {
+    pub schema: Schema,
+    pub books: Vec<BookFromDb>,
+    // All files matching filter, regardless of page
+    pub total: i64,
+    pub next_cursor: Option<String>,
+    pub search_hits: Vec<SearchHit>,
+}
 
-    let result: Vec<String> = res.iter().map(|r| r.get("value")).collect();
+pub async fn query_files(
+    path: String,
+    options: FilesQueryOptions,
+) -> Result<FilesQueryResult, ErrorFromRust> {
+    let schema = get_schema_cached_safe(&path).await?;
 
-    Ok(result)
+    let query = FilesQuery::new(schema.clone())
+        .in_folder(&path, options.include_subfolders.unwrap_or(true))
+        .filter(options.filter)
+        .sort(options.sort)
+        .search(options.search)
+        .page(options.limit, options.offset, options.cursor);
+
+    let page = get_files_page(&query).await?;
+    let total = count_files(&query).await?;
+
+    Ok(FilesQueryResult {
+        schema,
+        books: page.books,
+        total,
+        next_cursor: page.next_cursor,
+        search_hits: page.search_hits,
+    })
 }
 
 pub async fn get_all_folders(schema_path: &str) -> Result<Vec<String>, ErrorFromRust> {
-    let mut db = get_db_conn().lock().await;
-
     let schema = get_schema_cached_safe(&schema_path).await?;
 
-    let t_info = get_table_names(schema.internal_name.clone());
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let t_info = find_table_names(&mut db, &schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
 
     let res = sqlx::query(&format!(
         "SELECT DISTINCT path FROM {}",
@@ -0,0 +1,1225 @@
+use serde::{Deserialize, Serialize};
+
+use crate::schema::operations::get_schema_cached_safe;
+use crate::schema::types::{AttrKey, ComputedType, Schema, SchemaItem};
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::filter::{CompareOp, DatesPairPart, Filter, FilterValue, SortDirection, SortKey};
+use super::values::get_field_values;
+
+// Dates are compared as text and "~" sorts after digits, "-", "T" and ":".
+// So "2024" to "2024~" covers every date and datetime in 2024.
+const DATE_PREFIX_END: &str = "~";
+const SORT_KEYWORD: &str = "sort";
+// has:author is not empty, -has:author is empty
+const HAS_KEYWORD: &str = "has";
+const OR_KEYWORD: &str = "OR";
+const MAX_VALUE_SUGGESTIONS: u32 = 20;
+// Brackets and negations are parsed recursively on every keystroke,
+// deeper queries are rejected instead of overflowing stack
+const MAX_NESTING: usize = 100;
+
+// Positions are in UTF-16 code units, same as string indexes in frontend
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QueryParseError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Filter, sort and search are empty when there are errors
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ParsedQuery {
+    pub filter: Option<Filter>,
+    pub sort: Vec<SortKey>,
+    // Words that are not inside OR or NOT, for ranking and snippets. They are not part of filter then.
+    pub search: Option<String>,
+    pub errors: Vec<QueryParseError>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SuggestionKind {
+    Field,
+    Value,
+    Keyword,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QuerySuggestion {
+    pub kind: SuggestionKind,
+    pub label: String,
+    // Replaces text between start and end
+    pub insert: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Op {
+    Colon,
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ValueKind {
+    Text,
+    Number,
+    Date,
+    TextCollection,
+    DateCollection,
+    DatesPairs,
+}
+
+fn value_kind(key: &AttrKey) -> ValueKind {
+    match key {
+        AttrKey::Text(_) | AttrKey::Image(_) => ValueKind::Text,
+        AttrKey::Number(_) => ValueKind::Number,
+        AttrKey::Date(_) => ValueKind::Date,
+        AttrKey::Computed(settings) => match settings.as_ref().and_then(|s| s.returns.clone()) {
+            Some(ComputedType::Text) => ValueKind::Text,
+            Some(ComputedType::Date) => ValueKind::Date,
+            Some(ComputedType::Number) | None => ValueKind::Number,
+        },
+        AttrKey::TextCollection(_) => ValueKind::TextCollection,
+        AttrKey::DateCollection(_) => ValueKind::DateCollection,
+        AttrKey::DatesPairCollection(_) => ValueKind::DatesPairs,
+    }
+}
+
+// Exact name first, then case insensitive one
+fn find_item<'a>(schema: &'a Schema, name: &str) -> Option<&'a SchemaItem> {
+    schema.items.iter().find(|i| i.name == name).or_else(|| {
+        schema
+            .items
+            .iter()
+            .find(|i| i.name.to_lowercase() == name.to_lowercase())
+    })
+}
+
+// 2024, 2024-05 or 2024-05-17
+fn is_date_prefix(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('-').collect();
+    let is_num = |s: &str, len: usize| s.len() == len && s.chars().all(|c| c.is_ascii_digit());
+
+    match parts.as_slice() {
+        [y] => is_num(y, 4),
+        [y, m] => is_num(y, 4) && is_num(m, 2),
+        [y, m, d] => is_num(y, 4) && is_num(m, 2) && is_num(d, 2),
+        _ => false,
+    }
+}
+
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | ':' | '<' | '>' | '=')
+}
+
+fn quote_if_needed(value: &str) -> String {
+    if !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| is_word_end(c) || matches!(c, '"' | '!' | '-' | ','))
+    {
+        return value.to_string();
+    }
+
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn utf16_pos(text: &str, byte: usize) -> usize {
+    text[..byte.min(text.len())].encode_utf16().count()
+}
+
+fn byte_pos(text: &str, utf16: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        if units >= utf16 {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+struct Word {
+    text: String,
+    start: usize,
+    end: usize,
+    quoted: bool,
+}
+
+// Positions in errors are byte offsets while parsing
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    schema: &'a Schema,
+    sort: Vec<SortKey>,
+    errors: Vec<QueryParseError>,
+    depth: usize,
+}
+
+type ParseResult<T> = Result<T, QueryParseError>;
+
+fn parse_err(message: &str, start: usize, end: usize) -> QueryParseError {
+    QueryParseError {
+        message: message.to_string(),
+        start,
+        end,
+    }
+}
+
+fn join_filters(mut filters: Vec<Filter>, or: bool) -> Option<Filter> {
+    match filters.len() {
+        0 => None,
+        1 => filters.pop(),
+        _ if or => Some(Filter::Or { filters }),
+        _ => Some(Filter::And { filters }),
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str, schema: &'a Schema) -> Self {
+        Parser {
+            text,
+            pos: 0,
+            schema,
+            sort: Vec::new(),
+            errors: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.text[self.pos..].chars().nth(1)
+    }
+
+    fn bump(&mut self) {
+        if let Some(c) = self.peek() {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    fn at_or(&self) -> bool {
+        self.text[self.pos..].starts_with(OR_KEYWORD)
+            && self.text[self.pos + OR_KEYWORD.len()..]
+                .chars()
+                .next()
+                .map_or(true, |c| c.is_whitespace() || c == '(')
+    }
+
+    fn parse_or(&mut self) -> ParseResult<Option<Filter>> {
+        let mut filters = Vec::new();
+
+        loop {
+            if let Some(f) = self.parse_and()? {
+                filters.push(f);
+            }
+
+            self.skip_ws();
+            if !self.at_or() {
+                break;
+            }
+
+            let start = self.pos;
+            self.pos += OR_KEYWORD.len();
+            self.skip_ws();
+            if self.peek().map_or(true, |c| c == ')') {
+                return Err(parse_err("Expected term after OR", start, self.pos));
+            }
+        }
+
+        Ok(join_filters(filters, true))
+    }
+
+    fn parse_and(&mut self) -> ParseResult<Option<Filter>> {
+        let mut filters = Vec::new();
+
+        loop {
+            self.skip_ws();
+            if self.peek().map_or(true, |c| c == ')') || self.at_or() {
+                break;
+            }
+            if let Some(f) = self.parse_unary()? {
+                filters.push(f);
+            }
+        }
+
+        Ok(join_filters(filters, false))
+    }
+
+    fn enter(&mut self, start: usize) -> ParseResult<()> {
+        self.depth += 1;
+        match self.depth > MAX_NESTING {
+            true => Err(parse_err("Too much nesting", start, start + 1)),
+            false => Ok(()),
+        }
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<Option<Filter>> {
+        let start = self.pos;
+
+        if matches!(self.peek(), Some('-') | Some('(')) {
+            self.enter(start)?;
+            let res = self.parse_nested(start);
+            self.depth -= 1;
+            return res;
+        }
+
+        self.parse_term()
+    }
+
+    fn parse_nested(&mut self, start: usize) -> ParseResult<Option<Filter>> {
+        match self.peek() {
+            Some('-') => {
+                self.bump();
+                if self.peek().map_or(true, |c| c.is_whitespace() || c == ')') {
+                    return Err(parse_err("Expected term after -", start, self.pos));
+                }
+
+                let errors_before = self.errors.len();
+                let sort_before = self.sort.len();
+                let inner = self.parse_unary()?;
+
+                match inner {
+                    Some(filter) => Ok(Some(Filter::Not {
+                        filter: Box::new(filter),
+                    })),
+                    // Term already reported its own error
+                    None if self.errors.len() > errors_before => Ok(None),
+                    None if self.sort.len() > sort_before => Err(parse_err(
+                        "Sorting can't be negated, use sort:-field for descending order",
+                        start,
+                        self.pos,
+                    )),
+                    None => Ok(None),
+                }
+            }
+            Some('(') => {
+                self.bump();
+                let inner = self.parse_or()?;
+                self.skip_ws();
+                if self.peek() != Some(')') {
+                    return Err(parse_err("Missing closing bracket", start, start + 1));
+                }
+                self.bump();
+                Ok(inner)
+            }
+            _ => self.parse_term(),
+        }
+    }
+
+    fn read_quoted(&mut self) -> ParseResult<Word> {
+        let start = self.pos;
+        self.bump();
+
+        let mut res = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(parse_err("Missing closing quote", start, self.pos)),
+                Some('"') => {
+                    self.bump();
+                    break;
+                }
+                Some('\\') if matches!(self.peek_second(), Some('"') | Some('\\')) => {
+                    self.bump();
+                    res.push(self.peek().unwrap_or_default());
+                    self.bump();
+                }
+                Some(c) => {
+                    res.push(c);
+                    self.bump();
+                }
+            }
+        }
+
+        Ok(Word {
+            text: res,
+            start,
+            end: self.pos,
+            quoted: true,
+        })
+    }
+
+    fn read_word(&mut self) -> ParseResult<Word> {
+        if self.peek() == Some('"') {
+            return self.read_quoted();
+        }
+
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if is_word_end(c) || (c == '!' && self.peek_second() == Some('=')) {
+                break;
+            }
+            self.bump();
+        }
+
+        Ok(Word {
+            text: self.text[start..self.pos].to_string(),
+            start,
+            end: self.pos,
+            quoted: false,
+        })
+    }
+
+    fn read_op(&mut self) -> Option<(Op, usize, usize)> {
+        let start = self.pos;
+        let rest = &self.text[self.pos..];
+
+        let (op, len) = if rest.starts_with(">=") {
+            (Op::Gte, 2)
+        } else if rest.starts_with("<=") {
+            (Op::Lte, 2)
+        } else if rest.starts_with("!=") {
+            (Op::Ne, 2)
+        } else if rest.starts_with('>') {
+            (Op::Gt, 1)
+        } else if rest.starts_with('<') {
+            (Op::Lt, 1)
+        } else if rest.starts_with('=') {
+            (Op::Eq, 1)
+        } else if rest.starts_with(':') {
+            (Op::Colon, 1)
+        } else {
+            return None;
+        };
+
+        self.pos += len;
+        Some((op, start, self.pos))
+    }
+
+    // Unlike field names, values can contain ":" and other operators, only space and bracket end them
+    fn read_value(&mut self) -> ParseResult<Word> {
+        if self.peek() == Some('"') {
+            return self.read_quoted();
+        }
+
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !c.is_whitespace() && c != ')') {
+            self.bump();
+        }
+
+        Ok(Word {
+            text: self.text[start..self.pos].to_string(),
+            start,
+            end: self.pos,
+            quoted: false,
+        })
+    }
+
+    fn parse_term(&mut self) -> ParseResult<Option<Filter>> {
+        let word = self.read_word()?;
+
+        match self.read_op() {
+            Some((op, op_start, op_end)) => {
+                if word.text.is_empty() && !word.quoted {
+                    return Err(parse_err("Expected field name", op_start, op_end));
+                }
+
+                // sort:-"Field name" is descending sort by quoted field
+                let is_sort = !word.quoted && word.text.to_lowercase() == SORT_KEYWORD;
+                let value =
+                    if is_sort && self.peek() == Some('-') && self.peek_second() == Some('"') {
+                        let start = self.pos;
+                        self.bump();
+                        let quoted = self.read_quoted()?;
+                        Word {
+                            text: format!("-{}", quoted.text),
+                            start,
+                            end: quoted.end,
+                            quoted: false,
+                        }
+                    } else {
+                        self.read_value()?
+                    };
+                if value.text.is_empty() && !value.quoted {
+                    return Err(parse_err("Expected value", op_start, op_end));
+                }
+
+                Ok(self.field_term(&word, op, op_start, op_end, &value))
+            }
+            None => {
+                if word.text.is_empty() && !word.quoted {
+                    let end = self.pos + self.peek().map(|c| c.len_utf8()).unwrap_or(0);
+                    return Err(parse_err("Unexpected character", self.pos, end));
+                }
+                Ok(self.free_text(&word))
+            }
+        }
+    }
+
+    fn error(&mut self, message: &str, start: usize, end: usize) -> Option<Filter> {
+        self.errors.push(parse_err(message, start, end));
+        None
+    }
+
+    // Bare words and phrases go to full text search. Word is quoted for FTS5,
+    // so only trailing "*" for prefix search keeps its meaning.
+    fn free_text(&mut self, word: &Word) -> Option<Filter> {
+        let (text, prefix) = match word.text.strip_suffix('*') {
+            Some(t) if !word.quoted && !t.is_empty() => (t, "*"),
+            _ => (word.text.as_str(), ""),
+        };
+
+        Some(Filter::Search {
+            query: format!("\"{}\"{}", text.replace('"', "\"\""), prefix),
+        })
+    }
+
+    fn sort_term(
+        &mut self,
+        op: Op,
+        op_start: usize,
+        op_end: usize,
+        value: &Word,
+    ) -> Option<Filter> {
+        if op != Op::Colon {
+            return self.error("Use sort:field or sort:-field", op_start, op_end);
+        }
+
+        let (direction, name) = match value.text.strip_prefix('-') {
+            Some(name) if !value.quoted => (SortDirection::Desc, name),
+            _ => (SortDirection::Asc, value.text.as_str()),
+        };
+
+        match find_item(self.schema, name) {
+            Some(item) => {
+                self.sort.push(SortKey {
+                    field: item.name.clone(),
+                    direction,
+                });
+                None
+            }
+            None => self.error(
+                &format!("Unknown field \"{}\"", name),
+                value.start,
+                value.end,
+            ),
+        }
+    }
+
+    fn has_term(&mut self, op: Op, op_start: usize, op_end: usize, value: &Word) -> Option<Filter> {
+        if op != Op::Colon {
+            return self.error("Use has:field or -has:field", op_start, op_end);
+        }
+
+        match find_item(self.schema, &value.text) {
+            Some(item) => Some(Filter::IsNotEmpty {
+                field: item.name.clone(),
+            }),
+            None => self.error(
+                &format!("Unknown field \"{}\"", value.text),
+                value.start,
+                value.end,
+            ),
+        }
+    }
+
+    fn field_term(
+        &mut self,
+        name: &Word,
+        op: Op,
+        op_start: usize,
+        op_end: usize,
+        value: &Word,
+    ) -> Option<Filter> {
+        if !name.quoted && name.text.to_lowercase() == SORT_KEYWORD {
+            return self.sort_term(op, op_start, op_end, value);
+        }
+        if !name.quoted && name.text.to_lowercase() == HAS_KEYWORD {
+            return self.has_term(op, op_start, op_end, value);
+        }
+
+        let (item, part) = match find_item(self.schema, &name.text) {
+            Some(item) => (item.clone(), None),
+            None => match name.text.rsplit_once('.') {
+                Some((base, suffix)) if find_item(self.schema, base).is_some() => {
+                    let item = find_item(self.schema, base).unwrap().clone();
+                    if value_kind(&item.value) != ValueKind::DatesPairs {
+                        return self.error(
+                            &format!("Field \"{}\" has no started and finished dates", item.name),
+                            name.start,
+                            name.end,
+                        );
+                    }
+
+                    let part = match suffix.to_lowercase().as_str() {
+                        "started" => DatesPairPart::Started,
+                        "finished" => DatesPairPart::Finished,
+                        _ => {
+                            return self.error(
+                                "Use .started or .finished",
+                                name.start + base.len(),
+                                name.end,
+                            );
+                        }
+                    };
+                    (item, Some(part))
+                }
+                _ => {
+                    return self.error(
+                        &format!("Unknown field \"{}\"", name.text),
+                        name.start,
+                        name.end,
+                    );
+                }
+            },
+        };
+
+        let field = item.name.clone();
+        let kind = value_kind(&item.value);
+
+        let compare_op = match op {
+            Op::Colon | Op::Eq => CompareOp::Eq,
+            Op::Ne => CompareOp::Ne,
+            Op::Lt => CompareOp::Lt,
+            Op::Lte => CompareOp::Lte,
+            Op::Gt => CompareOp::Gt,
+            Op::Gte => CompareOp::Gte,
+        };
+
+        match kind {
+            ValueKind::Number => match value.text.trim().parse::<f64>() {
+                Ok(n) => Some(Filter::Compare {
+                    field,
+                    op: compare_op,
+                    value: FilterValue::Number(n),
+                }),
+                Err(_) => self.error(
+                    &format!("Field \"{}\" is a number", field),
+                    value.start,
+                    value.end,
+                ),
+            },
+            ValueKind::Text => Some(Filter::Compare {
+                field,
+                // ":" is the loose match, "=" is exact
+                op: if op == Op::Colon {
+                    CompareOp::Includes
+                } else {
+                    compare_op
+                },
+                value: FilterValue::Text(value.text.clone()),
+            }),
+            ValueKind::TextCollection => {
+                if !matches!(op, Op::Colon | Op::Eq | Op::Ne) {
+                    return self.error(
+                        "Collections can be matched only with :, = or !=",
+                        op_start,
+                        op_end,
+                    );
+                }
+
+                // tags:scifi,fantasy matches any of them
+                let values: Vec<String> = if value.quoted {
+                    vec![value.text.clone()]
+                } else {
+                    value
+                        .text
+                        .split(',')
+                        .map(|v| v.trim().to_string())
+                        .filter(|v| !v.is_empty())
+                        .collect()
+                };
+
+                let filter = Filter::Any { field, values };
+                Some(if op == Op::Ne {
+                    Filter::Not {
+                        filter: Box::new(filter),
+                    }
+                } else {
+                    filter
+                })
+            }
+            ValueKind::Date | ValueKind::DateCollection | ValueKind::DatesPairs => {
+                if !is_date_prefix(&value.text) {
+                    return self.error(
+                        "Expected date like 2024, 2024-05 or 2024-05-17",
+                        value.start,
+                        value.end,
+                    );
+                }
+
+                let from = value.text.clone();
+                let to = format!("{}{}", value.text, DATE_PREFIX_END);
+                let range = |from: Option<String>, to: Option<String>| Filter::DateRange {
+                    field: field.clone(),
+                    from,
+                    to,
+                    part: part.clone(),
+                };
+
+                match op {
+                    Op::Colon | Op::Eq => Some(range(Some(from), Some(to))),
+                    Op::Ne => Some(Filter::Not {
+                        filter: Box::new(range(Some(from), Some(to))),
+                    }),
+                    Op::Gte => Some(range(Some(from), None)),
+                    Op::Lte => Some(range(None, Some(to))),
+                    Op::Gt | Op::Lt if kind == ValueKind::DatesPairs => {
+                        self.error("Use >= or <= with reading dates", op_start, op_end)
+                    }
+                    Op::Gt => Some(Filter::Compare {
+                        field,
+                        op: CompareOp::Gt,
+                        value: FilterValue::Text(to),
+                    }),
+                    Op::Lt => Some(Filter::Compare {
+                        field,
+                        op: CompareOp::Lt,
+                        value: FilterValue::Text(from),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+// Syntax: field:value field>=4 "field name":"quoted value" read.finished:2024 -negated (a OR b) has:field
+// sort:-field
+pub fn parse_query(text: &str, schema: &Schema) -> ParsedQuery {
+    let mut p = Parser::new(text, schema);
+
+    let filter = match p.parse_or() {
+        Ok(filter) => {
+            p.skip_ws();
+            if p.peek().is_some() {
+                p.error("Unmatched closing bracket", p.pos, p.pos + 1);
+            }
+            filter
+        }
+        Err(e) => {
+            p.errors.push(e);
+            None
+        }
+    };
+
+    let errors: Vec<QueryParseError> = p
+        .errors
+        .iter()
+        .map(|e| QueryParseError {
+            message: e.message.clone(),
+            start: utf16_pos(text, e.start),
+            end: utf16_pos(text, e.end),
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        return ParsedQuery {
+            filter: None,
+            sort: Vec::new(),
+            search: None,
+            errors,
+        };
+    }
+
+    let (filter, search) = split_search(filter);
+
+    ParsedQuery {
+        filter,
+        sort: p.sort,
+        search,
+        errors,
+    }
+}
+
+// Top level search terms are required anyway, so they can be moved out of filter
+fn split_search(filter: Option<Filter>) -> (Option<Filter>, Option<String>) {
+    let filters = match filter {
+        Some(Filter::Search { query }) => return (None, Some(query)),
+        Some(Filter::And { filters }) => filters,
+        other => return (other, None),
+    };
+
+    let (search, rest): (Vec<Filter>, Vec<Filter>) = filters
+        .into_iter()
+        .partition(|f| matches!(f, Filter::Search { .. }));
+
+    let search: Vec<String> = search
+        .into_iter()
+        .filter_map(|f| match f {
+            Filter::Search { query } => Some(query),
+            _ => None,
+        })
+        .collect();
+
+    (
+        join_filters(rest, false),
+        (!search.is_empty()).then(|| search.join(" ")),
+    )
+}
+
+pub async fn parse_query_for_path(path: &str, text: &str) -> Result<ParsedQuery, ErrorFromRust> {
+    let schema = get_schema_cached_safe(path).await?;
+    Ok(parse_query(text, &schema))
+}
+
+// Start of term that ends at cursor, quoted parts are skipped
+fn term_start(text: &str) -> usize {
+    let mut start = 0;
+    let mut in_quote = false;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        if in_quote {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quote = false,
+                _ => (),
+            }
+            continue;
+        }
+
+        if c == '"' {
+            in_quote = true;
+        } else if c.is_whitespace() || c == '(' || c == ')' {
+            start = i + c.len_utf8();
+        }
+    }
+
+    start
+}
+
+fn field_suggestions(schema: &Schema, prefix: &str, insert_prefix: &str) -> Vec<(String, String)> {
+    let prefix = prefix.to_lowercase();
+    let mut res = Vec::new();
+
+    for item in schema.items.iter() {
+        let mut names = vec![item.name.clone()];
+        if value_kind(&item.value) == ValueKind::DatesPairs {
+            names.push(format!("{}.started", item.name));
+            names.push(format!("{}.finished", item.name));
+        }
+
+        for name in names {
+            if name.to_lowercase().starts_with(&prefix) {
+                res.push((
+                    name.clone(),
+                    format!("{}{}", insert_prefix, quote_if_needed(&name)),
+                ));
+            }
+        }
+    }
+
+    res
+}
+
+// Most used values first
+async fn get_value_suggestions(
+    schema: &Schema,
+    item: &SchemaItem,
+    prefix: &str,
+) -> Result<Vec<String>, ErrorFromRust> {
+    match value_kind(&item.value) {
+        ValueKind::Text | ValueKind::TextCollection => (),
+        _ => return Ok(Vec::new()),
+    }
+
+    Ok(get_field_values(
+        schema,
+        &item.name,
+        Some(prefix),
+        Some(MAX_VALUE_SUGGESTIONS),
+    )
+    .await?
+    .into_iter()
+    .filter_map(|v| v.value.as_str().map(String::from))
+    .collect())
+}
+
+// Rest of term after cursor that suggestion replaces too, so completing in the middle
+// of a word doesn't leave its tail behind. Returns its length in bytes.
+fn rest_of_term(rest: &str, schema: &Schema, value: bool, in_quote: bool) -> usize {
+    if in_quote {
+        let mut escaped = false;
+        for (i, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return i + 1,
+                _ => (),
+            }
+        }
+        return rest.len();
+    }
+
+    let mut p = Parser::new(rest, schema);
+    let word = match value {
+        true => p.read_value(),
+        false => p.read_word(),
+    };
+    word.map_or(0, |w| w.end)
+}
+
+enum Completion<'a> {
+    Done(Vec<QuerySuggestion>),
+    // Values of text field come from cache
+    Values {
+        item: &'a SchemaItem,
+        prefix: String,
+        start: usize,
+        end: usize,
+    },
+}
+
+fn complete_query<'a>(schema: &'a Schema, text: &str, cursor: usize) -> Completion<'a> {
+    let cursor_byte = byte_pos(text, cursor);
+    let before = &text[..cursor_byte];
+    let after = &text[cursor_byte..];
+
+    let mut start = term_start(before);
+    while before[start..].starts_with('-') {
+        start += 1;
+    }
+    let term = &before[start..];
+
+    let mut p = Parser::new(term, schema);
+    let field = p.read_word();
+
+    let end_after = |len: usize| cursor + utf16_pos(after, len);
+    let suggestion =
+        |kind: SuggestionKind, label: String, insert: String, from: usize, end| QuerySuggestion {
+            kind,
+            label,
+            insert,
+            start: utf16_pos(text, from),
+            end,
+        };
+
+    let op = match &field {
+        Ok(_) => p.read_op(),
+        Err(_) => None,
+    };
+
+    let Some((op, _, value_start)) = op else {
+        // Still typing field name, possibly quoted
+        let in_quote = term.starts_with('"');
+        let rest_len = rest_of_term(after, schema, false, in_quote);
+        let end = end_after(rest_len);
+        // Operator after cursor is kept, so only name is replaced
+        let op_follows = Parser::new(&after[rest_len..], schema).read_op().is_some();
+        let with_op = |insert: String| match op_follows {
+            true => insert,
+            false => format!("{}:", insert),
+        };
+
+        let prefix = term.trim_start_matches('"');
+        let mut res: Vec<QuerySuggestion> = field_suggestions(schema, prefix, "")
+            .into_iter()
+            .map(|(label, insert)| {
+                suggestion(SuggestionKind::Field, label, with_op(insert), start, end)
+            })
+            .collect();
+
+        for keyword in [SORT_KEYWORD, HAS_KEYWORD] {
+            if keyword.starts_with(&prefix.to_lowercase()) {
+                res.push(suggestion(
+                    SuggestionKind::Keyword,
+                    keyword.to_string(),
+                    with_op(keyword.to_string()),
+                    start,
+                    end,
+                ));
+            }
+        }
+
+        return Completion::Done(res);
+    };
+
+    let field = field.unwrap_or(Word {
+        text: String::new(),
+        start: 0,
+        end: 0,
+        quoted: false,
+    });
+    let in_quote = term[value_start..].starts_with('"');
+    let value = term[value_start..].trim_start_matches('"');
+    let from = start + value_start;
+    let end = end_after(rest_of_term(after, schema, true, in_quote));
+
+    let keyword = match field.quoted {
+        true => String::new(),
+        false => field.text.to_lowercase(),
+    };
+    if keyword == SORT_KEYWORD || keyword == HAS_KEYWORD {
+        if op != Op::Colon {
+            return Completion::Done(Vec::new());
+        }
+
+        let (desc, prefix) = match value.strip_prefix('-') {
+            Some(v) if keyword == SORT_KEYWORD => ("-", v),
+            _ => ("", value),
+        };
+
+        return Completion::Done(
+            field_suggestions(schema, prefix, desc)
+                .into_iter()
+                .filter(|(label, _)| find_item(schema, label).is_some())
+                .map(|(label, insert)| suggestion(SuggestionKind::Field, label, insert, from, end))
+                .collect(),
+        );
+    }
+
+    match find_item(schema, &field.text) {
+        Some(item) => Completion::Values {
+            item,
+            prefix: value.to_string(),
+            start: utf16_pos(text, from),
+            end,
+        },
+        None => Completion::Done(Vec::new()),
+    }
+}
+
+// Cursor is position in text in UTF-16 code units. Suggests field names while field is typed,
+// and values for text fields after operator.
+pub async fn get_query_suggestions(
+    path: &str,
+    text: &str,
+    cursor: usize,
+) -> Result<Vec<QuerySuggestion>, ErrorFromRust> {
+    let schema = get_schema_cached_safe(path).await?;
+
+    let (item, prefix, start, end) = match complete_query(&schema, text, cursor) {
+        Completion::Done(res) => return Ok(res),
+        Completion::Values {
+            item,
+            prefix,
+            start,
+            end,
+        } => (item, prefix, start, end),
+    };
+
+    Ok(get_value_suggestions(&schema, item, &prefix)
+        .await?
+        .into_iter()
+        .map(|v| QuerySuggestion {
+            kind: SuggestionKind::Value,
+            label: v.clone(),
+            insert: quote_if_needed(&v),
+            start,
+            end,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Schema {
+        let item = |name: &str, value: AttrKey| SchemaItem {
+            name: name.to_string(),
+            value,
+        };
+        Schema {
+            name: "Books".to_string(),
+            version: "1.0".to_string(),
+            icon: None,
+            items: vec![
+                item("author", AttrKey::Text(None)),
+                item("myRating", AttrKey::Number(None)),
+                item("tags", AttrKey::TextCollection(None)),
+                item("read", AttrKey::DatesPairCollection(None)),
+            ],
+            internal_path: "".to_string(),
+            internal_name: "".to_string(),
+        }
+    }
+
+    fn errors(text: &str) -> Vec<(String, usize, usize)> {
+        parse_query(text, &schema())
+            .errors
+            .into_iter()
+            .map(|e| (e.message, e.start, e.end))
+            .collect()
+    }
+
+    fn complete(text: &str, cursor: usize) -> Vec<(String, usize, usize)> {
+        let schema = schema();
+        match complete_query(&schema, text, cursor) {
+            Completion::Done(res) => res
+                .into_iter()
+                .map(|s| (s.insert, s.start, s.end))
+                .collect(),
+            Completion::Values {
+                item,
+                prefix,
+                start,
+                end,
+            } => vec![(format!("{}={}", item.name, prefix), start, end)],
+        }
+    }
+
+    #[test]
+    fn example_query() {
+        let q = parse_query(
+            "author:\"Le Guin\" myRating>=4 tags:scifi read.finished:2024 -tags:dnf sort:-myRating",
+            &schema(),
+        );
+
+        assert!(q.errors.is_empty());
+        assert_eq!(q.search, None);
+        assert_eq!(
+            serde_json::to_value(&q.filter).unwrap(),
+            json!({
+                "type": "And",
+                "filters": [
+                    { "type": "Compare", "field": "author", "op": "Includes", "value": "Le Guin" },
+                    { "type": "Compare", "field": "myRating", "op": "Gte", "value": 4.0 },
+                    { "type": "Any", "field": "tags", "values": ["scifi"] },
+                    {
+                        "type": "DateRange",
+                        "field": "read",
+                        "from": "2024",
+                        "to": "2024~",
+                        "part": "Finished"
+                    },
+                    {
+                        "type": "Not",
+                        "filter": { "type": "Any", "field": "tags", "values": ["dnf"] }
+                    }
+                ]
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(&q.sort).unwrap(),
+            json!([{ "field": "myRating", "direction": "Desc" }])
+        );
+    }
+
+    #[test]
+    fn free_words_go_to_search() {
+        let q = parse_query("dune (tags:scifi OR tags:fantasy) herbert", &schema());
+        // Words are quoted, so FTS5 doesn't read them as its own syntax
+        assert_eq!(q.search.as_deref(), Some("\"dune\" \"herbert\""));
+        assert!(matches!(q.filter, Some(Filter::Or { .. })));
+    }
+
+    #[test]
+    fn unknown_fields_and_type_mismatches() {
+        assert_eq!(
+            errors("pages>100"),
+            vec![("Unknown field \"pages\"".to_string(), 0, 5)]
+        );
+        assert_eq!(
+            errors("myRating:great"),
+            vec![("Field \"myRating\" is a number".to_string(), 9, 14)]
+        );
+        assert_eq!(
+            errors("read:last-year"),
+            vec![(
+                "Expected date like 2024, 2024-05 or 2024-05-17".to_string(),
+                5,
+                14
+            )]
+        );
+        assert_eq!(
+            errors("tags>b"),
+            vec![(
+                "Collections can be matched only with :, = or !=".to_string(),
+                4,
+                5
+            )]
+        );
+        assert_eq!(
+            errors("author.started:2024"),
+            vec![(
+                "Field \"author\" has no started and finished dates".to_string(),
+                0,
+                14
+            )]
+        );
+        assert_eq!(
+            errors("(tags:a"),
+            vec![("Missing closing bracket".to_string(), 0, 1)]
+        );
+    }
+
+    #[test]
+    fn deep_nesting_is_error_not_crash() {
+        let nested = format!("{}tags:a{}", "(".repeat(50), ")".repeat(50));
+        assert!(errors(&nested).is_empty());
+
+        let too_much = ("Too much nesting".to_string(), MAX_NESTING, MAX_NESTING + 1);
+        assert_eq!(errors(&"(".repeat(5000)), vec![too_much.clone()]);
+        assert_eq!(errors(&format!("{}a", "-".repeat(5000))), vec![too_much]);
+    }
+
+    #[test]
+    fn error_positions_are_utf16() {
+        // "📚" is one char, four bytes and two UTF-16 units
+        assert_eq!(
+            errors("📚 ёлка myRating:x"),
+            vec![("Field \"myRating\" is a number".to_string(), 17, 18)]
+        );
+        assert_eq!(
+            errors("author:\"📚"),
+            vec![("Missing closing quote".to_string(), 7, 10)]
+        );
+    }
+
+    #[test]
+    fn suggestions_at_end_of_term() {
+        assert_eq!(
+            complete("tags:a myR", 10),
+            vec![("myRating:".to_string(), 7, 10)]
+        );
+        assert_eq!(
+            complete("sort:-my", 8),
+            vec![("-myRating".to_string(), 5, 8)]
+        );
+        assert_eq!(
+            complete("re", 2),
+            vec![
+                ("read:".to_string(), 0, 2),
+                ("read.started:".to_string(), 0, 2),
+                ("read.finished:".to_string(), 0, 2)
+            ]
+        );
+        assert_eq!(
+            complete("author:Le", 9),
+            vec![("author=Le".to_string(), 7, 9)]
+        );
+        assert_eq!(complete("nope:x", 6), vec![]);
+    }
+
+    #[test]
+    fn suggestions_in_middle_of_term() {
+        // Tail of field name is replaced, operator after it is kept
+        assert_eq!(
+            complete("myRat>=4 tags:x", 5),
+            vec![("myRating".to_string(), 0, 5)]
+        );
+        assert_eq!(
+            complete("myRa tags:x", 3),
+            vec![("myRating:".to_string(), 0, 4)]
+        );
+        // Whole value is replaced, including rest of quoted value
+        assert_eq!(
+            complete("author:Le tags:x", 8),
+            vec![("author=L".to_string(), 7, 9)]
+        );
+        assert_eq!(
+            complete("author:\"Le Guin\" x", 10),
+            vec![("author=Le".to_string(), 7, 16)]
+        );
+        // Cursor is counted in UTF-16 units
+        assert_eq!(complete("📚 myR", 6), vec![("myRating:".to_string(), 3, 6)]);
+    }
+}
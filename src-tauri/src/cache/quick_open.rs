@@ -0,0 +1,612 @@
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::HashMap;
+use std::path::{Path, MAIN_SEPARATOR};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use crate::schema::operations::get_all_schemas_cached;
+use crate::schema::types::{AttrKey, AttrValue, Schema};
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::dbconn::get_read_conn;
+use super::query::BookFromDb;
+use super::tables::find_table_names;
+
+// Long texts are reviews and notes, they belong to full text search
+const MAX_FIELD_CHARS: usize = 200;
+// Only entries sharing most trigrams with query are scored
+const MAX_CANDIDATES: usize = 2000;
+const DEFAULT_LIMIT: usize = 20;
+
+const FILE_NAME_WEIGHT: f64 = 1.0;
+const TEXT_WEIGHT: f64 = 1.0;
+const COLLECTION_WEIGHT: f64 = 0.7;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QuickOpenHit {
+    pub path: String,
+    // First text attribute or file name
+    pub title: String,
+    pub schema_name: String,
+    // Text that matched best
+    pub matched: String,
+    // Higher is better
+    pub score: f64,
+}
+
+struct Field {
+    text: String,
+    // Lowercased letters and digits without spaces, "Left Hand" -> "lefthand"
+    compact: Vec<char>,
+    word_starts: Vec<bool>,
+    weight: f64,
+}
+
+struct Entry {
+    path: String,
+    title: String,
+    schema_name: String,
+    fields: Vec<Field>,
+    alive: bool,
+}
+
+#[derive(Default)]
+struct QuickOpenIndex {
+    entries: Vec<Entry>,
+    by_path: HashMap<String, usize>,
+    trigrams: HashMap<u64, Vec<u32>>,
+    dead: usize,
+}
+
+// Built on first search from cache db, then kept up to date by cache writes.
+// None means index has to be built again.
+static INDEX: RwLock<Option<QuickOpenIndex>> = RwLock::new(None);
+
+// Changes made while index was being built would be lost, so build is discarded when this moves
+static WRITES: AtomicU64 = AtomicU64::new(0);
+
+fn fold_char(c: char) -> char {
+    match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => 'e',
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' => 'i',
+        'ł' | 'ľ' => 'l',
+        'ñ' | 'ń' | 'ň' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => 'o',
+        'ř' => 'r',
+        'ś' | 'š' | 'ş' => 's',
+        'ť' | 'ţ' => 't',
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => 'u',
+        'ý' | 'ÿ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        'ё' => 'е',
+        'й' => 'и',
+        _ => c,
+    }
+}
+
+// Lowercase, accents removed, punctuation splits words
+fn normalize_words(text: &str) -> Vec<Vec<char>> {
+    let mut words: Vec<Vec<char>> = Vec::new();
+    let mut current: Vec<char> = Vec::new();
+
+    for c in text.chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_alphanumeric() {
+            current.push(fold_char(c));
+        } else if !current.is_empty() {
+            words.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+
+    words
+}
+
+fn make_field(text: &str, weight: f64) -> Option<Field> {
+    if text.trim().is_empty() || text.chars().count() > MAX_FIELD_CHARS {
+        return None;
+    }
+
+    let mut compact = Vec::new();
+    let mut word_starts = Vec::new();
+    for word in normalize_words(text) {
+        for (i, c) in word.into_iter().enumerate() {
+            compact.push(c);
+            word_starts.push(i == 0);
+        }
+    }
+
+    if compact.is_empty() {
+        return None;
+    }
+
+    Some(Field {
+        text: text.to_string(),
+        compact,
+        word_starts,
+        weight,
+    })
+}
+
+fn trigram_key(chars: &[char]) -> u64 {
+    ((chars[0] as u64) << 42) | ((chars[1] as u64) << 21) | (chars[2] as u64)
+}
+
+fn trigrams(chars: &[char]) -> Vec<u64> {
+    let mut res: Vec<u64> = chars.windows(3).map(trigram_key).collect();
+    res.sort_unstable();
+    res.dedup();
+    res
+}
+
+fn make_entry(path: &str, schema: &Schema, attrs: &HashMap<String, AttrValue>) -> Entry {
+    let file_name = Path::new(path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut fields: Vec<Field> = Vec::new();
+    let mut title: Option<String> = None;
+
+    for item in schema.items.iter() {
+        match (&item.value, attrs.get(&item.name)) {
+            (AttrKey::Text(_), Some(AttrValue::Text(v))) => {
+                if title.is_none() && !v.trim().is_empty() {
+                    title = Some(v.clone());
+                }
+                fields.extend(make_field(v, TEXT_WEIGHT));
+            }
+            (AttrKey::TextCollection(_), Some(AttrValue::TextCollection(values))) => {
+                for v in values {
+                    fields.extend(make_field(v, COLLECTION_WEIGHT));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fields.extend(make_field(&file_name, FILE_NAME_WEIGHT));
+
+    Entry {
+        path: path.to_string(),
+        title: title.unwrap_or(file_name),
+        schema_name: schema.name.clone(),
+        fields,
+        alive: true,
+    }
+}
+
+impl QuickOpenIndex {
+    fn insert(&mut self, entry: Entry) {
+        self.remove(&entry.path);
+
+        let id = self.entries.len() as u32;
+        let mut keys: Vec<u64> = entry
+            .fields
+            .iter()
+            .flat_map(|f| trigrams(&f.compact))
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        for key in keys {
+            self.trigrams.entry(key).or_default().push(id);
+        }
+
+        self.by_path.insert(entry.path.clone(), id as usize);
+        self.entries.push(entry);
+    }
+
+    fn remove(&mut self, path: &str) {
+        if let Some(id) = self.by_path.remove(path) {
+            self.entries[id].alive = false;
+            self.dead += 1;
+        }
+    }
+
+    fn remove_under(&mut self, folder: &str) {
+        // Separator keeps "Books 2" when "Books" is removed
+        let prefix = format!("{}{}", folder, MAIN_SEPARATOR);
+        let paths: Vec<String> = self
+            .by_path
+            .keys()
+            .filter(|p| p.starts_with(&prefix))
+            .cloned()
+            .collect();
+
+        for p in paths {
+            self.remove(&p);
+        }
+    }
+
+    // Removed entries stay in trigram lists until there are too many of them
+    fn compact_if_needed(&mut self) {
+        if self.dead < 1000 || self.dead * 2 < self.entries.len() {
+            return;
+        }
+
+        let entries = std::mem::take(&mut self.entries);
+        *self = QuickOpenIndex::default();
+        for e in entries.into_iter().filter(|e| e.alive) {
+            self.insert(e);
+        }
+    }
+
+    fn candidates(&self, tokens: &[Vec<char>]) -> Vec<usize> {
+        let query_trigrams: Vec<u64> = tokens
+            .iter()
+            .flat_map(|t| trigrams(t))
+            .collect::<Vec<u64>>();
+
+        // Too short for trigrams, every entry is checked
+        if query_trigrams.is_empty() {
+            return (0..self.entries.len())
+                .filter(|i| self.entries[*i].alive)
+                .collect();
+        }
+
+        let mut counts: Vec<u16> = vec![0; self.entries.len()];
+        for key in query_trigrams {
+            if let Some(ids) = self.trigrams.get(&key) {
+                for id in ids {
+                    counts[*id as usize] = counts[*id as usize].saturating_add(1);
+                }
+            }
+        }
+
+        let mut res: Vec<(usize, u16)> = counts
+            .into_iter()
+            .enumerate()
+            .filter(|(i, c)| *c > 0 && self.entries[*i].alive)
+            .collect();
+
+        res.sort_unstable_by_key(|(_, c)| std::cmp::Reverse(*c));
+        res.truncate(MAX_CANDIDATES);
+        res.into_iter().map(|(i, _)| i).collect()
+    }
+
+    fn search(&self, query: &str, limit: usize) -> Vec<QuickOpenHit> {
+        let tokens = normalize_words(query);
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+
+        // Entry index, score and matched field index. Hits are built only for returned entries.
+        let mut scored: Vec<(usize, f64, usize)> = self
+            .candidates(&tokens)
+            .into_iter()
+            .filter_map(|i| score_entry(&self.entries[i], &tokens).map(|(s, f)| (i, s, f)))
+            .collect();
+
+        scored.sort_by(|a, b| {
+            let (ea, eb) = (&self.entries[a.0], &self.entries[b.0]);
+            b.1.total_cmp(&a.1)
+                .then_with(|| ea.title.len().cmp(&eb.title.len()))
+                .then_with(|| ea.path.cmp(&eb.path))
+        });
+
+        scored
+            .into_iter()
+            .take(limit)
+            .map(|(i, score, field)| {
+                let entry = &self.entries[i];
+                QuickOpenHit {
+                    path: entry.path.clone(),
+                    title: entry.title.clone(),
+                    schema_name: entry.schema_name.clone(),
+                    matched: entry.fields[field].text.clone(),
+                    score,
+                }
+            })
+            .collect()
+    }
+}
+
+// Typos allowed in a word of this length
+fn allowed_typos(len: usize) -> usize {
+    match len {
+        0..=3 => 0,
+        4..=6 => 1,
+        _ => 2,
+    }
+}
+
+// Best approximate occurrence of token anywhere in text: edit distance and end position
+fn approximate_find(token: &[char], text: &[char]) -> (usize, usize) {
+    let mut prev: Vec<usize> = (0..=token.len()).collect();
+    let mut best = (token.len(), 0);
+
+    for (j, tc) in text.iter().enumerate() {
+        // Match can start at any position in text, so first row is always 0
+        let mut cur = vec![0; token.len() + 1];
+        for i in 1..=token.len() {
+            let cost = usize::from(token[i - 1] != *tc);
+            cur[i] = (prev[i - 1] + cost).min(prev[i] + 1).min(cur[i - 1] + 1);
+        }
+
+        if cur[token.len()] < best.0 {
+            best = (cur[token.len()], j + 1);
+        }
+        prev = cur;
+    }
+
+    best
+}
+
+// Typos and start position of match. Short words are matched exactly, which is also
+// much faster, they are checked against every entry.
+fn find_match(token: &[char], field: &Field) -> Option<(usize, usize)> {
+    let allowed = allowed_typos(token.len());
+
+    if allowed == 0 {
+        let mut found: Option<usize> = None;
+        for (i, w) in field.compact.windows(token.len()).enumerate() {
+            if w == token {
+                if field.word_starts[i] {
+                    return Some((0, i));
+                }
+                found = found.or(Some(i));
+            }
+        }
+        return found.map(|i| (0, i));
+    }
+
+    let (typos, end) = approximate_find(token, &field.compact);
+    if typos > allowed {
+        return None;
+    }
+    Some((typos, end.saturating_sub(token.len())))
+}
+
+// Every query word has to match in some field. Exact matches and matches at word start rank higher.
+// Returns score and index of field that matched best.
+fn score_entry(entry: &Entry, tokens: &[Vec<char>]) -> Option<(f64, usize)> {
+    let mut total = 0.0;
+    let mut best_field: Option<(usize, f64)> = None;
+
+    for token in tokens {
+        let mut token_best: Option<(usize, f64)> = None;
+
+        for (field_ind, field) in entry.fields.iter().enumerate() {
+            let (typos, start) = match find_match(token, field) {
+                Some(m) => m,
+                None => continue,
+            };
+
+            let at_word_start = (start.saturating_sub(typos)..=start.min(field.compact.len() - 1))
+                .any(|p| field.word_starts[p]);
+
+            let mut score = 1.0 - typos as f64 / (token.len() as f64 + 1.0);
+            if at_word_start {
+                score += 0.5;
+            }
+            if start == 0 {
+                score += 0.25;
+            }
+            score *= field.weight;
+
+            if token_best.map_or(true, |(_, s)| score > s) {
+                token_best = Some((field_ind, score));
+            }
+        }
+
+        let (field, score) = token_best?;
+        total += score;
+        if best_field.map_or(true, |(_, s)| score > s) {
+            best_field = Some((field, score));
+        }
+    }
+
+    best_field.map(|(f, _)| (total / tokens.len() as f64, f))
+}
+
+async fn build_index() -> Result<QuickOpenIndex, ErrorFromRust> {
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let mut index = QuickOpenIndex::default();
+
+    for schema in get_all_schemas_cached().await {
+        let t_info = match find_table_names(&mut db, &schema).await {
+            Ok(t) => t,
+            Err(sqlx::Error::RowNotFound) => continue,
+            Err(e) => return Err(ErrorFromRust::new("Error when getting table names").raw(e)),
+        };
+
+        let mut files: HashMap<String, HashMap<String, AttrValue>> = HashMap::new();
+
+        let rows = sqlx::query(&format!("SELECT * FROM {}", t_info.files_table))
+            .fetch_all(&mut *db)
+            .await
+            .map_err(|e| ErrorFromRust::new("Error when reading cache").raw(e))?;
+
+        for row in rows.iter() {
+            let mut attrs = HashMap::new();
+            for item in schema.items.iter() {
+                if let AttrKey::Text(_) = item.value {
+                    let v: Option<String> = row.get(t_info.field(&item.name)?);
+                    attrs.insert(item.name.clone(), AttrValue::Text(v.unwrap_or_default()));
+                }
+            }
+            files.insert(row.get("path"), attrs);
+        }
+
+        for item in schema.items.iter() {
+            if let AttrKey::TextCollection(_) = item.value {
+                let ident = t_info.field(&item.name)?;
+                let rows = sqlx::query(&format!(
+                    "SELECT path, value FROM {} ORDER BY path, ind",
+                    ident
+                ))
+                .fetch_all(&mut *db)
+                .await
+                .map_err(|e| ErrorFromRust::new("Error when reading cache").raw(e))?;
+
+                for row in rows.iter() {
+                    let path: String = row.get("path");
+                    let value: Option<String> = row.get("value");
+                    if let Some(attrs) = files.get_mut(&path) {
+                        let entry = attrs
+                            .entry(item.name.clone())
+                            .or_insert_with(|| AttrValue::TextCollection(Vec::new()));
+                        if let AttrValue::TextCollection(values) = entry {
+                            values.extend(value);
+                        }
+                    }
+                }
+            }
+        }
+
+        for (path, attrs) in files {
+            index.insert(make_entry(&path, &schema, &attrs));
+        }
+    }
+
+    Ok(index)
+}
+
+pub async fn quick_open(
+    query: &str,
+    limit: Option<usize>,
+) -> Result<Vec<QuickOpenHit>, ErrorFromRust> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+
+    if let Some(index) = INDEX.read().unwrap().as_ref() {
+        return Ok(index.search(query, limit));
+    }
+
+    let writes_before = WRITES.load(Ordering::SeqCst);
+    let index = build_index().await?;
+    let hits = index.search(query, limit);
+
+    if WRITES.load(Ordering::SeqCst) == writes_before {
+        *INDEX.write().unwrap() = Some(index);
+    }
+
+    Ok(hits)
+}
+
+fn with_index(f: impl FnOnce(&mut QuickOpenIndex)) {
+    WRITES.fetch_add(1, Ordering::SeqCst);
+    if let Some(index) = INDEX.write().unwrap().as_mut() {
+        f(index);
+        index.compact_if_needed();
+    }
+}
+
+// Called after file is written to cache
+pub fn quick_open_update(book: &BookFromDb, schema: &Schema) {
+    if let Some(path) = &book.path {
+        let entry = make_entry(path, schema, &book.attrs);
+        with_index(|index| index.insert(entry));
+    }
+}
+
+pub fn quick_open_remove(path: &str) {
+    with_index(|index| index.remove(path));
+}
+
+pub fn quick_open_remove_under(folder: &str) {
+    with_index(|index| index.remove_under(folder));
+}
+
+// Cache db was switched, deleted or tables were recreated
+pub fn clear_quick_open_index() {
+    WRITES.fetch_add(1, Ordering::SeqCst);
+    *INDEX.write().unwrap() = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::types::SchemaItem;
+
+    fn index(books: &[(&str, &str, &[&str])]) -> QuickOpenIndex {
+        let schema = Schema {
+            name: "Books".to_string(),
+            version: "1.0".to_string(),
+            icon: None,
+            items: vec![
+                SchemaItem {
+                    name: "title".to_string(),
+                    value: AttrKey::Text(None),
+                },
+                SchemaItem {
+                    name: "tags".to_string(),
+                    value: AttrKey::TextCollection(None),
+                },
+            ],
+            internal_path: "".to_string(),
+            internal_name: "".to_string(),
+        };
+
+        let mut index = QuickOpenIndex::default();
+        for (path, title, tags) in books {
+            let attrs = HashMap::from([
+                ("title".to_string(), AttrValue::Text(title.to_string())),
+                (
+                    "tags".to_string(),
+                    AttrValue::TextCollection(tags.iter().map(|t| t.to_string()).collect()),
+                ),
+            ]);
+            index.insert(make_entry(path, &schema, &attrs));
+        }
+        index
+    }
+
+    fn paths(hits: Vec<QuickOpenHit>) -> Vec<String> {
+        hits.into_iter().map(|h| h.path).collect()
+    }
+
+    #[test]
+    fn search_tolerates_typos_and_spacing() {
+        let index = index(&[
+            ("a.md", "The Left Hand of Darkness", &["scifi"]),
+            ("b.md", "The Dispossessed", &["scifi", "utopia"]),
+            ("c.md", "Lefty", &[]),
+        ]);
+
+        assert_eq!(paths(index.search("lefthand", 10)), vec!["a.md"]);
+        assert_eq!(paths(index.search("dispossesed", 10)), vec!["b.md"]);
+        assert_eq!(paths(index.search("darknes left", 10)), vec!["a.md"]);
+        assert_eq!(paths(index.search("utopia", 10)), vec!["b.md"]);
+        assert!(index.search("zzzz", 10).is_empty());
+    }
+
+    #[test]
+    fn exact_and_word_start_matches_rank_higher() {
+        let index = index(&[
+            ("a.md", "Kindred", &[]),
+            ("b.md", "Mankind", &[]),
+            ("c.md", "Kindrid", &[]),
+        ]);
+
+        let hits = index.search("kindred", 10);
+        assert_eq!(paths(hits.clone()), vec!["a.md", "c.md"]);
+        assert!(hits[0].score > hits[1].score);
+
+        assert_eq!(
+            paths(index.search("kind", 10)),
+            vec!["a.md", "c.md", "b.md"]
+        );
+    }
+
+    #[test]
+    fn remove_under_keeps_sibling_with_same_prefix() {
+        let sep = MAIN_SEPARATOR;
+        let books = format!("{0}v{0}Books", sep);
+        let in_books = format!("{}{}Dune.md", books, sep);
+        let in_books_2 = format!("{} 2{}Dune.md", books, sep);
+
+        let mut index = index(&[(&in_books, "Dune", &[]), (&in_books_2, "Dune", &[])]);
+        index.remove_under(&books);
+
+        assert_eq!(paths(index.search("dune", 10)), vec![in_books_2]);
+    }
+}
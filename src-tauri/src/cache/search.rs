@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::tables::TableNames;
+
+// Control characters mark highlighted parts of snippet, they don't appear in notes
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_END: char = '\u{3}';
+const SNIPPET_TOKENS: i64 = 16;
+
+// Match in title or tags says more about note than the same word somewhere in body
+const ATTRS_WEIGHT: f64 = 4.0;
+const BODY_WEIGHT: f64 = 1.0;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnippetPart {
+    pub text: String,
+    pub highlight: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchHit {
+    pub path: String,
+    // Higher is better
+    pub score: f64,
+    pub snippet: Vec<SnippetPart>,
+}
+
+// bm25 is lower for better matches
+pub fn rank_expression(t_info: &TableNames) -> String {
+    format!(
+        "bm25({}, {:.1}, {:.1})",
+        t_info.fts_table, ATTRS_WEIGHT, BODY_WEIGHT
+    )
+}
+
+pub fn snippet_expression(t_info: &TableNames) -> String {
+    format!(
+        "snippet({}, -1, char({}), char({}), '…', {})",
+        t_info.fts_table, HIGHLIGHT_START as u32, HIGHLIGHT_END as u32, SNIPPET_TOKENS
+    )
+}
+
+pub fn search_join(t_info: &TableNames) -> String {
+    format!(
+        "JOIN {0} ON {0}.rowid = {1}.id",
+        t_info.fts_table, t_info.files_table
+    )
+}
+
+pub fn parse_snippet(snippet: &str) -> Vec<SnippetPart> {
+    let mut res: Vec<SnippetPart> = Vec::new();
+    let mut highlight = false;
+    let mut current = String::new();
+
+    for c in snippet.chars() {
+        if c != HIGHLIGHT_START && c != HIGHLIGHT_END {
+            current.push(c);
+            continue;
+        }
+
+        if !current.is_empty() {
+            res.push(SnippetPart {
+                text: std::mem::take(&mut current),
+                highlight,
+            });
+        }
+        highlight = c == HIGHLIGHT_START;
+    }
+
+    if !current.is_empty() {
+        res.push(SnippetPart {
+            text: current,
+            highlight,
+        });
+    }
+
+    res
+}
+
+// Malformed search is user input error, not a bug. It's checked separately,
+// so errors of main query can still be reported as bugs.
+pub async fn check_search(
+    db: &mut SqliteConnection,
+    t_info: &TableNames,
+    search: &str,
+) -> Result<(), ErrorFromRust> {
+    sqlx::query(&format!(
+        "SELECT rowid FROM {0} WHERE {0} MATCH ?1 LIMIT 1",
+        t_info.fts_table
+    ))
+    .bind(search)
+    .fetch_optional(&mut *db)
+    .await
+    .map_err(|e| {
+        ErrorFromRust::new("Invalid search")
+            .info("Use words, \"exact phrases\", prefix* and AND, OR, NOT")
+            .raw(e)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(snippet: &str) -> Vec<(String, bool)> {
+        parse_snippet(snippet)
+            .into_iter()
+            .map(|p| (p.text, p.highlight))
+            .collect()
+    }
+
+    #[test]
+    fn parse_snippet_splits_highlights() {
+        assert_eq!(
+            parts("…the \u{2}dragon\u{3} slept \u{2}пещера\u{3}"),
+            vec![
+                ("…the ".to_string(), false),
+                ("dragon".to_string(), true),
+                (" slept ".to_string(), false),
+                ("пещера".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_snippet_without_highlights() {
+        assert_eq!(parts("plain text"), vec![("plain text".to_string(), false)]);
+        assert!(parts("").is_empty());
+        // Adjacent markers don't produce empty parts
+        assert_eq!(
+            parts("\u{2}a\u{3}\u{2}b\u{3}"),
+            vec![("a".to_string(), true), ("b".to_string(), true)]
+        );
+    }
+}
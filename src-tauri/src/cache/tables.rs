@@ -1,9 +1,16 @@
+use std::collections::HashMap;
+
+use once_cell::sync::OnceCell;
+use sqlx::{Row, SqliteConnection};
+
 use crate::schema::{
     operations::get_all_schemas_cached,
-    types::{AttrKey, Schema},
+    types::{AttrKey, ComputedType, Schema},
 };
+use crate::utils::{errorhandling::ErrorFromRust, hash::stable_hash};
 
-use super::dbconn::get_db_conn;
+use super::dbconn::get_write_conn;
+use super::quick_open::clear_quick_open_index;
 
 pub async fn create_db_tables_for_all_schemas() -> Result<(), sqlx::Error> {
     let schemas = get_all_schemas_cached().await;
@@ -20,21 +27,42 @@ pub async fn create_db_tables_for_schema(schema: Schema) -> Result<(), sqlx::Err
         return Ok(());
     }
 
-    let mut db = get_db_conn().lock().await;
+    let mut db = get_write_conn().await?;
 
-    let TableNames {
-        table_prefix,
-        files_table,
-        folders_table,
-    } = get_table_names(schema.internal_name.clone());
+    let table_names = get_table_names(&mut db, &schema).await?;
 
-    sqlx::query(&format!("DROP TABLE IF EXISTS {};", folders_table))
-        .execute(&mut *db)
-        .await?;
+    // Cache persists between restarts, tables are rebuilt only when stored data would change
+    let storage_hash = schema_storage_hash(&schema);
+
+    let stored_hash: Option<String> =
+        sqlx::query("SELECT schema_hash FROM meta_schemas WHERE internal_name = ?1")
+            .bind(&schema.internal_name)
+            .fetch_optional(&mut *db)
+            .await?
+            .and_then(|r| r.get("schema_hash"));
+
+    let tables_exist =
+        sqlx::query("SELECT name FROM sqlite_master WHERE type='table' AND name = ?1")
+            .bind(&table_names.files_table)
+            .fetch_optional(&mut *db)
+            .await?
+            .is_some();
+
+    if tables_exist && stored_hash.as_deref() == Some(storage_hash.as_str()) {
+        // Cache made before indexes were added gets them without rebuild
+        return create_indexes(&mut db, &schema, &table_names).await;
+    }
+
+    sqlx::query(&format!(
+        "DROP TABLE IF EXISTS {};",
+        table_names.folders_table
+    ))
+    .execute(&mut *db)
+    .await?;
 
     sqlx::query(&format!(
         "CREATE TABLE {} (path TEXT PRIMARY KEY, name TEXT);",
-        folders_table
+        table_names.folders_table
     ))
     .execute(&mut *db)
     .await?;
@@ -44,35 +72,45 @@ pub async fn create_db_tables_for_schema(schema: Schema) -> Result<(), sqlx::Err
     let mut side_tables: Vec<String> = Vec::new();
     let mut side_tables_names: Vec<String> = Vec::new();
 
-    for schema_i in schema.items {
-        let columm_name = schema_i.name.clone();
-        let table_name = format!("{}{}", table_prefix, columm_name);
+    for schema_i in schema.items.clone() {
+        let ident = table_names
+            .field(&schema_i.name)
+            .map_err(|_| sqlx::Error::ColumnNotFound(schema_i.name.clone()))?
+            .to_string();
         match schema_i.value {
             AttrKey::Text(_) | AttrKey::Date(_) | AttrKey::Image(_) => {
-                columns.push(format!("{} TEXT", columm_name));
+                columns.push(format!("{} TEXT", ident));
             }
             AttrKey::Number(_) => {
-                columns.push(format!("{} REAL", columm_name));
+                columns.push(format!("{} REAL", ident));
             }
+            AttrKey::Computed(settings) => match settings.and_then(|s| s.returns) {
+                Some(ComputedType::Text) | Some(ComputedType::Date) => {
+                    columns.push(format!("{} TEXT", ident));
+                }
+                Some(ComputedType::Number) | None => {
+                    columns.push(format!("{} REAL", ident));
+                }
+            },
             AttrKey::TextCollection(_) | AttrKey::DateCollection(_) => {
                 side_tables.push(format!(
-                    "CREATE TABLE {} 
-                    (id INTEGER PRIMARY KEY, ind INTEGER, path TEXT, value TEXT, 
-                    UNIQUE(ind,path) FOREIGN KEY (path) 
+                    "CREATE TABLE {}
+                    (id INTEGER PRIMARY KEY, ind INTEGER, path TEXT, value TEXT,
+                    UNIQUE(ind,path) FOREIGN KEY (path)
                     REFERENCES {} (path) ON DELETE CASCADE);",
-                    table_name, files_table
+                    ident, table_names.files_table
                 ));
-                side_tables_names.push(table_name);
+                side_tables_names.push(ident);
             }
             AttrKey::DatesPairCollection(_) => {
                 side_tables.push(format!(
-                    "CREATE TABLE {} 
+                    "CREATE TABLE {}
                     (id INTEGER PRIMARY KEY, ind INTEGER, path TEXT, started TEXT, finished TEXT,
-                    UNIQUE(ind,path) FOREIGN KEY (path) 
+                    UNIQUE(ind,path) FOREIGN KEY (path)
                     REFERENCES {} (path) ON DELETE CASCADE);",
-                    table_name, files_table
+                    ident, table_names.files_table
                 ));
-                side_tables_names.push(table_name);
+                side_tables_names.push(ident);
             }
         }
     }
@@ -83,14 +121,24 @@ pub async fn create_db_tables_for_schema(schema: Schema) -> Result<(), sqlx::Err
             .await?;
     }
 
-    sqlx::query(&format!("DROP TABLE IF EXISTS {}", files_table))
+    sqlx::query(&format!("DROP TABLE IF EXISTS {}", table_names.fts_table))
         .execute(&mut *db)
         .await?;
 
+    sqlx::query(&format!("DROP TABLE IF EXISTS {}", table_names.files_table))
+        .execute(&mut *db)
+        .await?;
+
+    // Leading comma is part of columns, so schema with no stored fields still gets a valid table.
+    // id is kept on upsert and links file to its full text row.
+    // hash of file content lets verification notice edits that kept modified time.
     sqlx::query(&format!(
-        "CREATE TABLE {} (path TEXT PRIMARY KEY, modified TEXT, {})",
-        files_table,
-        columns.join(", ")
+        "CREATE TABLE {} (id INTEGER PRIMARY KEY, path TEXT NOT NULL UNIQUE, modified TEXT, size INTEGER, hash TEXT{})",
+        table_names.files_table,
+        columns
+            .iter()
+            .map(|c| format!(", {}", c))
+            .collect::<String>()
     ))
     .execute(&mut *db)
     .await?;
@@ -99,20 +147,394 @@ pub async fn create_db_tables_for_schema(schema: Schema) -> Result<(), sqlx::Err
         sqlx::query(&q).execute(&mut *db).await?;
     }
 
+    // Text attributes and note body, rowid is id of file
+    sqlx::query(&format!(
+        "CREATE VIRTUAL TABLE {} USING fts5(attrs, body, tokenize = 'unicode61 remove_diacritics 2', prefix = '2 3')",
+        table_names.fts_table
+    ))
+    .execute(&mut *db)
+    .await?;
+
+    // Files are removed in many ways, trigger keeps full text index in sync with all of them
+    sqlx::query(&format!(
+        "CREATE TRIGGER {0}_fts_delete AFTER DELETE ON {0}
+        BEGIN DELETE FROM {1} WHERE rowid = old.id; END;",
+        table_names.files_table, table_names.fts_table
+    ))
+    .execute(&mut *db)
+    .await?;
+
+    create_indexes(&mut db, &schema, &table_names).await?;
+
+    sqlx::query("UPDATE meta_schemas SET schema_hash = ?1 WHERE internal_name = ?2")
+        .bind(&storage_hash)
+        .bind(&schema.internal_name)
+        .execute(&mut *db)
+        .await?;
+
+    // Files of this schema are gone until next scan
+    clear_quick_open_index();
+
+    Ok(())
+}
+
+// Single value fields are sorted and filtered by column. Collections are joined to files by path
+// in item order and filtered by value. Images are never compared, so they get no index.
+async fn create_indexes(
+    db: &mut SqliteConnection,
+    schema: &Schema,
+    table_names: &TableNames,
+) -> Result<(), sqlx::Error> {
+    let mut indexes: Vec<(String, String, String)> = Vec::new();
+
+    for schema_i in schema.items.iter() {
+        let ident = table_names
+            .field(&schema_i.name)
+            .map_err(|_| sqlx::Error::ColumnNotFound(schema_i.name.clone()))?
+            .to_string();
+        match schema_i.value {
+            AttrKey::Text(_) | AttrKey::Date(_) | AttrKey::Number(_) | AttrKey::Computed(_) => {
+                indexes.push((
+                    format!("{}_{}", table_names.files_table, ident),
+                    table_names.files_table.clone(),
+                    ident,
+                ));
+            }
+            AttrKey::TextCollection(_) | AttrKey::DateCollection(_) => {
+                indexes.push((
+                    format!("{}_path", ident),
+                    ident.clone(),
+                    "path, ind".to_string(),
+                ));
+                indexes.push((format!("{}_value", ident), ident, "value, path".to_string()));
+            }
+            AttrKey::DatesPairCollection(_) => {
+                indexes.push((
+                    format!("{}_path", ident),
+                    ident.clone(),
+                    "path, ind".to_string(),
+                ));
+                indexes.push((
+                    format!("{}_started", ident),
+                    ident.clone(),
+                    "started".to_string(),
+                ));
+                indexes.push((format!("{}_finished", ident), ident, "finished".to_string()));
+            }
+            AttrKey::Image(_) => (),
+        }
+    }
+
+    for (name, table, columns) in indexes {
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+            name, table, columns
+        ))
+        .execute(&mut *db)
+        .await?;
+    }
+
     Ok(())
 }
 
+// Only things that affect what is stored in cache. Display settings like font or size are ignored,
+// computed expressions are included because values need to be recalculated.
+pub fn schema_storage_hash(schema: &Schema) -> String {
+    let signature: Vec<String> = schema
+        .items
+        .iter()
+        .map(|i| {
+            let kind = match &i.value {
+                AttrKey::Text(_) => "Text".to_string(),
+                AttrKey::TextCollection(_) => "TextCollection".to_string(),
+                AttrKey::Number(_) => "Number".to_string(),
+                AttrKey::Date(_) => "Date".to_string(),
+                AttrKey::DateCollection(_) => "DateCollection".to_string(),
+                AttrKey::DatesPairCollection(_) => "DatesPairCollection".to_string(),
+                AttrKey::Image(_) => "Image".to_string(),
+                AttrKey::Computed(s) => format!(
+                    "Computed:{}:{:?}",
+                    s.as_ref().map(|s| s.expression.as_str()).unwrap_or(""),
+                    s.as_ref().and_then(|s| s.returns.clone())
+                ),
+            };
+            format!("{}={}", i.name, kind)
+        })
+        .collect();
+
+    stable_hash(signature.join("\n").as_bytes())
+}
+
+// Schema and field names are user input and can contain anything: spaces, quotes, unicode, SQL keywords.
+// They never go into SQL directly. Each schema and each field gets a numeric id stored in cache db,
+// and identifiers are built from those ids only.
+//
+// s{schema_id}_files           - main table
+// s{schema_id}_folders         - folders table
+// s{schema_id}_fts             - full text index of files
+// f{field_id}                  - column in main table for single value fields
+// s{schema_id}_f{field_id}     - side table for collection fields
+pub async fn create_meta_tables(db: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS meta_schemas
+        (id INTEGER PRIMARY KEY AUTOINCREMENT, internal_name TEXT NOT NULL UNIQUE, schema_hash TEXT);",
+    )
+    .execute(&mut *db)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS meta_fields
+        (id INTEGER PRIMARY KEY AUTOINCREMENT, schema_id INTEGER NOT NULL, field_name TEXT NOT NULL,
+        UNIQUE(schema_id, field_name));",
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
 pub struct TableNames {
-    pub table_prefix: String,
     pub files_table: String,
     pub folders_table: String,
+    pub fts_table: String,
+    // Schema field name -> column name or side table name
+    pub fields: HashMap<String, String>,
 }
 
-pub fn get_table_names(schema_internal_name: String) -> TableNames {
-    let table_prefix = format!("{}_", schema_internal_name);
-    return TableNames {
-        table_prefix: table_prefix.clone(),
-        files_table: format!("{}{}", table_prefix.clone(), "files"),
-        folders_table: format!("{}{}", table_prefix.clone(), "folders"),
+impl TableNames {
+    // Field can be unknown when name comes from frontend or schema was changed meanwhile
+    pub fn field(&self, name: &str) -> Result<&str, ErrorFromRust> {
+        self.fields.get(name).map(|f| f.as_str()).ok_or_else(|| {
+            ErrorFromRust::new("Unknown field")
+                .info("Schema might have been changed, reload it and try again")
+                .raw(name)
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SchemaIds {
+    schema_id: i64,
+    field_ids: HashMap<String, i64>,
+}
+
+// Ids never change once created, so it's safe to keep them in memory
+static SCHEMA_IDS_CACHE: OnceCell<std::sync::Mutex<HashMap<String, SchemaIds>>> = OnceCell::new();
+
+fn get_ids_cache() -> &'static std::sync::Mutex<HashMap<String, SchemaIds>> {
+    SCHEMA_IDS_CACHE.get_or_init(|| std::sync::Mutex::new(HashMap::new()))
+}
+
+// Ids belong to a cache db, so they must be dropped when db is switched or deleted
+pub fn clear_table_names_cache() {
+    get_ids_cache().lock().unwrap().clear();
+}
+
+// Without create, ids are only looked up, so it works on read only connection.
+// Schema or field missing in meta tables is RowNotFound then, there are no tables for it either.
+async fn get_schema_ids(
+    db: &mut SqliteConnection,
+    schema: &Schema,
+    create: bool,
+) -> Result<SchemaIds, sqlx::Error> {
+    if let Some(cached) = get_ids_cache().lock().unwrap().get(&schema.internal_name) {
+        if schema
+            .items
+            .iter()
+            .all(|i| cached.field_ids.contains_key(&i.name))
+        {
+            return Ok(cached.clone());
+        }
+    }
+
+    if create {
+        sqlx::query("INSERT OR IGNORE INTO meta_schemas (internal_name) VALUES (?1)")
+            .bind(&schema.internal_name)
+            .execute(&mut *db)
+            .await?;
+    }
+
+    let schema_id: i64 = sqlx::query("SELECT id FROM meta_schemas WHERE internal_name = ?1")
+        .bind(&schema.internal_name)
+        .fetch_one(&mut *db)
+        .await?
+        .get("id");
+
+    if create {
+        for item in &schema.items {
+            sqlx::query(
+                "INSERT OR IGNORE INTO meta_fields (schema_id, field_name) VALUES (?1, ?2)",
+            )
+            .bind(schema_id)
+            .bind(&item.name)
+            .execute(&mut *db)
+            .await?;
+        }
+    }
+
+    let field_ids: HashMap<String, i64> =
+        sqlx::query("SELECT id, field_name FROM meta_fields WHERE schema_id = ?1")
+            .bind(schema_id)
+            .fetch_all(&mut *db)
+            .await?
+            .iter()
+            .map(|r| (r.get("field_name"), r.get("id")))
+            .collect();
+
+    if schema
+        .items
+        .iter()
+        .any(|i| !field_ids.contains_key(&i.name))
+    {
+        return Err(sqlx::Error::RowNotFound);
+    }
+
+    let ids = SchemaIds {
+        schema_id,
+        field_ids,
     };
+
+    get_ids_cache()
+        .lock()
+        .unwrap()
+        .insert(schema.internal_name.clone(), ids.clone());
+
+    Ok(ids)
+}
+
+// Registers schema and fields in meta tables when needed, requires write connection
+pub async fn get_table_names(
+    db: &mut SqliteConnection,
+    schema: &Schema,
+) -> Result<TableNames, sqlx::Error> {
+    let ids = get_schema_ids(db, schema, true).await?;
+    Ok(table_names_from_ids(schema, &ids))
+}
+
+// For readers, fails with RowNotFound when tables for schema were not created yet
+pub async fn find_table_names(
+    db: &mut SqliteConnection,
+    schema: &Schema,
+) -> Result<TableNames, sqlx::Error> {
+    let ids = get_schema_ids(db, schema, false).await?;
+    Ok(table_names_from_ids(schema, &ids))
+}
+
+fn table_names_from_ids(schema: &Schema, ids: &SchemaIds) -> TableNames {
+    let table_prefix = format!("s{}_", ids.schema_id);
+
+    let fields = schema
+        .items
+        .iter()
+        .filter_map(|i| {
+            let id = ids.field_ids.get(&i.name)?;
+            let ident = match i.value {
+                AttrKey::TextCollection(_)
+                | AttrKey::DateCollection(_)
+                | AttrKey::DatesPairCollection(_) => format!("{}f{}", table_prefix, id),
+                _ => format!("f{}", id),
+            };
+            Some((i.name.clone(), ident))
+        })
+        .collect();
+
+    TableNames {
+        files_table: format!("{}files", table_prefix),
+        folders_table: format!("{}folders", table_prefix),
+        fts_table: format!("{}fts", table_prefix),
+        fields,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::types::{ComputedSettings, SchemaItem, TextSettings};
+
+    #[test]
+    fn unknown_field_is_error() {
+        let t_info = TableNames {
+            files_table: "s1_files".to_string(),
+            folders_table: "s1_folders".to_string(),
+            fts_table: "s1_fts".to_string(),
+            fields: HashMap::from([("title".to_string(), "f1".to_string())]),
+        };
+
+        assert_eq!(t_info.field("title").unwrap(), "f1");
+        assert!(t_info.field("removed").is_err());
+    }
+
+    fn schema(items: Vec<(&str, AttrKey)>) -> Schema {
+        Schema {
+            name: "Books".to_string(),
+            version: "1.0".to_string(),
+            icon: None,
+            items: items
+                .into_iter()
+                .map(|(name, value)| SchemaItem {
+                    name: name.to_string(),
+                    value,
+                })
+                .collect(),
+            internal_path: "/v/books".to_string(),
+            internal_name: "books".to_string(),
+        }
+    }
+
+    #[test]
+    fn identifiers_come_from_ids_only() {
+        let schema = schema(vec![
+            ("title\"; DROP TABLE x; --", AttrKey::Text(None)),
+            ("tags", AttrKey::TextCollection(None)),
+            ("not registered", AttrKey::Number(None)),
+        ]);
+        let ids = SchemaIds {
+            schema_id: 7,
+            field_ids: HashMap::from([
+                ("title\"; DROP TABLE x; --".to_string(), 1),
+                ("tags".to_string(), 2),
+            ]),
+        };
+
+        let t_info = table_names_from_ids(&schema, &ids);
+        assert_eq!(t_info.files_table, "s7_files");
+        assert_eq!(t_info.folders_table, "s7_folders");
+        assert_eq!(t_info.fts_table, "s7_fts");
+        assert_eq!(t_info.field("title\"; DROP TABLE x; --").unwrap(), "f1");
+        assert_eq!(t_info.field("tags").unwrap(), "s7_f2");
+        assert!(t_info.field("not registered").is_err());
+    }
+
+    #[test]
+    fn storage_hash_ignores_display_settings() {
+        let plain = schema(vec![("title", AttrKey::Text(None))]);
+        let styled = schema(vec![(
+            "title",
+            AttrKey::Text(Some(TextSettings {
+                display_name: Some("Title".to_string()),
+                is_multiline: Some(true),
+                ..Default::default()
+            })),
+        )]);
+        let retyped = schema(vec![("title", AttrKey::Number(None))]);
+        let computed = |expression: &str| {
+            schema(vec![(
+                "title",
+                AttrKey::Computed(Some(ComputedSettings {
+                    display_name: None,
+                    expression: expression.to_string(),
+                    returns: None,
+                    decimal_places: None,
+                })),
+            )])
+        };
+
+        assert_eq!(schema_storage_hash(&plain), schema_storage_hash(&styled));
+        assert_ne!(schema_storage_hash(&plain), schema_storage_hash(&retyped));
+        assert_ne!(
+            schema_storage_hash(&computed("pages / 2")),
+            schema_storage_hash(&computed("pages / 3"))
+        );
+    }
 }
@@ -0,0 +1,334 @@
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::schema::operations::get_all_schemas_cached;
+use crate::schema::types::{AttrKey, Schema};
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::dbconn::get_read_conn;
+use super::diagnostics::record_query;
+use super::filter::Filter;
+use super::query::{get_files_abstact, BookListGetResult, FilesQuery};
+use super::tables::find_table_names;
+
+pub const TAG_SEPARATOR: char = '/';
+
+// Tags are TextCollection fields called "tags" in any schema
+pub fn is_tags_field(key: &AttrKey, name: &str) -> bool {
+    matches!(key, AttrKey::TextCollection(_)) && name.to_lowercase() == "tags"
+}
+
+// "Sci-Fi", "scifi" and "#SciFi" are the same tag. Nested tags are separated by "/",
+// "Genre / Fantasy" becomes "genre/fantasy". None when nothing is left.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let segments: Vec<String> = tag
+        .nfkc()
+        .collect::<String>()
+        .to_lowercase()
+        .split(TAG_SEPARATOR)
+        .map(|s| {
+            s.chars()
+                .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '_' | '#'))
+                .collect::<String>()
+        })
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    match segments.is_empty() {
+        true => None,
+        false => Some(segments.join(&TAG_SEPARATOR.to_string())),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TagNode {
+    // Normalized full path, "genre/fantasy"
+    pub tag: String,
+    // Most used spelling of last segment, "Fantasy"
+    pub name: String,
+    // Notes with exactly this tag
+    pub count: usize,
+    // Notes with this tag or any tag under it
+    pub total: usize,
+    pub children: Vec<TagNode>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TagDetails {
+    pub tag: TagNode,
+    // From root to direct parent, without children
+    pub parents: Vec<TagNode>,
+}
+
+#[derive(Default)]
+struct TagEntry {
+    spellings: HashMap<String, usize>,
+    direct: HashSet<String>,
+    all: HashSet<String>,
+}
+
+struct TagField {
+    schema: Schema,
+    field: String,
+    // Normalized tag -> values as written in notes
+    values: HashMap<String, HashSet<String>>,
+}
+
+#[derive(Default)]
+struct TagIndex {
+    // Sorted, so parents come before children
+    entries: BTreeMap<String, TagEntry>,
+    fields: Vec<TagField>,
+}
+
+fn parent_tags(tag: &str) -> impl Iterator<Item = &str> {
+    tag.match_indices(TAG_SEPARATOR)
+        .map(move |(i, _)| &tag[..i])
+}
+
+fn last_segment(tag: &str) -> &str {
+    tag.rsplit(TAG_SEPARATOR).next().unwrap_or(tag)
+}
+
+impl TagIndex {
+    fn add(&mut self, note: &str, raw: &str, tag: &str) {
+        let raw_segments: Vec<&str> = raw
+            .split(TAG_SEPARATOR)
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let tag_paths: Vec<&str> = parent_tags(tag).chain(std::iter::once(tag)).collect();
+
+        for (i, path) in tag_paths.iter().enumerate() {
+            let entry = self.entries.entry(path.to_string()).or_default();
+            entry.all.insert(note.to_string());
+
+            // Segments that normalize to nothing make spelling ambiguous
+            if raw_segments.len() == tag_paths.len() {
+                *entry
+                    .spellings
+                    .entry(raw_segments[i].trim_start_matches('#').to_string())
+                    .or_default() += 1;
+            }
+        }
+
+        if let Some(entry) = self.entries.get_mut(tag) {
+            entry.direct.insert(note.to_string());
+        }
+    }
+
+    fn node(&self, tag: &str, entry: &TagEntry) -> TagNode {
+        let name = entry
+            .spellings
+            .iter()
+            .max_by(|a, b| a.1.cmp(b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(s, _)| s.clone())
+            .unwrap_or_else(|| last_segment(tag).to_string());
+
+        TagNode {
+            tag: tag.to_string(),
+            name,
+            count: entry.direct.len(),
+            total: entry.all.len(),
+            children: Vec::new(),
+        }
+    }
+
+    fn tree(&self) -> Vec<TagNode> {
+        let mut by_parent: HashMap<Option<&str>, Vec<TagNode>> = HashMap::new();
+
+        // Reverse order builds children before their parents
+        for (tag, entry) in self.entries.iter().rev() {
+            let mut node = self.node(tag, entry);
+            node.children = by_parent.remove(&Some(tag.as_str())).unwrap_or_default();
+            node.children.reverse();
+
+            let parent = tag.rfind(TAG_SEPARATOR).map(|i| &tag[..i]);
+            by_parent.entry(parent).or_default().push(node);
+        }
+
+        let mut roots = by_parent.remove(&None).unwrap_or_default();
+        roots.reverse();
+        roots
+    }
+
+    fn matches(tag: &str, wanted: &str, include_children: bool) -> bool {
+        tag == wanted
+            || (include_children
+                && tag.starts_with(wanted)
+                && tag[wanted.len()..].starts_with(TAG_SEPARATOR))
+    }
+}
+
+async fn build_tag_index() -> Result<TagIndex, ErrorFromRust> {
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let mut index = TagIndex::default();
+
+    for schema in get_all_schemas_cached().await {
+        let t_info = match find_table_names(&mut db, &schema).await {
+            Ok(t) => t,
+            Err(sqlx::Error::RowNotFound) => continue,
+            Err(e) => return Err(ErrorFromRust::new("Error when getting table names").raw(e)),
+        };
+
+        for item in schema.items.iter() {
+            if !is_tags_field(&item.value, &item.name) {
+                continue;
+            }
+
+            let sql = format!(
+                "SELECT path, value FROM {} WHERE value IS NOT NULL",
+                t_info.field(&item.name)?
+            );
+            let started = Instant::now();
+
+            let rows = sqlx::query(&sql)
+                .fetch_all(&mut *db)
+                .await
+                .map_err(|e| ErrorFromRust::new("Error when getting tags").raw(e))?;
+            record_query(&mut db, "tags", &sql, started.elapsed(), rows.len()).await;
+
+            let mut values: HashMap<String, HashSet<String>> = HashMap::new();
+
+            for row in rows.iter() {
+                let path: String = row.get("path");
+                let raw: String = row.get("value");
+                if let Some(tag) = normalize_tag(&raw) {
+                    index.add(&path, &raw, &tag);
+                    values.entry(tag).or_default().insert(raw);
+                }
+            }
+
+            index.fields.push(TagField {
+                schema: schema.clone(),
+                field: item.name.clone(),
+                values,
+            });
+        }
+    }
+
+    Ok(index)
+}
+
+// Tags of all schemas as a tree
+pub async fn get_tag_tree() -> Result<Vec<TagNode>, ErrorFromRust> {
+    Ok(build_tag_index().await?.tree())
+}
+
+// Every tag with its full path, parents included
+pub async fn get_all_tags() -> Result<Vec<String>, ErrorFromRust> {
+    Ok(build_tag_index().await?.entries.into_keys().collect())
+}
+
+pub async fn get_tag_details(tag: &str) -> Result<TagDetails, ErrorFromRust> {
+    let wanted = normalize_tag(tag).ok_or_else(|| ErrorFromRust::new("Tag is empty"))?;
+    let index = build_tag_index().await?;
+
+    let mut node = index
+        .tree()
+        .into_iter()
+        .find(|n| TagIndex::matches(&wanted, &n.tag, true))
+        .ok_or_else(|| ErrorFromRust::new("Tag does not exist").raw(tag))?;
+
+    let mut parents: Vec<TagNode> = Vec::new();
+    while node.tag != wanted {
+        let child = std::mem::take(&mut node.children)
+            .into_iter()
+            .find(|n| TagIndex::matches(&wanted, &n.tag, true))
+            .ok_or_else(|| ErrorFromRust::new("Tag does not exist").raw(tag))?;
+        parents.push(node);
+        node = child;
+    }
+
+    Ok(TagDetails { tag: node, parents })
+}
+
+// Notes of every schema that have the tag, or with include_children also any tag under it
+pub async fn get_files_by_tag(
+    tag: &str,
+    include_children: bool,
+) -> Result<Vec<BookListGetResult>, ErrorFromRust> {
+    let wanted = normalize_tag(tag).ok_or_else(|| ErrorFromRust::new("Tag is empty"))?;
+    let index = build_tag_index().await?;
+
+    // Filter uses values as written in notes, normalization happens only here
+    let mut by_schema: Vec<(Schema, Vec<Filter>)> = Vec::new();
+    for tag_field in index.fields {
+        let values: Vec<String> = tag_field
+            .values
+            .iter()
+            .filter(|(t, _)| TagIndex::matches(t, &wanted, include_children))
+            .flat_map(|(_, raw)| raw.iter().cloned())
+            .collect();
+
+        if values.is_empty() {
+            continue;
+        }
+
+        let filter = Filter::Any {
+            field: tag_field.field,
+            values,
+        };
+        match by_schema
+            .iter_mut()
+            .find(|(s, _)| s.internal_path == tag_field.schema.internal_path)
+        {
+            Some((_, filters)) => filters.push(filter),
+            None => by_schema.push((tag_field.schema, vec![filter])),
+        }
+    }
+
+    let mut result: Vec<BookListGetResult> = Vec::new();
+    for (schema, filters) in by_schema {
+        let books = get_files_abstact(
+            &FilesQuery::new(schema.clone()).filter(Some(Filter::Or { filters })),
+        )
+        .await?;
+
+        result.push(BookListGetResult { schema, books });
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_tag_merges_spellings() {
+        for tag in ["Sci-Fi", "scifi", "#SciFi", " sci_fi ", "ＳｃｉＦｉ"] {
+            assert_eq!(normalize_tag(tag).as_deref(), Some("scifi"), "{}", tag);
+        }
+        assert_eq!(normalize_tag("Фэнтези").as_deref(), Some("фэнтези"));
+    }
+
+    #[test]
+    fn normalize_tag_keeps_nesting() {
+        assert_eq!(
+            normalize_tag("Genre / Fantasy").as_deref(),
+            Some("genre/fantasy")
+        );
+        assert_eq!(
+            normalize_tag("/genre//#fantasy/").as_deref(),
+            Some("genre/fantasy")
+        );
+        assert_eq!(normalize_tag(" # - / _"), None);
+        assert_eq!(normalize_tag(""), None);
+    }
+
+    #[test]
+    fn parent_tags_from_root() {
+        let parents: Vec<&str> = parent_tags("genre/fantasy/epic").collect();
+        assert_eq!(parents, vec!["genre", "genre/fantasy"]);
+        assert_eq!(parent_tags("genre").count(), 0);
+        assert_eq!(last_segment("genre/fantasy/epic"), "epic");
+    }
+}
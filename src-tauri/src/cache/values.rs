@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Instant;
+
+use crate::schema::operations::get_schema_cached_safe;
+use crate::schema::types::{AttrKey, Schema};
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::dbconn::get_read_conn;
+use super::diagnostics::record_query;
+use super::query::column_value;
+use super::tables::find_table_names;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FieldValueCount {
+    // Text, date or number
+    pub value: Value,
+    // Notes that have this value, note with repeated collection item is counted once
+    pub count: i64,
+}
+
+// Most used values first. Prefix is case insensitive, for collections it's matched against each item.
+pub async fn get_field_values(
+    schema: &Schema,
+    field: &str,
+    prefix: Option<&str>,
+    limit: Option<u32>,
+) -> Result<Vec<FieldValueCount>, ErrorFromRust> {
+    let item = schema
+        .items
+        .iter()
+        .find(|i| i.name == field)
+        .ok_or_else(|| ErrorFromRust::new("Unknown field").raw(field))?;
+
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let t_info = find_table_names(&mut db, schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
+
+    let ident = t_info.field(field)?.to_string();
+    let (table, column) = match item.value {
+        AttrKey::TextCollection(_) | AttrKey::DateCollection(_) => (ident, "value".to_string()),
+        AttrKey::DatesPairCollection(_) => {
+            return Err(ErrorFromRust::new("Field has no distinct values")
+                .info("Reads are pairs of dates, use grouping by year or month instead")
+                .raw(field))
+        }
+        _ => (t_info.files_table.clone(), ident),
+    };
+
+    // Limit -1 means no limit in SQLite
+    let sql = format!(
+        "SELECT {0} AS value, COUNT(DISTINCT path) AS n FROM {1}
+        WHERE {0} IS NOT NULL AND {0} != ''
+        AND CAST({0} AS TEXT) REGEXP ?1
+        GROUP BY {0} ORDER BY n DESC, {0} LIMIT ?2",
+        column, table
+    );
+    let started = Instant::now();
+
+    let res = sqlx::query(&sql)
+        // SQLite lower() folds only ASCII, "(?i)" folds Unicode case
+        .bind(format!("(?i)^{}", regex::escape(prefix.unwrap_or(""))))
+        .bind(limit.map_or(-1, |l| l as i64))
+        .fetch_all(&mut *db)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting field values").raw(e))?;
+    record_query(&mut db, "field values", &sql, started.elapsed(), res.len()).await;
+
+    Ok(res
+        .iter()
+        .map(|r| FieldValueCount {
+            value: column_value(r, "value"),
+            count: column_value(r, "n").as_i64().unwrap_or(0),
+        })
+        .collect())
+}
+
+pub async fn get_field_values_for_path(
+    path: &str,
+    field: &str,
+    prefix: Option<&str>,
+    limit: Option<u32>,
+) -> Result<Vec<FieldValueCount>, ErrorFromRust> {
+    let schema = get_schema_cached_safe(path).await?;
+    get_field_values(&schema, field, prefix, limit).await
+}
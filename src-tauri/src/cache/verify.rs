@@ -0,0 +1,446 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqliteConnection};
+use walkdir::WalkDir;
+
+use crate::files::get_file_modified_time;
+use crate::schema::operations::{get_all_schemas_cached, get_schema_cached};
+use crate::schema::types::{AttrKey, Schema};
+use crate::utils::{errorhandling::ErrorFromRust, hash::stable_hash};
+
+use super::dbconn::{get_read_conn, get_write_conn};
+use super::diagnostics::record_query;
+use super::quick_open::quick_open_remove;
+use super::tables::{find_table_names, TableNames};
+use super::write::{cache_file, cache_folder};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CacheIssueKind {
+    // On disk, but not in cache
+    Missing,
+    // In cache, but file was changed after it was cached
+    Stale,
+    // In cache, but not on disk or not linked to any note
+    Orphaned,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CacheIssue {
+    pub kind: CacheIssueKind,
+    // Files, folders, collection or search table of schema
+    pub table: String,
+    // Note or folder, search rows left without note have no path
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CacheVerifyReport {
+    pub files_checked: usize,
+    pub folders_checked: usize,
+    pub issues: Vec<CacheIssue>,
+    // Issues fixed when fix was requested
+    pub fixed: usize,
+    pub errors: Vec<ErrorFromRust>,
+}
+
+#[derive(Default)]
+struct OnDisk {
+    // Path -> internal path of schema it belongs to
+    files: HashMap<String, String>,
+    folders: HashMap<String, String>,
+}
+
+// Same rules as scan: only notes and folders inside of schema folders
+async fn read_disk(dir: &Path) -> OnDisk {
+    let mut res = OnDisk::default();
+    let mut folder_schemas: HashMap<PathBuf, Option<String>> = HashMap::new();
+
+    for entry in WalkDir::new(dir).into_iter().filter_map(Result::ok) {
+        let path = entry.path();
+        let is_dir = entry.file_type().is_dir();
+
+        let folder = match (is_dir, path.parent()) {
+            (true, _) => path,
+            (false, Some(p)) => p,
+            (false, None) => continue,
+        };
+
+        if !folder_schemas.contains_key(folder) {
+            let schema = get_schema_cached(&folder.to_string_lossy()).await;
+            folder_schemas.insert(folder.to_path_buf(), schema.map(|s| s.internal_path));
+        }
+
+        let Some(Some(schema_path)) = folder_schemas.get(folder) else {
+            continue;
+        };
+
+        let path_str = path.to_string_lossy().to_string();
+        if is_dir {
+            res.folders.insert(path_str, schema_path.clone());
+        } else if path.extension().is_some_and(|e| e == "md") {
+            res.files.insert(path_str, schema_path.clone());
+        }
+    }
+
+    res
+}
+
+fn issue(kind: CacheIssueKind, table: &str, path: Option<String>) -> CacheIssue {
+    CacheIssue {
+        kind,
+        table: table.to_string(),
+        path,
+    }
+}
+
+fn is_stale(path: &str, modified: Option<String>, size: Option<i64>, hash: Option<String>) -> bool {
+    let disk_modified = get_file_modified_time(path).ok();
+    let disk_size = fs::metadata(path).ok().map(|m| m.len() as i64);
+    if disk_modified != modified || disk_size != size {
+        return true;
+    }
+
+    // Content can change within resolution of modified time, or be restored with old time
+    fs::read(path).ok().map(|b| stable_hash(&b)) != hash
+}
+
+async fn verify_schema(
+    db: &mut SqliteConnection,
+    schema: &Schema,
+    t_info: &TableNames,
+    disk: &OnDisk,
+    report: &mut CacheVerifyReport,
+) -> Result<(), ErrorFromRust> {
+    let read_err = |e: sqlx::Error| ErrorFromRust::new("Error when reading cache").raw(e);
+
+    let sql = format!(
+        "SELECT f.path, f.modified, f.size, f.hash, s.rowid IS NOT NULL AS indexed
+        FROM {} f LEFT JOIN {} s ON s.rowid = f.id",
+        t_info.files_table, t_info.fts_table
+    );
+    let started = Instant::now();
+    let files = sqlx::query(&sql)
+        .fetch_all(&mut *db)
+        .await
+        .map_err(read_err)?;
+    record_query(db, "verify", &sql, started.elapsed(), files.len()).await;
+
+    let mut cached_files: HashSet<String> = HashSet::new();
+    for row in files.iter() {
+        let path: String = row.get("path");
+        report.files_checked += 1;
+
+        // Note can also belong to another schema now, after schema folder was moved
+        if disk.files.get(&path) != Some(&schema.internal_path) {
+            report.issues.push(issue(
+                CacheIssueKind::Orphaned,
+                &t_info.files_table,
+                Some(path),
+            ));
+            continue;
+        }
+
+        if is_stale(&path, row.get("modified"), row.get("size"), row.get("hash")) {
+            report.issues.push(issue(
+                CacheIssueKind::Stale,
+                &t_info.files_table,
+                Some(path.clone()),
+            ));
+        } else if !row.get::<bool, _>("indexed") {
+            report.issues.push(issue(
+                CacheIssueKind::Missing,
+                &t_info.fts_table,
+                Some(path.clone()),
+            ));
+        }
+        cached_files.insert(path);
+    }
+
+    for (path, schema_path) in disk.files.iter() {
+        if schema_path == &schema.internal_path && !cached_files.contains(path) {
+            report.issues.push(issue(
+                CacheIssueKind::Missing,
+                &t_info.files_table,
+                Some(path.clone()),
+            ));
+        }
+    }
+
+    let sql = format!("SELECT path FROM {}", t_info.folders_table);
+    let started = Instant::now();
+    let folders = sqlx::query(&sql)
+        .fetch_all(&mut *db)
+        .await
+        .map_err(read_err)?;
+    record_query(db, "verify", &sql, started.elapsed(), folders.len()).await;
+
+    let mut cached_folders: HashSet<String> = HashSet::new();
+    for row in folders.iter() {
+        let path: String = row.get("path");
+        report.folders_checked += 1;
+
+        if disk.folders.get(&path) != Some(&schema.internal_path) {
+            report.issues.push(issue(
+                CacheIssueKind::Orphaned,
+                &t_info.folders_table,
+                Some(path),
+            ));
+            continue;
+        }
+        cached_folders.insert(path);
+    }
+
+    for (path, schema_path) in disk.folders.iter() {
+        if schema_path == &schema.internal_path && !cached_folders.contains(path) {
+            report.issues.push(issue(
+                CacheIssueKind::Missing,
+                &t_info.folders_table,
+                Some(path.clone()),
+            ));
+        }
+    }
+
+    // Rows that survived removal of their note, for example written without foreign keys
+    for item in schema.items.iter() {
+        if !matches!(
+            item.value,
+            AttrKey::TextCollection(_)
+                | AttrKey::DateCollection(_)
+                | AttrKey::DatesPairCollection(_)
+        ) {
+            continue;
+        }
+
+        let side_table = t_info.field(&item.name)?;
+        let sql = format!(
+            "SELECT DISTINCT path FROM {0} WHERE path NOT IN (SELECT path FROM {1})",
+            side_table, t_info.files_table
+        );
+        let started = Instant::now();
+        let rows = sqlx::query(&sql)
+            .fetch_all(&mut *db)
+            .await
+            .map_err(read_err)?;
+        record_query(db, "verify", &sql, started.elapsed(), rows.len()).await;
+
+        for row in rows.iter() {
+            report.issues.push(issue(
+                CacheIssueKind::Orphaned,
+                side_table,
+                Some(row.get("path")),
+            ));
+        }
+    }
+
+    let sql = format!(
+        "SELECT COUNT(*) AS n FROM {} WHERE rowid NOT IN (SELECT id FROM {})",
+        t_info.fts_table, t_info.files_table
+    );
+    let started = Instant::now();
+    let search_rows: i64 = sqlx::query(&sql)
+        .fetch_one(&mut *db)
+        .await
+        .map_err(read_err)?
+        .get("n");
+    record_query(db, "verify", &sql, started.elapsed(), 1).await;
+
+    for _ in 0..search_rows {
+        report
+            .issues
+            .push(issue(CacheIssueKind::Orphaned, &t_info.fts_table, None));
+    }
+
+    Ok(())
+}
+
+// Orphaned rows are deleted from the table they were found in. Removing by path like watcher does
+// would look up schema by path, which is wrong for notes that now belong to another schema.
+async fn remove_orphans(
+    db: &mut SqliteConnection,
+    tables: &[TableNames],
+    report: &mut CacheVerifyReport,
+) -> Result<(), ErrorFromRust> {
+    let write_err = |e: sqlx::Error| ErrorFromRust::new("Error when removing from cache").raw(e);
+    // Search table is linked to files by rowid, its rows are removed after files of schema
+    let mut search_tables: HashSet<usize> = HashSet::new();
+
+    for i in report.issues.iter() {
+        if i.kind != CacheIssueKind::Orphaned {
+            continue;
+        }
+
+        if let Some(path) = &i.path {
+            let sql = format!("DELETE FROM {} WHERE path = ?1", i.table);
+            let started = Instant::now();
+            let res = sqlx::query(&sql)
+                .bind(path)
+                .execute(&mut *db)
+                .await
+                .map_err(write_err)?;
+            record_query(
+                db,
+                "repair",
+                &sql,
+                started.elapsed(),
+                res.rows_affected() as usize,
+            )
+            .await;
+            quick_open_remove(path);
+        }
+
+        if let Some(ind) = tables
+            .iter()
+            .position(|t| t.files_table == i.table || t.fts_table == i.table)
+        {
+            search_tables.insert(ind);
+        }
+        report.fixed += 1;
+    }
+
+    for ind in search_tables {
+        let t = &tables[ind];
+        let sql = format!(
+            "DELETE FROM {} WHERE rowid NOT IN (SELECT id FROM {})",
+            t.fts_table, t.files_table
+        );
+        let started = Instant::now();
+        let res = sqlx::query(&sql)
+            .execute(&mut *db)
+            .await
+            .map_err(write_err)?;
+        record_query(
+            db,
+            "repair",
+            &sql,
+            started.elapsed(),
+            res.rows_affected() as usize,
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+// Compares notes and folders on disk with every cache table of every schema.
+// With fix set, orphaned rows are removed and missing or stale notes are cached again,
+// so drift after missed watcher events is repaired without full rebuild.
+pub async fn verify_cache(dir: &Path, fix: bool) -> Result<CacheVerifyReport, ErrorFromRust> {
+    let disk = read_disk(dir).await;
+    let mut report = CacheVerifyReport::default();
+    let mut tables: Vec<TableNames> = Vec::new();
+
+    {
+        let mut db = get_read_conn()
+            .await
+            .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+        for schema in get_all_schemas_cached().await {
+            let t_info = match find_table_names(&mut db, &schema).await {
+                Ok(t) => t,
+                // Tables are created for every schema before scan, nothing to compare with yet
+                Err(sqlx::Error::RowNotFound) => continue,
+                Err(e) => return Err(ErrorFromRust::new("Error when getting table names").raw(e)),
+            };
+
+            verify_schema(&mut db, &schema, &t_info, &disk, &mut report).await?;
+            tables.push(t_info);
+        }
+    }
+
+    if !fix {
+        return Ok(report);
+    }
+
+    {
+        let mut db = get_write_conn()
+            .await
+            .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+        remove_orphans(&mut db, &tables, &mut report).await?;
+    }
+
+    // Folders first, so notes are cached into known folders. Note with several issues is read once.
+    let mut to_cache: Vec<&CacheIssue> = report
+        .issues
+        .iter()
+        .filter(|i| i.kind != CacheIssueKind::Orphaned)
+        .collect();
+    let is_folder = |i: &CacheIssue| tables.iter().any(|t| t.folders_table == i.table);
+    to_cache.sort_by_key(|i| !is_folder(i));
+
+    let mut fixed = 0;
+    let mut errors: Vec<ErrorFromRust> = Vec::new();
+    let mut cached: HashSet<&str> = HashSet::new();
+    for i in to_cache {
+        let Some(path) = i.path.as_deref() else {
+            continue;
+        };
+        if !cached.insert(path) {
+            fixed += 1;
+            continue;
+        }
+
+        let res = match is_folder(i) {
+            true => cache_folder(Path::new(path)).await,
+            false => cache_file(Path::new(path)).await.map(|_| ()),
+        };
+        match res {
+            Ok(_) => fixed += 1,
+            Err(e) => errors.push(e),
+        }
+    }
+
+    report.fixed += fixed;
+    report.errors = errors;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Connection;
+
+    #[tokio::test]
+    async fn removing_orphaned_note_also_clears_its_search_row() {
+        let mut db = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        for q in [
+            "CREATE TABLE files (id INTEGER PRIMARY KEY, path TEXT UNIQUE)",
+            "CREATE VIRTUAL TABLE fts USING fts5(attrs, body)",
+            "INSERT INTO files (id, path) VALUES (1, 'Dune.md'), (2, 'Emma.md')",
+            "INSERT INTO fts (rowid, attrs, body) VALUES (1, 'Dune', ''), (2, 'Emma', '')",
+        ] {
+            sqlx::query(q).execute(&mut db).await.unwrap();
+        }
+
+        let tables = vec![TableNames {
+            files_table: "files".to_string(),
+            folders_table: "folders".to_string(),
+            fts_table: "fts".to_string(),
+            fields: HashMap::new(),
+        }];
+        let mut report = CacheVerifyReport {
+            issues: vec![issue(
+                CacheIssueKind::Orphaned,
+                "files",
+                Some("Dune.md".to_string()),
+            )],
+            ..Default::default()
+        };
+
+        remove_orphans(&mut db, &tables, &mut report).await.unwrap();
+
+        let search_rows: Vec<i64> = sqlx::query("SELECT rowid FROM fts")
+            .fetch_all(&mut db)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.get(0))
+            .collect();
+        assert_eq!(search_rows, vec![2]);
+        assert_eq!(report.fixed, 1);
+    }
+}
@@ -1,25 +1,135 @@
-use sqlx::{QueryBuilder, Sqlite};
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Connection, QueryBuilder, Row, Sqlite, SqliteConnection};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::{Arc, Mutex};
+use tokio::{select, sync::mpsc, task};
 use walkdir::WalkDir;
 
-use crate::files::{read_file_by_path, FileReadMode};
-use crate::schema::operations::get_schema_cached_safe;
-use crate::schema::types::{AttrKey, AttrValue};
+use crate::files::{
+    get_file_modified_time, read_file_by_path, read_file_with_schema, FileReadMode,
+};
+use crate::schema::operations::{
+    get_all_schemas_cached, get_schema_cached, get_schema_cached_safe,
+};
+use crate::schema::types::{AttrKey, AttrValue, Schema};
 use crate::utils::errorhandling::ErrorFromRust;
 
-use super::dbconn::get_db_conn;
+use super::dbconn::{get_read_conn, get_write_conn};
 use super::query::BookFromDb;
-use super::tables::get_table_names;
+use super::quick_open::{quick_open_remove, quick_open_remove_under, quick_open_update};
+use super::tables::{find_table_names, get_table_names};
+
+// Files written in one transaction during scan. Each file also gets a savepoint,
+// so a failed file is rolled back alone and doesn't leave half written rows.
+const SCAN_BATCH_SIZE: usize = 500;
+
+// Parsing is CPU and disk bound, more workers than this only fight over disk
+const MAX_PARSE_WORKERS: usize = 8;
+
+// Progress is sent after each written batch and every this many entries found on disk
+const DISCOVERY_PROGRESS_STEP: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ScanPhase {
+    Discovering,
+    Folders,
+    Files,
+    Cleanup,
+    Done,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanProgress {
+    pub phase: ScanPhase,
+    pub processed: usize,
+    // While discovering total is not known yet and equals processed
+    pub total: usize,
+    pub current_path: Option<String>,
+    pub errors: usize,
+}
+
+impl ScanProgress {
+    fn update(&mut self, phase: ScanPhase, processed: usize, total: usize) -> &Self {
+        self.phase = phase;
+        self.processed = processed;
+        self.total = total;
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ScanCommand {
+    Cancel,
+}
+
+// Control channels of scans in progress, there can be several, e.g. initial scan and added folder
+static SCAN_COMMANDS: Mutex<Vec<mpsc::Sender<ScanCommand>>> = Mutex::new(Vec::new());
+
+fn register_scan() -> mpsc::Receiver<ScanCommand> {
+    let (cmd_tx, cmd_rx) = mpsc::channel::<ScanCommand>(8);
+
+    let mut senders = SCAN_COMMANDS.lock().unwrap();
+    senders.retain(|s| !s.is_closed());
+    senders.push(cmd_tx);
+
+    cmd_rx
+}
+
+// Sends command to all running scans, returns how many received it
+pub fn send_scan_command(cmd: ScanCommand) -> usize {
+    let mut senders = SCAN_COMMANDS.lock().unwrap();
+    senders.retain(|s| !s.is_closed());
+
+    senders
+        .iter()
+        .filter(|s| s.try_send(cmd.clone()).is_ok())
+        .count()
+}
 
 enum InsertValues {
     Text(String),
     Number(f64),
+    // Attribute is not set in file, not the same as "" or 0
+    Null,
+}
+
+fn tx_err(e: sqlx::Error) -> ErrorFromRust {
+    ErrorFromRust::new("Error when saving to cache").raw(e)
 }
 
 // Function to insert a file record into the database
 pub async fn insert_file(file: &BookFromDb) -> Result<(), ErrorFromRust> {
-    let mut db = get_db_conn().lock().await;
+    let path = match file.path.as_ref() {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+
+    let files_schema = get_schema_cached_safe(path).await?;
 
+    let mut db = get_write_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    // Dropped transaction is rolled back, so early returns on error leave no partial rows
+    let mut tx = db.begin().await.map_err(tx_err)?;
+    write_file(&mut tx, file, &files_schema).await?;
+    tx.commit().await.map_err(tx_err)?;
+
+    quick_open_update(file, &files_schema);
+    Ok(())
+}
+
+// Main row and all collection rows of a file, caller is responsible for transaction
+async fn write_file(
+    db: &mut SqliteConnection,
+    file: &BookFromDb,
+    files_schema: &Schema,
+) -> Result<(), ErrorFromRust> {
     let path = match file.path.as_ref() {
         Some(p) => p,
         None => return Ok(()),
@@ -28,45 +138,70 @@ pub async fn insert_file(file: &BookFromDb) -> Result<(), ErrorFromRust> {
     let mut insert_keys: Vec<String> = Vec::new();
     let mut insert_values: Vec<InsertValues> = Vec::new();
 
+    // Text attributes go to full text index together with body
+    let mut search_attrs: Vec<String> = Vec::new();
+
     // Don't forget to add ";" at the end of statements you push here
     let mut separate_statements: Vec<QueryBuilder<'_, Sqlite>> = Vec::new();
 
-    let files_schema = get_schema_cached_safe(&path).await?;
-    let table_names = get_table_names(files_schema.internal_name);
+    let table_names = get_table_names(db, files_schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
 
-    for schema_i in files_schema.items {
+    for schema_i in files_schema.items.clone() {
         let name = schema_i.name;
+        let ident = table_names.field(&name)?.to_string();
         match schema_i.value {
             AttrKey::Text(_) | AttrKey::Date(_) | AttrKey::Image(_) => {
                 let v = match file.attrs.get(&name) {
-                    Some(AttrValue::Text(v)) => v,
-                    Some(AttrValue::Date(v)) => v,
-                    Some(AttrValue::Image(v)) => v,
-                    _ => "",
+                    Some(AttrValue::Text(v)) => {
+                        search_attrs.push(v.clone());
+                        InsertValues::Text(v.clone())
+                    }
+                    Some(AttrValue::Date(v)) => InsertValues::Text(v.clone()),
+                    Some(AttrValue::Image(v)) => InsertValues::Text(v.clone()),
+                    _ => InsertValues::Null,
                 };
-                insert_keys.push(name);
-                insert_values.push(InsertValues::Text(v.to_string()));
+                insert_keys.push(ident);
+                insert_values.push(v);
             }
             AttrKey::Number(_) => {
                 let v = match file.attrs.get(&name) {
-                    Some(AttrValue::Number(v)) => v.to_owned() as f64,
-                    _ => 0.0,
+                    Some(AttrValue::Number(v)) => InsertValues::Number(v.to_owned()),
+                    _ => InsertValues::Null,
+                };
+
+                insert_keys.push(ident);
+                insert_values.push(v);
+            }
+            // Value is already calculated when file is read
+            AttrKey::Computed(_) => {
+                let v = match file.attrs.get(&name) {
+                    Some(AttrValue::Number(v)) => InsertValues::Number(v.to_owned()),
+                    Some(AttrValue::Text(v)) => {
+                        search_attrs.push(v.clone());
+                        InsertValues::Text(v.to_owned())
+                    }
+                    Some(AttrValue::Date(v)) => InsertValues::Text(v.to_owned()),
+                    // Expression failed or used attributes that are not set
+                    _ => InsertValues::Null,
                 };
 
-                insert_keys.push(name);
-                insert_values.push(InsertValues::Number(v.to_owned()));
+                insert_keys.push(ident);
+                insert_values.push(v);
             }
             AttrKey::TextCollection(_) | AttrKey::DateCollection(_) => {
                 let v = match file.attrs.get(&name) {
-                    Some(AttrValue::TextCollection(v)) => v.clone(),
+                    Some(AttrValue::TextCollection(v)) => {
+                        search_attrs.extend(v.iter().cloned());
+                        v.clone()
+                    }
                     Some(AttrValue::DateCollection(v)) => v.clone(),
                     _ => Vec::new(),
                 };
 
-                let mut deletion: QueryBuilder<'_, Sqlite> = QueryBuilder::new(&format!(
-                    "DELETE FROM {}{} WHERE path =",
-                    table_names.table_prefix, name
-                ));
+                let mut deletion: QueryBuilder<'_, Sqlite> =
+                    QueryBuilder::new(&format!("DELETE FROM {} WHERE path =", ident));
                 deletion
                     .push_bind(path)
                     .push(" AND ind >=")
@@ -77,10 +212,8 @@ pub async fn insert_file(file: &BookFromDb) -> Result<(), ErrorFromRust> {
                     continue;
                 }
 
-                let mut insertion: QueryBuilder<'_, Sqlite> = QueryBuilder::new(&format!(
-                    "INSERT INTO {}{} (ind, path, value) VALUES ",
-                    table_names.table_prefix, name
-                ));
+                let mut insertion: QueryBuilder<'_, Sqlite> =
+                    QueryBuilder::new(&format!("INSERT INTO {} (ind, path, value) VALUES ", ident));
 
                 v.iter().enumerate().for_each(|(ind, value)| {
                     if ind > 0 {
@@ -104,10 +237,8 @@ pub async fn insert_file(file: &BookFromDb) -> Result<(), ErrorFromRust> {
                     _ => &Vec::new(),
                 };
 
-                let mut deletion: QueryBuilder<'_, Sqlite> = QueryBuilder::new(&format!(
-                    "DELETE FROM {}{} WHERE path=",
-                    table_names.table_prefix, name
-                ));
+                let mut deletion: QueryBuilder<'_, Sqlite> =
+                    QueryBuilder::new(&format!("DELETE FROM {} WHERE path=", ident));
                 deletion
                     .push_bind(path)
                     .push(" AND ind >= ")
@@ -119,8 +250,8 @@ pub async fn insert_file(file: &BookFromDb) -> Result<(), ErrorFromRust> {
                 }
 
                 let mut insertion: QueryBuilder<'_, Sqlite> = QueryBuilder::new(&format!(
-                    "INSERT INTO {}{} (ind, path, started, finished) VALUES ",
-                    table_names.table_prefix, name
+                    "INSERT INTO {} (ind, path, started, finished) VALUES ",
+                    ident
                 ));
 
                 v.iter().enumerate().for_each(|(ind, value)| {
@@ -145,8 +276,10 @@ pub async fn insert_file(file: &BookFromDb) -> Result<(), ErrorFromRust> {
         }
     }
 
+    let size = fs::metadata(path).ok().map(|m| m.len() as i64);
+
     let mut qb = QueryBuilder::new(&format!("INSERT INTO {}", table_names.files_table));
-    qb.push("(path, modified");
+    qb.push("(path, modified, size, hash");
 
     insert_keys.iter().for_each(|k| {
         qb.push(", ").push(k);
@@ -155,26 +288,58 @@ pub async fn insert_file(file: &BookFromDb) -> Result<(), ErrorFromRust> {
     qb.push(") VALUES (");
     qb.push_bind(path)
         .push(", ")
-        .push_bind(file.modified.clone());
+        .push_bind(file.modified.clone())
+        .push(", ")
+        .push_bind(size)
+        .push(", ")
+        .push_bind(file.hash.clone());
 
     insert_values.iter().for_each(|k| {
         qb.push(", ").push_bind(match k {
-            InsertValues::Text(v) => v.clone(),
-            InsertValues::Number(v) => v.to_string(),
+            InsertValues::Text(v) => Some(v.clone()),
+            InsertValues::Number(v) => Some(v.to_string()),
+            InsertValues::Null => None,
         });
     });
 
-    qb.push(") ON CONFLICT(path) DO UPDATE SET modified=excluded.modified");
+    qb.push(") ON CONFLICT(path) DO UPDATE SET modified=excluded.modified, size=excluded.size, hash=excluded.hash");
 
     insert_keys.iter().for_each(|k| {
         qb.push(", ").push(k).push("=excluded.").push(k);
     });
 
-    qb.build().execute(&mut *db).await.map_err(|e| {
-        ErrorFromRust::new("Error when saving file to cache")
-            .info("Unless you changed schema files manually without app restart, this is likely a bug, please report it")
-            .raw(e)
-    })?;
+    qb.push(" RETURNING id");
+
+    let id: i64 = qb
+        .build()
+        .fetch_one(&mut *db)
+        .await
+        .map_err(|e| {
+            ErrorFromRust::new("Error when saving file to cache")
+                .info("Unless you changed schema files manually without app restart, this is likely a bug, please report it")
+                .raw(e)
+        })?
+        .get("id");
+
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE rowid = ?1",
+        table_names.fts_table
+    ))
+    .bind(id)
+    .execute(&mut *db)
+    .await
+    .map_err(|e| ErrorFromRust::new("Error when updating search index").raw(e))?;
+
+    sqlx::query(&format!(
+        "INSERT INTO {} (rowid, attrs, body) VALUES (?1, ?2, ?3)",
+        table_names.fts_table
+    ))
+    .bind(id)
+    .bind(search_attrs.join("\n"))
+    .bind(file.markdown.clone().unwrap_or_default())
+    .execute(&mut *db)
+    .await
+    .map_err(|e| ErrorFromRust::new("Error when updating search index").raw(e))?;
 
     for mut qq in separate_statements {
         qq.build().execute(&mut *db).await.map_err(|e| {
@@ -187,24 +352,39 @@ pub async fn insert_file(file: &BookFromDb) -> Result<(), ErrorFromRust> {
     Ok(())
 }
 
+// Body is read for full text index, but not returned
 pub async fn cache_file(path: &Path) -> Result<BookFromDb, ErrorFromRust> {
-    match read_file_by_path(&path.to_string_lossy(), FileReadMode::OnlyMeta).await {
-        Ok(file) => insert_file(&file.book).await.map(|_| file.book),
+    match read_file_by_path(&path.to_string_lossy(), FileReadMode::FullFile).await {
+        Ok(file) => insert_file(&file.book).await.map(|_| BookFromDb {
+            markdown: None,
+            ..file.book
+        }),
         Err(e) => Err(e),
     }
 }
 
 pub async fn remove_file_from_cache(path: &Path) -> Result<(), ErrorFromRust> {
-    let mut db = get_db_conn().lock().await;
+    let mut db = get_write_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
 
-    let files_schema = get_schema_cached_safe(&path.to_string_lossy().to_string()).await?;
-    let table_names = get_table_names(files_schema.internal_name);
+    delete_file_row(&mut db, &path.to_string_lossy()).await?;
+
+    quick_open_remove(&path.to_string_lossy());
+    Ok(())
+}
+
+async fn delete_file_row(db: &mut SqliteConnection, path: &str) -> Result<(), ErrorFromRust> {
+    let files_schema = get_schema_cached_safe(path).await?;
+    let table_names = get_table_names(db, &files_schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
 
     sqlx::query(&format!(
         "DELETE FROM {} WHERE path=?1",
         table_names.files_table
     ))
-    .bind(path.to_string_lossy().to_string())
+    .bind(path)
     .execute(&mut *db)
     .await
     .map_err(|e| ErrorFromRust::new("Error when removing file from cache").raw(e))?;
@@ -212,45 +392,510 @@ pub async fn remove_file_from_cache(path: &Path) -> Result<(), ErrorFromRust> {
     Ok(())
 }
 
-pub async fn cache_files_and_folders<P: AsRef<Path>>(dir: P) -> Result<(), ErrorFromRust> {
+#[derive(PartialEq, Debug)]
+struct FileStamp {
+    modified: Option<String>,
+    size: Option<i64>,
+}
+
+fn get_file_stamp(path: &Path) -> Option<FileStamp> {
+    let size = fs::metadata(path).ok()?.len() as i64;
+    let modified = get_file_modified_time(&path.to_string_lossy()).ok()?;
+    Some(FileStamp {
+        modified: Some(modified),
+        size: Some(size),
+    })
+}
+
+struct CachedUnderPath {
+    files: HashMap<String, FileStamp>,
+    folders: HashSet<String>,
+}
+
+// Rows of dir itself and everything inside of it. Prefix ends with separator,
+// so scan of "Books" doesn't pick up "Books 2".
+async fn select_under_path(
+    db: &mut SqliteConnection,
+    table: &str,
+    columns: &str,
+    dir: &str,
+) -> Result<Vec<SqliteRow>, sqlx::Error> {
+    sqlx::query(&format!(
+        "SELECT {} FROM {} WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+        columns, table
+    ))
+    .bind(dir)
+    .bind(format!("{}{}", dir, MAIN_SEPARATOR))
+    .fetch_all(&mut *db)
+    .await
+}
+
+// Same matching as select_under_path, LIKE would treat "_" and "%" in names as wildcards
+async fn delete_under_path(
+    db: &mut SqliteConnection,
+    table: &str,
+    dir: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+        table
+    ))
+    .bind(dir)
+    .bind(format!("{}{}", dir, MAIN_SEPARATOR))
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+// Everything cache currently knows about inside dir, across all schemas
+async fn get_cached_under_path(dir: &str) -> Result<CachedUnderPath, ErrorFromRust> {
+    let mut db = get_read_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let mut res = CachedUnderPath {
+        files: HashMap::new(),
+        folders: HashSet::new(),
+    };
+
+    for schema in get_all_schemas_cached().await {
+        let table_names = match find_table_names(&mut db, &schema).await {
+            Ok(t) => t,
+            // No tables yet, so nothing is cached for this schema
+            Err(sqlx::Error::RowNotFound) => continue,
+            Err(e) => {
+                return Err(ErrorFromRust::new("Error when getting table names").raw(e));
+            }
+        };
+
+        let files = select_under_path(
+            &mut db,
+            &table_names.files_table,
+            "path, modified, size",
+            dir,
+        )
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when reading cache").raw(e))?;
+
+        for r in files {
+            res.files.insert(
+                r.get("path"),
+                FileStamp {
+                    modified: r.get("modified"),
+                    size: r.get("size"),
+                },
+            );
+        }
+
+        let folders = select_under_path(&mut db, &table_names.folders_table, "path", dir)
+            .await
+            .map_err(|e| ErrorFromRust::new("Error when reading cache").raw(e))?;
+
+        for r in folders {
+            res.folders.insert(r.get("path"));
+        }
+    }
+
+    Ok(res)
+}
+
+pub fn default_parse_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(MAX_PARSE_WORKERS)
+}
+
+// Incremental: only files with changed modification time or size are parsed again,
+// and cache entries for files and folders that are no longer on disk are removed.
+// Files are parsed on a pool of blocking workers, all writes go through a single connection.
+//
+// Scan can be stopped with ScanCommand::Cancel. Batches written before that stay in cache,
+// removal of missing entries is skipped, next scan continues from unchanged files.
+pub async fn cache_files_and_folders<P: AsRef<Path>>(
+    dir: P,
+    workers: usize,
+    on_progress: impl Fn(&ScanProgress),
+) -> Result<(), ErrorFromRust> {
+    let dir = dir.as_ref();
     let mut err = ErrorFromRust::new("Error when caching files and folders");
 
-    for entry in WalkDir::new(dir).into_iter().filter_map(Result::ok) {
+    let mut commands = register_scan();
+
+    let mut progress = ScanProgress {
+        phase: ScanPhase::Discovering,
+        processed: 0,
+        total: 0,
+        current_path: Some(dir.to_string_lossy().to_string()),
+        errors: 0,
+    };
+    on_progress(&progress);
+
+    let cached = get_cached_under_path(&dir.to_string_lossy()).await?;
+
+    let mut seen_files: HashSet<String> = HashSet::new();
+    let mut seen_folders: HashSet<String> = HashSet::new();
+
+    // Schema is resolved once per folder, files use schema of their parent folder
+    let mut schemas: Vec<Schema> = Vec::new();
+    let mut folder_schemas: HashMap<PathBuf, Option<usize>> = HashMap::new();
+
+    // Path and index in schemas
+    let mut files_to_cache: Vec<(PathBuf, usize)> = Vec::new();
+    let mut folders_to_cache: Vec<(PathBuf, usize)> = Vec::new();
+
+    for (i, entry) in WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .enumerate()
+    {
+        if let Ok(ScanCommand::Cancel) = commands.try_recv() {
+            on_progress(progress.update(ScanPhase::Cancelled, i, i));
+            return Ok(());
+        }
+
+        let path = entry.path();
+        let path_str = path.to_string_lossy().to_string();
+
+        if i > 0 && i % DISCOVERY_PROGRESS_STEP == 0 {
+            progress.current_path = Some(path_str.clone());
+            on_progress(progress.update(ScanPhase::Discovering, i, i));
+        }
+
+        let folder = if entry.file_type().is_dir() {
+            path
+        } else {
+            match path.parent() {
+                Some(p) => p,
+                None => continue,
+            }
+        };
+
+        if !folder_schemas.contains_key(folder) {
+            let schema = get_schema_cached(&folder.to_string_lossy()).await;
+            let ind = schema.map(|s| {
+                schemas.push(s);
+                schemas.len() - 1
+            });
+            folder_schemas.insert(folder.to_path_buf(), ind);
+        }
+
+        // Files outside of schema folders are not cached
+        let schema_ind = match folder_schemas.get(folder) {
+            Some(Some(i)) => *i,
+            _ => continue,
+        };
+
         if entry.file_type().is_file() {
-            if let Some(extension) = entry.path().extension() {
+            if let Some(extension) = path.extension() {
                 if extension == "md" {
-                    match cache_file(&entry.path()).await {
-                        Ok(_) => (),
-                        Err(e) => {
-                            err = err.sub(e.info(&entry.file_name().to_string_lossy()));
-                        }
+                    seen_files.insert(path_str.clone());
+
+                    let stamp = get_file_stamp(path);
+                    if stamp.is_some() && stamp.as_ref() == cached.files.get(&path_str) {
+                        continue;
                     }
+
+                    files_to_cache.push((path.to_path_buf(), schema_ind));
                 }
             }
         }
         if entry.file_type().is_dir() {
-            match cache_folder(&entry.path()).await {
-                Ok(_) => (),
-                Err(e) => {
-                    err = err.sub(e);
-                }
+            seen_folders.insert(path_str.clone());
+
+            if cached.folders.contains(&path_str) {
+                continue;
+            }
+
+            folders_to_cache.push((path.to_path_buf(), schema_ind));
+        }
+    }
+
+    let folders_total = folders_to_cache.len();
+    for (i, batch) in folders_to_cache.chunks(SCAN_BATCH_SIZE).enumerate() {
+        if let Err(e) = cache_folders_batch(batch, &schemas, &mut err).await {
+            err = err.sub(e);
+        }
+
+        progress.current_path = batch.last().map(|b| b.0.to_string_lossy().to_string());
+        progress.errors = err.sub_errors.len();
+        let processed = (i * SCAN_BATCH_SIZE + batch.len()).min(folders_total);
+        on_progress(progress.update(ScanPhase::Folders, processed, folders_total));
+    }
+
+    let files_total = files_to_cache.len();
+    progress.current_path = None;
+    on_progress(progress.update(ScanPhase::Files, 0, files_total));
+
+    let schemas = Arc::new(schemas);
+    let mut parsed = spawn_parse_workers(files_to_cache, schemas.clone(), workers);
+    let mut batch: Vec<(BookFromDb, usize)> = Vec::with_capacity(SCAN_BATCH_SIZE);
+
+    let mut processed = 0;
+
+    loop {
+        let next = select! {
+            next = parsed.recv() => next,
+            Some(ScanCommand::Cancel) = commands.recv() => {
+                // Dropping receiver stops parse workers, parsed but not written files are discarded
+                drop(parsed);
+                progress.errors = err.sub_errors.len();
+                on_progress(progress.update(ScanPhase::Cancelled, processed, files_total));
+                return Ok(());
+            }
+        };
+        let finished = next.is_none();
+
+        match next {
+            Some((_, schema_ind, Ok(book))) => batch.push((book, schema_ind)),
+            Some((path, _, Err(e))) => {
+                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                err.sub_errors.push(e.info(&file_name));
+                processed += 1;
+            }
+            None => (),
+        }
+
+        if batch.len() >= SCAN_BATCH_SIZE || (finished && !batch.is_empty()) {
+            if let Err(e) = cache_files_batch(&batch, &schemas, &mut err).await {
+                err = err.sub(e);
+            }
+            processed += batch.len();
+
+            progress.current_path = batch.last().and_then(|b| b.0.path.clone());
+            progress.errors = err.sub_errors.len();
+            on_progress(progress.update(ScanPhase::Files, processed, files_total));
+
+            batch.clear();
+        }
+
+        if finished {
+            break;
+        }
+    }
+
+    let removed_files: Vec<&String> = cached
+        .files
+        .keys()
+        .filter(|p| !seen_files.contains(*p))
+        .collect();
+    let removed_folders: Vec<&String> = cached
+        .folders
+        .iter()
+        .filter(|p| !seen_folders.contains(*p))
+        .collect();
+
+    let removed_total = removed_files.len() + removed_folders.len();
+    if removed_total > 0 {
+        progress.current_path = None;
+        on_progress(progress.update(ScanPhase::Cleanup, 0, removed_total));
+
+        if let Err(e) = remove_rows_batch(&removed_files, &removed_folders, &mut err).await {
+            err = err.sub(e);
+        }
+    }
+
+    progress.current_path = None;
+    progress.errors = err.sub_errors.len();
+    on_progress(progress.update(ScanPhase::Done, files_total, files_total));
+
+    match err.sub_errors.len() {
+        0 => Ok(()),
+        _ => Err(err),
+    }
+}
+
+type ParsedFile = (PathBuf, usize, Result<BookFromDb, ErrorFromRust>);
+
+// Workers take files from shared queue until it's empty. Channel is bounded,
+// so parsing waits for writer instead of keeping whole vault in memory.
+fn spawn_parse_workers(
+    files: Vec<(PathBuf, usize)>,
+    schemas: Arc<Vec<Schema>>,
+    workers: usize,
+) -> mpsc::Receiver<ParsedFile> {
+    let (tx, rx) = mpsc::channel::<ParsedFile>(SCAN_BATCH_SIZE * 2);
+    let queue = Arc::new(Mutex::new(files.into_iter()));
+
+    for _ in 0..workers.max(1) {
+        let queue = queue.clone();
+        let schemas = schemas.clone();
+        let tx = tx.clone();
+
+        task::spawn_blocking(move || loop {
+            let next = queue.lock().unwrap().next();
+            let (path, schema_ind) = match next {
+                Some(v) => v,
+                None => break,
+            };
+
+            let res = read_file_with_schema(
+                &path.to_string_lossy(),
+                FileReadMode::FullFile,
+                schemas[schema_ind].clone(),
+            )
+            .map(|r| r.book);
+
+            // Receiver is dropped only when writer is gone, there is no one to parse for
+            if tx.blocking_send((path, schema_ind, res)).is_err() {
+                break;
+            }
+        });
+    }
+
+    rx
+}
+
+// Errors of single files go to err, returned error means whole batch failed
+async fn cache_files_batch(
+    batch: &[(BookFromDb, usize)],
+    schemas: &[Schema],
+    err: &mut ErrorFromRust,
+) -> Result<(), ErrorFromRust> {
+    let mut db = get_write_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let mut tx = db.begin().await.map_err(tx_err)?;
+    let mut written: Vec<&(BookFromDb, usize)> = Vec::new();
+
+    for item in batch {
+        let (book, schema_ind) = item;
+        match write_file_with_savepoint(&mut tx, book, &schemas[*schema_ind]).await {
+            Ok(_) => written.push(item),
+            Err(e) => {
+                let file_name = book
+                    .path
+                    .as_ref()
+                    .and_then(|p| Path::new(p).file_name())
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                err.sub_errors.push(e.info(&file_name));
             }
         }
     }
+
+    tx.commit().await.map_err(tx_err)?;
+
+    for (book, schema_ind) in written {
+        quick_open_update(book, &schemas[*schema_ind]);
+    }
+    Ok(())
+}
+
+async fn write_file_with_savepoint(
+    db: &mut SqliteConnection,
+    file: &BookFromDb,
+    files_schema: &Schema,
+) -> Result<(), ErrorFromRust> {
+    // Inside transaction begin() creates a savepoint
+    let mut sp = db.begin().await.map_err(tx_err)?;
+
+    match write_file(&mut sp, file, files_schema).await {
+        Ok(_) => sp.commit().await.map_err(tx_err),
+        Err(e) => {
+            let _ = sp.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+async fn cache_folders_batch(
+    batch: &[(PathBuf, usize)],
+    schemas: &[Schema],
+    err: &mut ErrorFromRust,
+) -> Result<(), ErrorFromRust> {
+    let mut db = get_write_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let mut tx = db.begin().await.map_err(tx_err)?;
+
+    for (path, schema_ind) in batch {
+        if let Err(e) = write_folder(&mut tx, path, &schemas[*schema_ind]).await {
+            err.sub_errors.push(e);
+        }
+    }
+
+    tx.commit().await.map_err(tx_err)
+}
+
+async fn remove_rows_batch(
+    files: &[&String],
+    folders: &[&String],
+    err: &mut ErrorFromRust,
+) -> Result<(), ErrorFromRust> {
+    let mut db = get_write_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    let mut tx = db.begin().await.map_err(tx_err)?;
+    let mut removed: Vec<&String> = Vec::new();
+
+    for path in files {
+        match delete_file_row(&mut tx, path).await {
+            Ok(_) => removed.push(path),
+            Err(e) => err.sub_errors.push(e),
+        }
+    }
+
+    for path in folders {
+        if let Err(e) = delete_folder_row(&mut tx, path).await {
+            err.sub_errors.push(e);
+        }
+    }
+
+    tx.commit().await.map_err(tx_err)?;
+
+    for path in removed {
+        quick_open_remove(path);
+    }
+    Ok(())
+}
+
+async fn delete_folder_row(db: &mut SqliteConnection, path: &str) -> Result<(), ErrorFromRust> {
+    let files_schema = get_schema_cached_safe(path).await?;
+    let table_names = get_table_names(db, &files_schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
+
+    sqlx::query(&format!(
+        "DELETE FROM {} WHERE path=?1",
+        table_names.folders_table
+    ))
+    .bind(path)
+    .execute(&mut *db)
+    .await
+    .map_err(|e| ErrorFromRust::new("Error when removing folder from cache").raw(e))?;
+
     Ok(())
 }
 
 pub async fn cache_folder(path: &Path) -> Result<(), ErrorFromRust> {
-    let mut db = get_db_conn().lock().await;
+    let files_schema = get_schema_cached_safe(&path.to_string_lossy()).await?;
 
+    let mut db = get_write_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
+
+    write_folder(&mut db, path, &files_schema).await
+}
+
+async fn write_folder(
+    db: &mut SqliteConnection,
+    path: &Path,
+    files_schema: &Schema,
+) -> Result<(), ErrorFromRust> {
     let folder_name = match path.file_name() {
         Some(s) => s.to_string_lossy().to_string(),
         // None is root path(technically it's also "some/folder/" but I assume this will never happen)
         None => "/".to_string(),
     };
 
-    let files_schema = get_schema_cached_safe(&path.to_string_lossy().to_string()).await?;
-    let table_names = get_table_names(files_schema.internal_name);
+    let table_names = get_table_names(db, files_schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
 
     sqlx::query(
        &format!(
@@ -268,37 +913,107 @@ pub async fn cache_folder(path: &Path) -> Result<(), ErrorFromRust> {
 }
 
 pub async fn remove_folder_from_cache(path: &Path) -> Result<(), ErrorFromRust> {
-    let mut db = get_db_conn().lock().await;
+    let mut db = get_write_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
 
     let files_schema = get_schema_cached_safe(&path.to_string_lossy().to_string()).await?;
-    let table_names = get_table_names(files_schema.internal_name);
+    let table_names = get_table_names(&mut db, &files_schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
 
-    sqlx::query(&format!(
-        "DELETE FROM {} WHERE path LIKE concat(?1, '%')",
-        table_names.folders_table
-    ))
-    .bind(path.to_string_lossy().to_string())
-    .execute(&mut *db)
-    .await
-    .map_err(|e| ErrorFromRust::new("Error when removing folder from cache").raw(e))?;
+    delete_under_path(&mut db, &table_names.folders_table, &path.to_string_lossy())
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when removing folder from cache").raw(e))?;
 
     Ok(())
 }
 
 pub async fn remove_files_in_folder_rom_cache(path: &Path) -> Result<(), ErrorFromRust> {
-    let mut db = get_db_conn().lock().await;
+    let mut db = get_write_conn()
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when connecting to cache").raw(e))?;
 
     let files_schema = get_schema_cached_safe(&path.to_string_lossy().to_string()).await?;
-    let table_names = get_table_names(files_schema.internal_name);
+    let table_names = get_table_names(&mut db, &files_schema)
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when getting table names").raw(e))?;
 
-    sqlx::query(&format!(
-        "DELETE FROM {} WHERE path LIKE concat(?1, '%')",
-        table_names.files_table
-    ))
-    .bind(path.to_string_lossy().to_string())
-    .execute(&mut *db)
-    .await
-    .map_err(|e| ErrorFromRust::new("Error when removing folder from cache").raw(e))?;
+    delete_under_path(&mut db, &table_names.files_table, &path.to_string_lossy())
+        .await
+        .map_err(|e| ErrorFromRust::new("Error when removing folder from cache").raw(e))?;
 
+    quick_open_remove_under(&path.to_string_lossy());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn select_under_path_skips_sibling_with_same_prefix() {
+        let mut db = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE folders (path TEXT)")
+            .execute(&mut db)
+            .await
+            .unwrap();
+
+        let books = format!("{0}v{0}Books", MAIN_SEPARATOR);
+        let paths = [
+            books.clone(),
+            format!("{}{}Dune", books, MAIN_SEPARATOR),
+            format!("{} 2", books),
+            format!("{} 2{}Dune", books, MAIN_SEPARATOR),
+        ];
+        for p in paths.iter() {
+            sqlx::query("INSERT INTO folders (path) VALUES (?1)")
+                .bind(p)
+                .execute(&mut db)
+                .await
+                .unwrap();
+        }
+
+        let rows = select_under_path(&mut db, "folders", "path", &books)
+            .await
+            .unwrap();
+        let mut found: Vec<String> = rows.iter().map(|r| r.get("path")).collect();
+        found.sort();
+
+        assert_eq!(found, vec![paths[0].clone(), paths[1].clone()]);
+    }
+
+    #[tokio::test]
+    async fn delete_under_path_keeps_sibling_with_same_prefix() {
+        let mut db = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE files (path TEXT)")
+            .execute(&mut db)
+            .await
+            .unwrap();
+
+        let books = format!("{0}v{0}Books", MAIN_SEPARATOR);
+        let mut kept = vec![
+            format!("{} 2", books),
+            format!("{} 2{}Dune.md", books, MAIN_SEPARATOR),
+            format!("{0}v{0}Books_x{0}Dune.md", MAIN_SEPARATOR),
+        ];
+        let removed = [books.clone(), format!("{}{}Dune.md", books, MAIN_SEPARATOR)];
+        for p in kept.iter().chain(removed.iter()) {
+            sqlx::query("INSERT INTO files (path) VALUES (?1)")
+                .bind(p)
+                .execute(&mut db)
+                .await
+                .unwrap();
+        }
+
+        delete_under_path(&mut db, "files", &books).await.unwrap();
+
+        let rows = sqlx::query("SELECT path FROM files ORDER BY path")
+            .fetch_all(&mut db)
+            .await
+            .unwrap();
+        let left: Vec<String> = rows.iter().map(|r| r.get("path")).collect();
+        kept.sort();
+        assert_eq!(left, kept);
+    }
+}
@@ -2,20 +2,24 @@ use chrono::offset::Utc;
 use chrono::DateTime;
 
 use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader};
+use std::fs;
+use std::io::{self, BufRead};
 
 use crate::cache::query::BookFromDb;
-use crate::schema::operations::get_schema_cached_safe;
+use crate::schema::expression::fill_computed_values;
+use crate::schema::operations::{get_schema_cached, get_schema_cached_safe};
 use crate::schema::types::{AttrKey, AttrValue, DateRead, Schema};
 use crate::utils::errorhandling::{ErrorActionCode, ErrorFromRust};
+use crate::utils::hash::stable_hash;
 
 pub enum FileReadMode {
+    // Cache needs bodies for full text search, so nothing reads only metadata at the moment
+    #[allow(dead_code)]
     OnlyMeta,
     FullFile,
 }
 
-fn get_file_modified_time(path_str: &str) -> Result<String, String> {
+pub fn get_file_modified_time(path_str: &str) -> Result<String, String> {
     match fs::metadata(path_str) {
         Ok(meta) => match meta.modified() {
             Ok(tt) => {
@@ -39,6 +43,17 @@ pub struct BookReadResult {
 pub async fn read_file_by_path(
     path_str: &str,
     read_mode: FileReadMode,
+) -> Result<BookReadResult, ErrorFromRust> {
+    let files_schema = get_schema_cached_safe(path_str).await?;
+
+    read_file_with_schema(path_str, read_mode, files_schema)
+}
+
+// For callers that already resolved schema, e.g. once per folder during scan
+pub fn read_file_with_schema(
+    path_str: &str,
+    read_mode: FileReadMode,
+    files_schema: Schema,
 ) -> Result<BookReadResult, ErrorFromRust> {
     let file_modified = match get_file_modified_time(path_str) {
         Ok(v) => v,
@@ -49,11 +64,20 @@ pub async fn read_file_by_path(
         }
     };
 
-    let files_schema = get_schema_cached_safe(path_str).await?;
-
     let p = path_str.to_string();
 
-    match read_file(&path_str, &read_mode) {
+    // Whole file is read once, so hash stored in cache matches what was parsed
+    let bytes = match fs::read(path_str) {
+        Ok(v) => v,
+        Err(e) => {
+            return Err(ErrorFromRust::new("Error reading file")
+                .raw(e)
+                .action_c(ErrorActionCode::FileReadRetry, "Retry"))
+        }
+    };
+    let hash = Some(stable_hash(&bytes));
+
+    match split_front_matter(&bytes, &read_mode) {
         Ok(fmc) => {
             let parsed_meta: Result<HashMap<String, serde_yml::Value>, serde_yml::Error> =
                 serde_yml::from_str(&fmc.0);
@@ -125,6 +149,8 @@ pub async fn read_file_by_path(
                         }
                     }
 
+                    fill_computed_values(&files_schema.items, &mut hm);
+
                     Ok(BookReadResult {
                         book: BookFromDb {
                             path: Some(p),
@@ -133,8 +159,8 @@ pub async fn read_file_by_path(
                                 FileReadMode::FullFile => Some(fmc.1),
                             },
                             modified: Some(file_modified),
+                            hash,
                             attrs: hm,
-                            ..Default::default()
                         },
                         parsing_error: None,
                         schema: files_schema,
@@ -148,6 +174,7 @@ pub async fn read_file_by_path(
                             FileReadMode::OnlyMeta => None,
                             FileReadMode::FullFile => Some(fmc.1),
                         },
+                        hash,
                         ..Default::default()
                     },
                     parsing_error: Some(
@@ -173,7 +200,7 @@ pub struct BookSaveResult {
     pub modified: String,
 }
 
-pub fn save_file(book: BookFromDb, forced: bool) -> Result<BookSaveResult, ErrorFromRust> {
+pub async fn save_file(book: BookFromDb, forced: bool) -> Result<BookSaveResult, ErrorFromRust> {
     let path = match book.path {
         Some(v) => v,
         None => {
@@ -209,7 +236,18 @@ pub fn save_file(book: BookFromDb, forced: bool) -> Result<BookSaveResult, Error
 
     let markdown = book.markdown.unwrap_or("".to_string());
 
-    let yaml = serde_yml::to_string(&book.attrs).map_err(|e| {
+    let mut attrs = book.attrs;
+
+    // Computed values are derived on read, they never go to file
+    if let Some(schema) = get_schema_cached(&path).await {
+        for item in schema.items {
+            if let AttrKey::Computed(_) = item.value {
+                attrs.remove(&item.name);
+            }
+        }
+    }
+
+    let yaml = serde_yml::to_string(&attrs).map_err(|e| {
         ErrorFromRust::new("Error serializing book metadata")
             .info("File was not saved")
             .raw(e)
@@ -237,17 +275,14 @@ pub fn save_file(book: BookFromDb, forced: bool) -> Result<BookSaveResult, Error
     }
 }
 
-pub fn read_file(file_path: &str, read_mode: &FileReadMode) -> io::Result<(String, String)> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-
+pub fn split_front_matter(file: &[u8], read_mode: &FileReadMode) -> io::Result<(String, String)> {
     let mut front_matter = String::new();
     let mut content = String::new();
 
     let mut inside_front_matter = false;
     let mut frontmatter_found = false;
 
-    for line in reader.lines() {
+    for line in file.lines() {
         // TODO: handle cases with no frontmatter better
         let line = line?;
 
@@ -283,3 +318,52 @@ pub fn read_file(file_path: &str, read_mode: &FileReadMode) -> io::Result<(Strin
 
     Ok((front_matter, content))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::types::SchemaItem;
+
+    #[test]
+    fn hash_is_taken_from_parsed_content() {
+        let path = std::env::temp_dir().join(format!("files_hash_{}.md", std::process::id()));
+        let content = "---\ntitle: Dune\n---\nSpice\n";
+        fs::write(&path, content).unwrap();
+
+        let schema = Schema {
+            name: "Books".to_string(),
+            version: "1.0".to_string(),
+            icon: None,
+            items: vec![SchemaItem {
+                name: "title".to_string(),
+                value: AttrKey::Text(None),
+            }],
+            internal_path: "".to_string(),
+            internal_name: "".to_string(),
+        };
+
+        let res =
+            read_file_with_schema(&path.to_string_lossy(), FileReadMode::FullFile, schema).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(res.book.hash, Some(stable_hash(content.as_bytes())));
+        assert_eq!(res.book.markdown.as_deref(), Some("Spice\n"));
+        assert!(matches!(
+            res.book.attrs.get("title"),
+            Some(AttrValue::Text(t)) if t == "Dune"
+        ));
+    }
+
+    #[test]
+    fn front_matter_is_split_from_body() {
+        let file = b"ignored\n---\na: 1\n---\nbody\n";
+
+        let (meta, body) = split_front_matter(file, &FileReadMode::FullFile).unwrap();
+        assert_eq!(meta, "a: 1\n");
+        assert_eq!(body, "body\n");
+
+        let (meta, body) = split_front_matter(file, &FileReadMode::OnlyMeta).unwrap();
+        assert_eq!(meta, "a: 1\n");
+        assert_eq!(body, "");
+    }
+}
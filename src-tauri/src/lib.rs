@@ -4,24 +4,47 @@ mod schema;
 mod utils;
 mod watcher;
 
-use std::{path::PathBuf, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use cache::{
-    dbconn::db_setup,
-    query::{get_all_folders, get_all_tags, get_files_by_path, BookFromDb, BookListGetResult},
+    aggregate::{aggregate_files_in_path, AggregateOptions, AggregateResult},
+    dbconn::{db_setup, delete_vault_cache},
+    diagnostics::{set_query_diagnostics, take_query_diagnostics, QueryDiagnostics},
+    folders::{get_folder_tree, FolderNode},
+    query::{
+        get_all_folders, get_files_by_path, query_files, BookFromDb, BookListGetResult,
+        FilesQueryOptions, FilesQueryResult,
+    },
+    query_parser::{get_query_suggestions, parse_query_for_path, ParsedQuery, QuerySuggestion},
+    quick_open::{quick_open, QuickOpenHit},
     tables::create_db_tables_for_all_schemas,
-    write::cache_files_and_folders,
+    tags::{get_all_tags, get_files_by_tag, get_tag_details, get_tag_tree, TagDetails, TagNode},
+    values::{get_field_values_for_path, FieldValueCount},
+    verify::{verify_cache, CacheVerifyReport},
+    write::{
+        cache_files_and_folders, default_parse_workers, send_scan_command, ScanCommand,
+        ScanProgress,
+    },
 };
 use files::{read_file_by_path, save_file, FileReadMode};
 use schema::{
-    defaults::get_default_schemas,
+    defaults::{export_schema_as_template, get_default_schemas, DefaultSchema, DefaultSchemaList},
+    json_schema::get_json_schema,
     operations::{
         get_all_schemas_cached, load_schema, load_schemas_from_disk, save_schema, SchemaLoadList,
     },
+    package::{
+        export_schema_package, import_schema_package, read_package_manifest, validate_folder_name,
+        PackageImportResult, PackageManifest,
+    },
+    types::Schema,
+    views::{delete_view, evaluate_view, get_views, save_view, SavedView, ViewEvaluation},
 };
-use schema::{defaults::DefaultSchema, types::Schema};
 use serde::{Deserialize, Serialize};
-use tauri::AppHandle;
+use tauri::{AppHandle, Emitter};
 use tokio::task;
 use utils::{
     errorhandling::{send_err_to_frontend, ErrorFromRust},
@@ -32,6 +55,10 @@ use watcher::{
     watcher_process::{init_watcher, subscribe_to_events, watch_path},
 };
 
+// Used by examples/scan_benchmark.rs
+#[cfg(feature = "benchmark")]
+pub use cache::benchmark::run_scan_benchmark;
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type", content = "value")]
 enum ResultStringJson {
@@ -41,12 +68,6 @@ enum ResultStringJson {
 
 #[tauri::command]
 async fn c_init_once(app: AppHandle) -> Result<bool, ErrorFromRust> {
-    db_setup().await.map_err(|e| {
-        ErrorFromRust::new("Error on db setup")
-            .info("This is a critical error. Report bug")
-            .raw(e)
-    })?;
-
     init_watcher().await.map_err(|e| {
         ErrorFromRust::new("Error on watcher init.")
             .info("This is a critical error. Report bug")
@@ -74,9 +95,9 @@ async fn c_init_once(app: AppHandle) -> Result<bool, ErrorFromRust> {
     Ok(true)
 }
 
-#[tauri::command]
-async fn c_prepare_cache(app: AppHandle) -> Result<bool, ErrorFromRust> {
-    let rp = get_root_path()?;
+async fn prepare_cache(app: &AppHandle, rp: &str) -> Result<(), ErrorFromRust> {
+    // Each vault has its own cache db, this switches to it if root path changed
+    db_setup(rp).await?;
 
     create_db_tables_for_all_schemas().await.map_err(|e| {
         ErrorFromRust::new("Error when creating tables in cache db")
@@ -84,17 +105,78 @@ async fn c_prepare_cache(app: AppHandle) -> Result<bool, ErrorFromRust> {
             .raw(e)
     })?;
 
-    match cache_files_and_folders(&rp).await {
+    let on_progress = |p: &ScanProgress| {
+        let _ = app.emit("cache_progress", p);
+    };
+
+    match cache_files_and_folders(rp, default_parse_workers(), on_progress).await {
         Err(e) => {
             // We don't return error here because user can have a few problematic files, which is ok
-            send_err_to_frontend(&app, &e);
+            send_err_to_frontend(app, &e);
         }
         Ok(_) => (),
     }
 
+    Ok(())
+}
+
+#[tauri::command]
+async fn c_prepare_cache(app: AppHandle) -> Result<bool, ErrorFromRust> {
+    let rp = get_root_path()?;
+    prepare_cache(&app, &rp).await?;
     return Ok(true);
 }
 
+// Returns false when there was no scan running
+#[tauri::command]
+fn c_cancel_scan(_: AppHandle) -> bool {
+    send_scan_command(ScanCommand::Cancel) > 0
+}
+
+// Deletes cache and indexes current vault from scratch
+#[tauri::command]
+async fn c_rebuild_cache(app: AppHandle) -> Result<bool, ErrorFromRust> {
+    let rp = get_root_path()?;
+    delete_vault_cache(&rp).await?;
+    prepare_cache(&app, &rp).await?;
+    Ok(true)
+}
+
+// Compares cache with notes on disk, with fix set also repairs what was found
+#[tauri::command]
+async fn c_verify_cache(
+    _: AppHandle,
+    fix: Option<bool>,
+) -> Result<CacheVerifyReport, ErrorFromRust> {
+    let rp = get_root_path()?;
+    verify_cache(Path::new(&rp), fix.unwrap_or(false)).await
+}
+
+// Debug only. While enabled, queries are recorded with their plan and time: files, counts,
+// aggregates and views, field values, tags, folder tree, cache verification and repair.
+// Writes of scan and watcher are not recorded, thousands of them would push everything else out.
+#[tauri::command]
+fn c_set_query_diagnostics(_: AppHandle, enabled: bool) -> bool {
+    set_query_diagnostics(enabled);
+    enabled
+}
+
+#[tauri::command]
+fn c_get_query_diagnostics(_: AppHandle) -> Vec<QueryDiagnostics> {
+    take_query_diagnostics()
+}
+
+// Deletes cache of any vault, current vault is used when root_path is not set
+#[tauri::command]
+async fn c_delete_cache(_: AppHandle, root_path: Option<String>) -> Result<bool, ErrorFromRust> {
+    let rp = match root_path {
+        Some(p) => p,
+        None => get_root_path()?,
+    };
+    delete_vault_cache(&rp).await?;
+    Ok(true)
+}
+
 #[tauri::command]
 async fn c_watch_path(_: AppHandle) -> Result<bool, ErrorFromRust> {
     let rp = get_root_path()?;
@@ -109,15 +191,98 @@ async fn c_watch_path(_: AppHandle) -> Result<bool, ErrorFromRust> {
 }
 
 #[tauri::command]
-async fn c_get_files_path(_: AppHandle, path: String) -> Result<BookListGetResult, ErrorFromRust> {
-    get_files_by_path(path).await
+async fn c_get_files_path(
+    _: AppHandle,
+    path: String,
+    include_subfolders: Option<bool>,
+) -> Result<BookListGetResult, ErrorFromRust> {
+    get_files_by_path(path, include_subfolders.unwrap_or(true)).await
+}
+
+// Filtering, sorting and pagination all run in cache db
+#[tauri::command]
+async fn c_query_files(
+    _: AppHandle,
+    path: String,
+    options: FilesQueryOptions,
+) -> Result<FilesQueryResult, ErrorFromRust> {
+    query_files(path, options).await
+}
+
+#[tauri::command]
+async fn c_aggregate_files(
+    _: AppHandle,
+    path: String,
+    options: AggregateOptions,
+) -> Result<AggregateResult, ErrorFromRust> {
+    aggregate_files_in_path(path, options).await
+}
+
+#[tauri::command]
+async fn c_parse_query(
+    _: AppHandle,
+    path: String,
+    text: String,
+) -> Result<ParsedQuery, ErrorFromRust> {
+    parse_query_for_path(&path, &text).await
+}
+
+// Cursor is position in text, as string index in frontend
+#[tauri::command]
+async fn c_query_suggestions(
+    _: AppHandle,
+    path: String,
+    text: String,
+    cursor: usize,
+) -> Result<Vec<QuerySuggestion>, ErrorFromRust> {
+    get_query_suggestions(&path, &text, cursor).await
+}
+
+// Typo tolerant search over titles, authors and file names of all schemas
+#[tauri::command]
+async fn c_quick_open(
+    _: AppHandle,
+    query: String,
+    limit: Option<usize>,
+) -> Result<Vec<QuickOpenHit>, ErrorFromRust> {
+    quick_open(&query, limit).await
 }
 
 #[tauri::command]
 async fn c_get_all_tags(_: AppHandle) -> Result<Vec<String>, ErrorFromRust> {
-    get_all_tags()
-        .await
-        .map_err(|e| ErrorFromRust::new("Error when getting all tags").raw(e))
+    get_all_tags().await
+}
+
+#[tauri::command]
+async fn c_get_tag_tree(_: AppHandle) -> Result<Vec<TagNode>, ErrorFromRust> {
+    get_tag_tree().await
+}
+
+#[tauri::command]
+async fn c_get_tag(_: AppHandle, tag: String) -> Result<TagDetails, ErrorFromRust> {
+    get_tag_details(&tag).await
+}
+
+// Notes from all schemas, grouped by schema
+#[tauri::command]
+async fn c_get_files_by_tag(
+    _: AppHandle,
+    tag: String,
+    include_children: Option<bool>,
+) -> Result<Vec<BookListGetResult>, ErrorFromRust> {
+    get_files_by_tag(&tag, include_children.unwrap_or(true)).await
+}
+
+// Distinct values of field with number of notes using them, most used first
+#[tauri::command]
+async fn c_get_field_values(
+    _: AppHandle,
+    path: String,
+    field: String,
+    prefix: Option<String>,
+    limit: Option<u32>,
+) -> Result<Vec<FieldValueCount>, ErrorFromRust> {
+    get_field_values_for_path(&path, &field, prefix.as_deref(), limit).await
 }
 
 #[tauri::command]
@@ -125,6 +290,12 @@ async fn c_get_all_folders(_: AppHandle, schema_path: String) -> Result<Vec<Stri
     get_all_folders(&schema_path).await
 }
 
+// Folders of all schemas with note counts, kept up to date by "folder_tree_changed" event
+#[tauri::command]
+async fn c_get_folder_tree(_: AppHandle) -> Result<Vec<FolderNode>, ErrorFromRust> {
+    get_folder_tree().await
+}
+
 #[tauri::command]
 async fn c_read_file_by_path(
     _: AppHandle,
@@ -160,17 +331,101 @@ async fn c_save_schema(
 }
 
 #[tauri::command]
-fn c_get_default_schemas(_: AppHandle) -> Vec<DefaultSchema> {
+async fn c_export_schema_package(
+    _: AppHandle,
+    schema_path: String,
+    target_path: String,
+    example_paths: Vec<String>,
+) -> Result<PackageManifest, ErrorFromRust> {
+    export_schema_package(&schema_path, &target_path, example_paths).await
+}
+
+#[tauri::command]
+fn c_read_schema_package(
+    _: AppHandle,
+    package_path: String,
+) -> Result<PackageManifest, ErrorFromRust> {
+    read_package_manifest(&package_path)
+}
+
+#[tauri::command]
+async fn c_import_schema_package(
+    _: AppHandle,
+    package_path: String,
+    folder_name: String,
+    overwrite: bool,
+) -> Result<PackageImportResult, ErrorFromRust> {
+    validate_folder_name(&folder_name)?;
+    let path = PathBuf::from(get_root_path()?).join(folder_name);
+    import_schema_package(&package_path, &path, overwrite).await
+}
+
+#[tauri::command]
+async fn c_get_json_schema(
+    _: AppHandle,
+    schema_path: String,
+    target_path: Option<String>,
+) -> Result<serde_json::Value, ErrorFromRust> {
+    get_json_schema(&schema_path, target_path).await
+}
+
+#[tauri::command]
+fn c_get_default_schemas(_: AppHandle) -> Result<DefaultSchemaList, ErrorFromRust> {
     get_default_schemas()
 }
 
 #[tauri::command]
-fn c_save_file(
+async fn c_export_schema_template(
+    _: AppHandle,
+    schema_path: String,
+    name: String,
+    description: String,
+) -> Result<DefaultSchema, ErrorFromRust> {
+    export_schema_as_template(&schema_path, name, description).await
+}
+
+#[tauri::command]
+async fn c_get_views(_: AppHandle, schema_path: String) -> Result<Vec<SavedView>, ErrorFromRust> {
+    get_views(&schema_path).await
+}
+
+#[tauri::command]
+async fn c_save_view(
+    _: AppHandle,
+    schema_path: String,
+    view: SavedView,
+) -> Result<SavedView, ErrorFromRust> {
+    save_view(&schema_path, view).await
+}
+
+#[tauri::command]
+async fn c_delete_view(
+    _: AppHandle,
+    schema_path: String,
+    id: String,
+) -> Result<bool, ErrorFromRust> {
+    delete_view(&schema_path, &id).await
+}
+
+#[tauri::command]
+async fn c_evaluate_view(
+    _: AppHandle,
+    schema_path: String,
+    id: String,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    cursor: Option<String>,
+) -> Result<ViewEvaluation, ErrorFromRust> {
+    evaluate_view(&schema_path, &id, limit, offset, cursor).await
+}
+
+#[tauri::command]
+async fn c_save_file(
     _: AppHandle,
     book: BookFromDb,
     forced: bool,
 ) -> Result<files::BookSaveResult, ErrorFromRust> {
-    save_file(book, forced)
+    save_file(book, forced).await
 }
 
 pub fn run() {
@@ -185,11 +440,36 @@ pub fn run() {
             c_load_schema,
             c_save_schema,
             c_get_default_schemas,
+            c_export_schema_template,
+            c_export_schema_package,
+            c_read_schema_package,
+            c_import_schema_package,
+            c_get_json_schema,
+            c_get_views,
+            c_save_view,
+            c_delete_view,
+            c_evaluate_view,
             c_prepare_cache,
+            c_rebuild_cache,
+            c_delete_cache,
+            c_verify_cache,
+            c_set_query_diagnostics,
+            c_get_query_diagnostics,
+            c_cancel_scan,
             c_watch_path,
             c_get_files_path,
+            c_query_files,
+            c_aggregate_files,
+            c_parse_query,
+            c_query_suggestions,
+            c_quick_open,
             c_get_all_tags,
+            c_get_tag_tree,
+            c_get_tag,
+            c_get_files_by_tag,
+            c_get_field_values,
             c_get_all_folders,
+            c_get_folder_tree,
             c_read_file_by_path,
             c_save_file,
             c_get_schemas
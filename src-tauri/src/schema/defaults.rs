@@ -1,146 +1,246 @@
+use std::fs::{create_dir_all, read_dir, read_to_string, write};
+use std::path::{Path, PathBuf};
+
 use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
 
-use crate::schema::types::{EmptySettings, NumberSettings, TextSettings};
-
-use super::types::{
-    AttrKey, InputSize, NumberStyle, SchemaItem, SchemaItems, TextFont, TextTheme, TextWeight,
-};
-
-pub fn default_book_schema() -> SchemaItems {
-    vec![
-        SchemaItem {
-            name: "title".to_owned(),
-            value: AttrKey::Text(Some(TextSettings {
-                size: Some(InputSize::L),
-                font: Some(TextFont::Serif),
-                is_multiline: Some(true),
-                theme: Some(TextTheme::Hidden),
-                ..TextSettings::default()
-            })),
-        },
-        SchemaItem {
-            name: "author".to_owned(),
-            value: AttrKey::Text(Some(TextSettings {
-                size: Some(InputSize::M),
-                weight: Some(TextWeight::Bold),
-                theme: Some(TextTheme::Hidden),
-                ..TextSettings::default()
-            })),
-        },
-        SchemaItem {
-            name: "year".to_owned(),
-            value: AttrKey::Number(Some(NumberSettings {
-                size: Some(InputSize::S),
-                min: Some(0.0),
-                ..NumberSettings::default()
-            })),
-        },
-        SchemaItem {
-            name: "myRating".to_owned(),
-            value: AttrKey::Number(Some(NumberSettings {
-                min: Some(0.0),
-                max: Some(5.0),
-                decimal_places: Some(1),
-                style: Some(NumberStyle::Stars),
-                ..NumberSettings::default()
-            })),
-        },
-        SchemaItem {
-            name: "read".to_owned(),
-            value: AttrKey::DatesPairCollection(Some(EmptySettings {})),
-        },
-        SchemaItem {
-            name: "tags".to_owned(),
-            value: AttrKey::TextCollection(Some(EmptySettings {})),
-        },
-        SchemaItem {
-            name: "cover".to_owned(),
-            value: AttrKey::Image(Some(EmptySettings {})),
-        },
-        SchemaItem {
-            name: "ISBN13".to_owned(),
-            value: AttrKey::Number(Some(NumberSettings {
-                size: Some(InputSize::S),
-                min: Some(0.0),
-                ..NumberSettings::default()
-            })),
-        },
-    ]
-}
+use crate::utils::{errorhandling::ErrorFromRust, global_app::get_app_data_dir};
 
-pub fn default_movie_schema() -> SchemaItems {
-    vec![
-        SchemaItem {
-            name: "title".to_owned(),
-            value: AttrKey::Text(Some(TextSettings {
-                size: Some(InputSize::L),
-                font: Some(TextFont::Serif),
-                is_multiline: Some(true),
-                theme: Some(TextTheme::Hidden),
-
-                ..TextSettings::default()
-            })),
-        },
-        SchemaItem {
-            name: "director".to_owned(),
-            value: AttrKey::Text(Some(TextSettings {
-                size: Some(InputSize::M),
-                weight: Some(TextWeight::Bold),
-                theme: Some(TextTheme::Hidden),
-                ..TextSettings::default()
-            })),
-        },
-        SchemaItem {
-            name: "premiere".to_owned(),
-            value: AttrKey::Date(Some(EmptySettings {})),
-        },
-        SchemaItem {
-            name: "myRating".to_owned(),
-            value: AttrKey::Number(Some(NumberSettings {
-                min: Some(0.0),
-                max: Some(5.0),
-                style: Some(NumberStyle::Slider),
-                ..NumberSettings::default()
-            })),
-        },
-        SchemaItem {
-            name: "watched".to_owned(),
-            value: AttrKey::DateCollection(Some(EmptySettings {})),
-        },
-        SchemaItem {
-            name: "tags".to_owned(),
-            value: AttrKey::TextCollection(Some(EmptySettings {})),
-        },
-        SchemaItem {
-            name: "poster".to_owned(),
-            value: AttrKey::Image(Some(EmptySettings {})),
-        },
-    ]
-}
+use super::operations::get_schema_cached_safe;
+use super::types::SchemaItems;
+
+// Templates shipped with the app. Order here is order in UI.
+const BUNDLED_TEMPLATES: [&str; 8] = [
+    include_str!("../../templates/empty.yaml"),
+    include_str!("../../templates/books.yaml"),
+    include_str!("../../templates/movies.yaml"),
+    include_str!("../../templates/tv_series.yaml"),
+    include_str!("../../templates/video_games.yaml"),
+    include_str!("../../templates/podcasts.yaml"),
+    include_str!("../../templates/board_games.yaml"),
+    include_str!("../../templates/articles.yaml"),
+];
 
+const USER_TEMPLATES_FOLDER: &str = "schema_templates";
+
+#[skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DefaultSchema {
     pub name: String,
     pub description: String,
+    pub icon: Option<String>,
     pub schema_items: SchemaItems,
 }
 
-pub fn get_default_schemas() -> Vec<DefaultSchema> {
-    vec![
-        DefaultSchema {
-            name: "Empty".to_owned(),
-            description: "Empty schema without any fields".to_owned(),
-            schema_items: vec![],
-        },
-        DefaultSchema {
-            name: "Books".to_owned(),
-            description: "Default schema for books, inspired by Goodreads".to_owned(),
-            schema_items: default_book_schema(),
-        },
-        DefaultSchema {
-            name: "Movies".to_owned(),
-            description: "Default schema for books, inspired by Letterboxd".to_owned(),
-            schema_items: default_movie_schema(),
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DefaultSchemaList {
+    pub templates: Vec<DefaultSchema>,
+    // Templates that could not be parsed are left out of the list and reported here
+    pub error: Option<ErrorFromRust>,
+}
+
+pub fn get_bundled_templates() -> Vec<Result<DefaultSchema, ErrorFromRust>> {
+    BUNDLED_TEMPLATES
+        .iter()
+        .enumerate()
+        .map(|(index, content)| {
+            serde_yml::from_str(content).map_err(|e| {
+                ErrorFromRust::new("Error parsing bundled template")
+                    .info(&format!("Template #{}", index))
+                    .raw(e)
+            })
+        })
+        .collect()
+}
+
+fn get_user_templates_dir() -> Result<PathBuf, ErrorFromRust> {
+    Ok(get_app_data_dir()?.join(USER_TEMPLATES_FOLDER))
+}
+
+fn parse_template_file(path: &PathBuf) -> Result<DefaultSchema, ErrorFromRust> {
+    let content = read_to_string(path).map_err(|e| {
+        ErrorFromRust::new("Error when reading template file")
+            .info(&path.to_string_lossy())
+            .raw(e)
+    })?;
+
+    serde_yml::from_str(&content).map_err(|e| {
+        ErrorFromRust::new("Error parsing template")
+            .info(&path.to_string_lossy())
+            .raw(e)
+    })
+}
+
+// Each broken user template is its own error, so one bad file does not hide the rest
+pub fn get_user_templates() -> Result<Vec<Result<DefaultSchema, ErrorFromRust>>, ErrorFromRust> {
+    let dir = get_user_templates_dir()?;
+
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let entries = read_dir(&dir).map_err(|e| {
+        ErrorFromRust::new("Unable to read user templates folder")
+            .info(&dir.to_string_lossy())
+            .raw(e)
+    })?;
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|ext| ext == "yaml" || ext == "yml")
+        })
+        .collect();
+    paths.sort();
+
+    Ok(paths.iter().map(parse_template_file).collect())
+}
+
+pub fn get_default_schemas() -> Result<DefaultSchemaList, ErrorFromRust> {
+    let mut templates: Vec<DefaultSchema> = Vec::new();
+    let mut errors: Vec<ErrorFromRust> = Vec::new();
+
+    for t in get_bundled_templates()
+        .into_iter()
+        .chain(get_user_templates()?)
+    {
+        match t {
+            Ok(v) => templates.push(v),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    Ok(DefaultSchemaList {
+        templates,
+        error: match errors.len() {
+            0 => None,
+            _ => Some(ErrorFromRust::new("Some templates could not be loaded").subs(errors)),
         },
-    ]
+    })
+}
+
+fn template_file_name(name: &str) -> String {
+    let slug: String = name
+        .trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+
+    match slug.is_empty() {
+        true => "template".to_string(),
+        false => slug,
+    }
+}
+
+// Different names can give the same file name, e.g. "Books!" and "Books?".
+// File is reused only when it holds template with the same name.
+fn template_file_path(dir: &Path, name: &str) -> PathBuf {
+    let slug = template_file_name(name);
+
+    let mut path = dir.join(format!("{}.yaml", slug));
+    let mut n = 2;
+    while path.exists() && !parse_template_file(&path).is_ok_and(|t| t.name == name) {
+        path = dir.join(format!("{}_{}.yaml", slug, n));
+        n += 1;
+    }
+
+    path
+}
+
+// Saves existing schema to user templates folder. Template with the same name is overwritten.
+pub async fn export_schema_as_template(
+    schema_path: &str,
+    name: String,
+    description: String,
+) -> Result<DefaultSchema, ErrorFromRust> {
+    let schema = get_schema_cached_safe(schema_path).await?;
+
+    let template = DefaultSchema {
+        name: name.clone(),
+        description,
+        icon: schema.icon,
+        schema_items: schema.items,
+    };
+
+    let serialized = serde_yml::to_string(&template)
+        .map_err(|e| ErrorFromRust::new("Error serializing template").raw(e))?;
+
+    let dir = get_user_templates_dir()?;
+
+    create_dir_all(&dir).map_err(|e| {
+        ErrorFromRust::new("Error creating directory")
+            .info("Could not create templates folder")
+            .raw(e)
+    })?;
+
+    write(template_file_path(&dir, &name), serialized).map_err(|e| {
+        ErrorFromRust::new("Error writing to disk")
+            .info("Template was not saved")
+            .raw(e)
+    })?;
+
+    Ok(template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::expression::validate_computed_items;
+    use crate::schema::types::{Schema, SCHEMA_VERSION};
+
+    #[test]
+    fn bundled_templates_are_valid_schemas() {
+        for (i, t) in get_bundled_templates().into_iter().enumerate() {
+            let template = t.unwrap_or_else(|e| panic!("template #{}: {:?}", i, e));
+
+            // Same as creating schema from template in UI
+            let schema = Schema {
+                name: template.name.clone(),
+                version: SCHEMA_VERSION.to_string(),
+                icon: template.icon,
+                items: template.schema_items,
+                internal_path: "".to_string(),
+                internal_name: "".to_string(),
+            };
+            let yaml = serde_yml::to_string(&schema).unwrap();
+            let schema: Schema = serde_yml::from_str(&yaml).unwrap();
+
+            assert!(
+                validate_computed_items(&schema).is_ok(),
+                "template {}",
+                template.name
+            );
+        }
+    }
+
+    #[test]
+    fn template_with_same_file_name_is_not_overwritten() {
+        let dir = std::env::temp_dir().join(format!("templates_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+
+        let save = |name: &str| {
+            let template = DefaultSchema {
+                name: name.to_string(),
+                description: "".to_string(),
+                icon: None,
+                schema_items: vec![],
+            };
+            let path = template_file_path(&dir, name);
+            write(&path, serde_yml::to_string(&template).unwrap()).unwrap();
+            path
+        };
+
+        let first = save("Books!");
+        let second = save("Books?");
+        let again = save("Books!");
+
+        assert_eq!(first, dir.join("books_.yaml"));
+        assert_eq!(second, dir.join("books__2.yaml"));
+        assert_eq!(again, first);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
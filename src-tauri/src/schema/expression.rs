@@ -0,0 +1,915 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Datelike, NaiveDate};
+
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::types::{AttrKey, AttrValue, ComputedType, Schema, SchemaItem};
+
+// Small expression language used by computed schema fields.
+// Examples:
+//   len(read)
+//   max(read.finished)
+//   pages / days(last(read.started), last(read.finished))
+//   if(len(read) > len(read.finished), "reading", if(len(read) > 0, "read", "to read"))
+
+// Parser and evaluator are recursive, deeper expressions are rejected instead of overflowing stack
+const MAX_NESTING: usize = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExprValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Text(String),
+    List(Vec<ExprValue>),
+    Record(BTreeMap<String, ExprValue>),
+}
+
+#[derive(Clone, Debug)]
+pub struct ExprError {
+    pub message: String,
+    // Char offset in expression string
+    pub position: usize,
+}
+
+impl ExprError {
+    fn new(message: &str, position: usize) -> Self {
+        ExprError {
+            message: message.to_string(),
+            position,
+        }
+    }
+
+    pub fn to_rust_error(&self, field: &str, expression: &str) -> ErrorFromRust {
+        ErrorFromRust::new(&format!(
+            "Invalid expression in computed field \"{}\"",
+            field
+        ))
+        .info(&format!(
+            "{}\n{}\n{}^",
+            self.message,
+            expression,
+            " ".repeat(self.position)
+        ))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+    Dot,
+}
+
+const OPERATORS: [&str; 15] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "!", "=",
+];
+
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, ExprError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+
+        match c {
+            '(' => tokens.push((Token::LParen, start)),
+            ')' => tokens.push((Token::RParen, start)),
+            ',' => tokens.push((Token::Comma, start)),
+            '.' if !chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                tokens.push((Token::Dot, start))
+            }
+            '"' | '\'' => {
+                let quote = c;
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err(ExprError::new("Unterminated string", start)),
+                        Some('\\') => {
+                            if let Some(escaped) = chars.get(i + 1) {
+                                s.push(*escaped);
+                            }
+                            i += 2;
+                        }
+                        Some(ch) if *ch == quote => break,
+                        Some(ch) => {
+                            s.push(*ch);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push((Token::Text(s), start));
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let s: String = chars[start..i].iter().collect();
+                let n = s
+                    .parse::<f64>()
+                    .map_err(|_| ExprError::new("Invalid number", start))?;
+                tokens.push((Token::Number(n), start));
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push((Token::Ident(chars[start..i].iter().collect()), start));
+                continue;
+            }
+            _ => {
+                let op = OPERATORS.iter().find(|op| {
+                    op.chars()
+                        .enumerate()
+                        .all(|(ind, oc)| chars.get(i + ind) == Some(&oc))
+                });
+                match op {
+                    Some(op) => {
+                        tokens.push((Token::Op(op), start));
+                        i += op.len();
+                        continue;
+                    }
+                    None => {
+                        return Err(ExprError::new(
+                            &format!("Unexpected character '{}'", c),
+                            start,
+                        ))
+                    }
+                }
+            }
+        }
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Literal(ExprValue),
+    // Field name followed by optional sub keys, i.e. read.finished
+    Field(Vec<String>, usize),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.0)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map(|t| t.1).unwrap_or(self.end)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).map(|t| t.0.clone());
+        self.pos += 1;
+        t
+    }
+
+    fn eat_op(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), ExprError> {
+        self.depth += 1;
+        match self.depth > MAX_NESTING {
+            true => Err(ExprError::new(
+                "Expression is nested too deeply",
+                self.position(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    fn expect(&mut self, token: Token, what: &str) -> Result<(), ExprError> {
+        let position = self.position();
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            _ => Err(ExprError::new(&format!("Expected {}", what), position)),
+        }
+    }
+
+    fn binary_level(
+        &mut self,
+        ops: &[&'static str],
+        next: fn(&mut Parser) -> Result<Expr, ExprError>,
+    ) -> Result<Expr, ExprError> {
+        let depth = self.depth;
+        let mut left = next(self)?;
+        // Chain of operators is a tree as deep as the chain
+        while let Some(op) = self.eat_op(ops) {
+            self.enter()?;
+            let right = next(self)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expr, ExprError> {
+        self.binary_level(&["||"], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, ExprError> {
+        self.binary_level(&["&&"], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ExprError> {
+        self.binary_level(&["==", "=", "!=", "<=", ">=", "<", ">"], Parser::additive)
+    }
+
+    fn additive(&mut self) -> Result<Expr, ExprError> {
+        self.binary_level(&["+", "-"], Parser::multiplicative)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, ExprError> {
+        self.binary_level(&["*", "/", "%"], Parser::unary)
+    }
+
+    // Every nested expression, in parentheses or as argument, goes through here
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        self.enter()?;
+        let e = match self.eat_op(&["!", "-"]) {
+            Some(op) => Expr::Unary(op, Box::new(self.unary()?)),
+            None => self.primary()?,
+        };
+        self.depth -= 1;
+        Ok(e)
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExprError> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Literal(ExprValue::Number(n))),
+            Some(Token::Text(s)) => Ok(Expr::Literal(ExprValue::Text(s))),
+            Some(Token::LParen) => {
+                let e = self.or()?;
+                self.expect(Token::RParen, "')'")?;
+                Ok(e)
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "null" => Ok(Expr::Literal(ExprValue::Null)),
+                "true" => Ok(Expr::Literal(ExprValue::Bool(true))),
+                "false" => Ok(Expr::Literal(ExprValue::Bool(false))),
+                _ if self.peek() == Some(&Token::LParen) => {
+                    self.pos += 1;
+                    let mut args = Vec::new();
+                    if self.peek() != Some(&Token::RParen) {
+                        loop {
+                            args.push(self.or()?);
+                            if self.peek() == Some(&Token::Comma) {
+                                self.pos += 1;
+                                continue;
+                            }
+                            break;
+                        }
+                    }
+                    self.expect(Token::RParen, "')' after function arguments")?;
+                    check_function(&name, args.len(), position)?;
+                    Ok(Expr::Call(name, args))
+                }
+                _ => {
+                    let mut parts = vec![name];
+                    while self.peek() == Some(&Token::Dot) {
+                        self.pos += 1;
+                        let p = self.position();
+                        match self.next() {
+                            Some(Token::Ident(sub)) => parts.push(sub),
+                            _ => return Err(ExprError::new("Expected field name after '.'", p)),
+                        }
+                    }
+                    Ok(Expr::Field(parts, position))
+                }
+            },
+            Some(_) => Err(ExprError::new("Unexpected token", position)),
+            None => Err(ExprError::new("Unexpected end of expression", position)),
+        }
+    }
+}
+
+// (name, min args, max args)
+const FUNCTIONS: [(&str, usize, usize); 14] = [
+    ("len", 1, 1),
+    ("count", 1, 1),
+    ("sum", 1, usize::MAX),
+    ("avg", 1, usize::MAX),
+    ("min", 1, usize::MAX),
+    ("max", 1, usize::MAX),
+    ("first", 1, 1),
+    ("last", 1, 1),
+    ("days", 2, 2),
+    ("year", 1, 1),
+    ("if", 3, 3),
+    ("coalesce", 1, usize::MAX),
+    ("round", 1, 2),
+    ("abs", 1, 1),
+];
+
+fn check_function(name: &str, args: usize, position: usize) -> Result<(), ExprError> {
+    match FUNCTIONS.iter().find(|f| f.0 == name) {
+        // Values are computed when note is cached and would not change until note does
+        None if name == "today" => Err(ExprError::new(
+            "\"today\" can't be used, computed values are only updated when note changes",
+            position,
+        )),
+        None => Err(ExprError::new(
+            &format!("Unknown function \"{}\"", name),
+            position,
+        )),
+        Some((_, min, max)) if args < *min || args > *max => Err(ExprError::new(
+            &format!("Wrong number of arguments for \"{}\"", name),
+            position,
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+pub fn parse_expression(input: &str) -> Result<Expr, ExprError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: input.chars().count(),
+        depth: 0,
+    };
+    let e = parser.or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(ExprError::new("Unexpected token", parser.position()));
+    }
+    Ok(e)
+}
+
+impl ExprValue {
+    pub fn from_attr(v: &AttrValue) -> ExprValue {
+        match v {
+            AttrValue::Text(s) | AttrValue::Date(s) | AttrValue::Image(s) => {
+                ExprValue::Text(s.clone())
+            }
+            AttrValue::Number(n) => ExprValue::Number(*n),
+            AttrValue::TextCollection(v) | AttrValue::DateCollection(v) => {
+                ExprValue::List(v.iter().map(|s| ExprValue::Text(s.clone())).collect())
+            }
+            AttrValue::DatesPairCollection(v) => ExprValue::List(
+                v.iter()
+                    .map(|d| {
+                        let mut r = BTreeMap::new();
+                        let to_val = |o: &Option<String>| match o {
+                            Some(s) => ExprValue::Text(s.clone()),
+                            None => ExprValue::Null,
+                        };
+                        r.insert("started".to_string(), to_val(&d.started));
+                        r.insert("finished".to_string(), to_val(&d.finished));
+                        ExprValue::Record(r)
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn truthy(&self) -> bool {
+        match self {
+            ExprValue::Null => false,
+            ExprValue::Bool(b) => *b,
+            ExprValue::Number(n) => *n != 0.0,
+            ExprValue::Text(s) => !s.is_empty(),
+            ExprValue::List(l) => !l.is_empty(),
+            ExprValue::Record(_) => true,
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            ExprValue::Number(n) => Some(*n),
+            ExprValue::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            ExprValue::Text(s) => s.trim().parse::<f64>().ok(),
+            _ => None,
+        }
+    }
+
+    fn to_text(&self) -> Option<String> {
+        match self {
+            ExprValue::Null => None,
+            ExprValue::Bool(b) => Some(b.to_string()),
+            ExprValue::Number(n) => Some(n.to_string()),
+            ExprValue::Text(s) => Some(s.clone()),
+            ExprValue::List(l) => Some(
+                l.iter()
+                    .filter_map(|v| v.to_text())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            ExprValue::Record(_) => None,
+        }
+    }
+
+    // Lists passed as the only argument are flattened, so max(read.finished) and max(a, b) both work.
+    fn flatten(args: Vec<ExprValue>) -> Vec<ExprValue> {
+        args.into_iter()
+            .flat_map(|a| match a {
+                ExprValue::List(l) => l,
+                v => vec![v],
+            })
+            .filter(|v| *v != ExprValue::Null)
+            .collect()
+    }
+}
+
+fn compare(a: &ExprValue, b: &ExprValue) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (ExprValue::Number(_), _) | (_, ExprValue::Number(_)) => {
+            a.as_number()?.partial_cmp(&b.as_number()?)
+        }
+        (ExprValue::Text(x), ExprValue::Text(y)) => Some(x.cmp(y)),
+        (ExprValue::Bool(x), ExprValue::Bool(y)) => Some(x.cmp(y)),
+        _ => None,
+    }
+}
+
+pub fn parse_date(s: &str) -> Option<NaiveDate> {
+    // Dates are stored as YYYY-MM-DD, but tolerate full timestamps
+    s.get(0..10)
+        .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+}
+
+fn get_sub_key(v: ExprValue, key: &str) -> ExprValue {
+    match v {
+        ExprValue::Record(mut r) => r.remove(key).unwrap_or(ExprValue::Null),
+        // Mapping over list skips empty values, so len(read.finished) is number of finished reads
+        ExprValue::List(l) => ExprValue::List(
+            l.into_iter()
+                .map(|i| get_sub_key(i, key))
+                .filter(|i| *i != ExprValue::Null)
+                .collect(),
+        ),
+        _ => ExprValue::Null,
+    }
+}
+
+pub fn evaluate(e: &Expr, attrs: &HashMap<String, AttrValue>) -> ExprValue {
+    match e {
+        Expr::Literal(v) => v.clone(),
+        Expr::Field(parts, _) => {
+            let mut v = match attrs.get(&parts[0]) {
+                Some(a) => ExprValue::from_attr(a),
+                None => ExprValue::Null,
+            };
+            for p in parts.iter().skip(1) {
+                v = get_sub_key(v, p);
+            }
+            v
+        }
+        Expr::Unary(op, inner) => {
+            let v = evaluate(inner, attrs);
+            match *op {
+                "!" => ExprValue::Bool(!v.truthy()),
+                _ => match v.as_number() {
+                    Some(n) => ExprValue::Number(-n),
+                    None => ExprValue::Null,
+                },
+            }
+        }
+        Expr::Binary(op, l, r) => {
+            let left = evaluate(l, attrs);
+            match *op {
+                "&&" => {
+                    if !left.truthy() {
+                        return ExprValue::Bool(false);
+                    }
+                    ExprValue::Bool(evaluate(r, attrs).truthy())
+                }
+                "||" => {
+                    if left.truthy() {
+                        return ExprValue::Bool(true);
+                    }
+                    ExprValue::Bool(evaluate(r, attrs).truthy())
+                }
+                _ => evaluate_binary(op, left, evaluate(r, attrs)),
+            }
+        }
+        Expr::Call(name, args) => {
+            if name == "if" {
+                return match evaluate(&args[0], attrs).truthy() {
+                    true => evaluate(&args[1], attrs),
+                    false => evaluate(&args[2], attrs),
+                };
+            }
+            let values = args.iter().map(|a| evaluate(a, attrs)).collect();
+            evaluate_call(name, values)
+        }
+    }
+}
+
+fn evaluate_binary(op: &str, left: ExprValue, right: ExprValue) -> ExprValue {
+    use std::cmp::Ordering;
+
+    match op {
+        "==" | "=" => ExprValue::Bool(match compare(&left, &right) {
+            Some(o) => o == Ordering::Equal,
+            None => left == right,
+        }),
+        "!=" => ExprValue::Bool(match compare(&left, &right) {
+            Some(o) => o != Ordering::Equal,
+            None => left != right,
+        }),
+        "<" | "<=" | ">" | ">=" => match compare(&left, &right) {
+            Some(o) => ExprValue::Bool(match op {
+                "<" => o == Ordering::Less,
+                "<=" => o != Ordering::Greater,
+                ">" => o == Ordering::Greater,
+                _ => o != Ordering::Less,
+            }),
+            None => ExprValue::Null,
+        },
+        "+" => match (&left, &right) {
+            (ExprValue::Text(a), ExprValue::Text(b)) => ExprValue::Text(format!("{}{}", a, b)),
+            _ => arithmetic(&left, &right, |a, b| Some(a + b)),
+        },
+        "-" => arithmetic(&left, &right, |a, b| Some(a - b)),
+        "*" => arithmetic(&left, &right, |a, b| Some(a * b)),
+        "/" => arithmetic(
+            &left,
+            &right,
+            |a, b| if b == 0.0 { None } else { Some(a / b) },
+        ),
+        _ => arithmetic(
+            &left,
+            &right,
+            |a, b| if b == 0.0 { None } else { Some(a % b) },
+        ),
+    }
+}
+
+fn arithmetic(a: &ExprValue, b: &ExprValue, f: fn(f64, f64) -> Option<f64>) -> ExprValue {
+    match (a.as_number(), b.as_number()) {
+        (Some(x), Some(y)) => match f(x, y) {
+            Some(n) => ExprValue::Number(n),
+            None => ExprValue::Null,
+        },
+        _ => ExprValue::Null,
+    }
+}
+
+fn evaluate_call(name: &str, mut args: Vec<ExprValue>) -> ExprValue {
+    match name {
+        "len" | "count" => match args.remove(0) {
+            ExprValue::Null => ExprValue::Number(0.0),
+            ExprValue::List(l) => ExprValue::Number(l.len() as f64),
+            ExprValue::Text(s) => ExprValue::Number(s.chars().count() as f64),
+            _ => ExprValue::Number(1.0),
+        },
+        "sum" | "avg" => {
+            let numbers: Vec<f64> = ExprValue::flatten(args)
+                .iter()
+                .filter_map(|v| v.as_number())
+                .collect();
+            if numbers.is_empty() {
+                return match name {
+                    "sum" => ExprValue::Number(0.0),
+                    _ => ExprValue::Null,
+                };
+            }
+            let sum: f64 = numbers.iter().sum();
+            match name {
+                "sum" => ExprValue::Number(sum),
+                _ => ExprValue::Number(sum / numbers.len() as f64),
+            }
+        }
+        "min" | "max" => {
+            let wanted = match name {
+                "min" => std::cmp::Ordering::Less,
+                _ => std::cmp::Ordering::Greater,
+            };
+            ExprValue::flatten(args)
+                .into_iter()
+                .reduce(|acc, v| match compare(&v, &acc) {
+                    Some(o) if o == wanted => v,
+                    _ => acc,
+                })
+                .unwrap_or(ExprValue::Null)
+        }
+        "first" | "last" => match args.remove(0) {
+            ExprValue::List(mut l) => {
+                let v = match name {
+                    "first" => l.drain(..).next(),
+                    _ => l.pop(),
+                };
+                v.unwrap_or(ExprValue::Null)
+            }
+            v => v,
+        },
+        "days" => {
+            let to = args.remove(1);
+            let from = args.remove(0);
+            match (
+                from.to_text().and_then(|s| parse_date(&s)),
+                to.to_text().and_then(|s| parse_date(&s)),
+            ) {
+                (Some(f), Some(t)) => ExprValue::Number((t - f).num_days() as f64),
+                _ => ExprValue::Null,
+            }
+        }
+        "year" => match args.remove(0).to_text().and_then(|s| parse_date(&s)) {
+            Some(d) => ExprValue::Number(d.year() as f64),
+            None => ExprValue::Null,
+        },
+        "coalesce" => args
+            .into_iter()
+            .find(|v| *v != ExprValue::Null)
+            .unwrap_or(ExprValue::Null),
+        "round" => {
+            let places = args.get(1).and_then(|p| p.as_number()).unwrap_or(0.0);
+            match args[0].as_number() {
+                Some(n) => {
+                    let m = 10f64.powi(places as i32);
+                    ExprValue::Number((n * m).round() / m)
+                }
+                None => ExprValue::Null,
+            }
+        }
+        "abs" => match args[0].as_number() {
+            Some(n) => ExprValue::Number(n.abs()),
+            None => ExprValue::Null,
+        },
+        _ => ExprValue::Null,
+    }
+}
+
+fn collect_fields<'a>(e: &'a Expr, out: &mut Vec<(&'a str, usize)>) {
+    match e {
+        Expr::Literal(_) => (),
+        Expr::Field(parts, position) => out.push((&parts[0], *position)),
+        Expr::Unary(_, inner) => collect_fields(inner, out),
+        Expr::Binary(_, l, r) => {
+            collect_fields(l, out);
+            collect_fields(r, out);
+        }
+        Expr::Call(_, args) => args.iter().for_each(|a| collect_fields(a, out)),
+    }
+}
+
+// Checks that expression parses and references only fields defined before it in schema
+pub fn validate_computed_items(schema: &Schema) -> Result<(), ErrorFromRust> {
+    let mut errors: Vec<ErrorFromRust> = Vec::new();
+
+    for (index, item) in schema.items.iter().enumerate() {
+        let expression = match &item.value {
+            AttrKey::Computed(Some(s)) => &s.expression,
+            _ => continue,
+        };
+
+        let parsed = match parse_expression(expression) {
+            Ok(v) => v,
+            Err(e) => {
+                errors.push(e.to_rust_error(&item.name, expression));
+                continue;
+            }
+        };
+
+        let mut fields = Vec::new();
+        collect_fields(&parsed, &mut fields);
+
+        for (field, position) in fields {
+            if !schema.items[..index].iter().any(|i| i.name == field) {
+                errors.push(
+                    ExprError::new(&format!("Unknown field \"{}\"", field), position)
+                        .to_rust_error(&item.name, expression),
+                );
+            }
+        }
+    }
+
+    match errors.len() {
+        0 => Ok(()),
+        _ => Err(ErrorFromRust::new("Schema has invalid computed fields").subs(errors)),
+    }
+}
+
+pub fn computed_to_attr(v: ExprValue, returns: &ComputedType) -> Option<AttrValue> {
+    match returns {
+        ComputedType::Number => match v {
+            ExprValue::Number(n) if !n.is_finite() => None,
+            v => v.as_number().map(AttrValue::Number),
+        },
+        ComputedType::Text => v.to_text().map(AttrValue::Text),
+        ComputedType::Date => v.to_text().map(AttrValue::Date),
+    }
+}
+
+// Computes values in schema order, which allows computed fields to reference previous computed fields.
+// Invalid expressions are skipped, they are reported when schema is saved.
+pub fn fill_computed_values(items: &[SchemaItem], attrs: &mut HashMap<String, AttrValue>) {
+    for item in items {
+        let settings = match &item.value {
+            AttrKey::Computed(Some(s)) => s,
+            _ => continue,
+        };
+
+        // Never trust value from file, computed fields are not stored there
+        attrs.remove(&item.name);
+
+        let parsed = match parse_expression(&settings.expression) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        let returns = settings.returns.clone().unwrap_or(ComputedType::Number);
+
+        if let Some(v) = computed_to_attr(evaluate(&parsed, attrs), &returns) {
+            attrs.insert(item.name.clone(), v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::types::{ComputedSettings, DateRead};
+
+    fn eval(expression: &str, attrs: &HashMap<String, AttrValue>) -> ExprValue {
+        evaluate(&parse_expression(expression).unwrap(), attrs)
+    }
+
+    fn num(expression: &str) -> ExprValue {
+        eval(expression, &HashMap::new())
+    }
+
+    fn book() -> HashMap<String, AttrValue> {
+        let read = |started: &str, finished: Option<&str>| DateRead {
+            started: Some(started.to_string()),
+            finished: finished.map(String::from),
+        };
+        HashMap::from([
+            ("pages".to_string(), AttrValue::Number(300.0)),
+            ("title".to_string(), AttrValue::Text("Dune".to_string())),
+            (
+                "read".to_string(),
+                AttrValue::DatesPairCollection(vec![
+                    read("2023-12-30", Some("2024-01-09")),
+                    read("2024-03-01", None),
+                ]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        assert_eq!(num("1 + 2 * 3"), ExprValue::Number(7.0));
+        assert_eq!(num("(1 + 2) * 3"), ExprValue::Number(9.0));
+        assert_eq!(num("10 - 4 - 3"), ExprValue::Number(3.0));
+        assert_eq!(num("-2 * 3 + 10 % 4"), ExprValue::Number(-4.0));
+        assert_eq!(num("1 + 1 == 2 && 3 > 2"), ExprValue::Bool(true));
+        assert_eq!(num("false && true || true"), ExprValue::Bool(true));
+        assert_eq!(num("!0 == true"), ExprValue::Bool(true));
+    }
+
+    #[test]
+    fn mismatched_types_give_null() {
+        assert_eq!(num("\"abc\" * 2"), ExprValue::Null);
+        assert_eq!(num("1 / 0"), ExprValue::Null);
+        assert_eq!(eval("read > 1", &book()), ExprValue::Null);
+        assert_eq!(eval("missing + 1", &book()), ExprValue::Null);
+        // Text that looks like number is compared as number
+        assert_eq!(num("\"10\" > 9"), ExprValue::Bool(true));
+        assert_eq!(num("\"a\" + \"b\""), ExprValue::Text("ab".to_string()));
+    }
+
+    #[test]
+    fn dates_pairs_and_date_math() {
+        let attrs = book();
+        assert_eq!(eval("len(read)", &attrs), ExprValue::Number(2.0));
+        assert_eq!(eval("len(read.finished)", &attrs), ExprValue::Number(1.0));
+        assert_eq!(
+            eval("max(read.started)", &attrs),
+            ExprValue::Text("2024-03-01".to_string())
+        );
+        assert_eq!(
+            eval(
+                "pages / days(first(read.started), first(read.finished))",
+                &attrs
+            ),
+            ExprValue::Number(30.0)
+        );
+        assert_eq!(
+            eval("year(last(read.started))", &attrs),
+            ExprValue::Number(2024.0)
+        );
+        assert_eq!(
+            num("days(\"2024-02-28\", \"2024-03-01T10:00:00Z\")"),
+            ExprValue::Number(2.0)
+        );
+        assert_eq!(num("days(\"2024-02-28\", \"never\")"), ExprValue::Null);
+        assert_eq!(
+            eval(
+                "if(len(read) > len(read.finished), \"reading\", \"read\")",
+                &attrs
+            ),
+            ExprValue::Text("reading".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_errors_have_positions() {
+        let err = |e: &str| parse_expression(e).unwrap_err();
+
+        assert_eq!(err("1 + ").position, 4);
+        assert_eq!(err("len(a, b)").position, 0);
+        assert_eq!(err("nope(a)").position, 0);
+        assert_eq!(err("(1 + 2").position, 6);
+        assert_eq!(err("a.").position, 2);
+        assert_eq!(err("'open").position, 0);
+        assert_eq!(err("1 # 2").position, 2);
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let nested = format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000));
+        assert!(parse_expression(&nested)
+            .unwrap_err()
+            .message
+            .contains("nested"));
+
+        assert!(parse_expression(&"!".repeat(10_000)).is_err());
+        assert!(parse_expression(&vec!["1"; 10_000].join(" + ")).is_err());
+
+        let allowed = format!("{}1{}", "(".repeat(20), ")".repeat(20));
+        assert_eq!(num(&allowed), ExprValue::Number(1.0));
+        assert!(parse_expression(&vec!["1"; 50].join(" + ")).is_ok());
+    }
+
+    #[test]
+    fn today_is_rejected() {
+        let e = parse_expression("days(max(read.finished), today())").unwrap_err();
+        assert_eq!(e.position, 25);
+        assert!(e.message.contains("today"));
+    }
+
+    #[test]
+    fn computed_fields_can_use_only_previous_fields() {
+        let computed = |name: &str, expression: &str| SchemaItem {
+            name: name.to_string(),
+            value: AttrKey::Computed(Some(ComputedSettings {
+                expression: expression.to_string(),
+                returns: Some(ComputedType::Number),
+                display_name: None,
+                decimal_places: None,
+            })),
+        };
+        let mut schema = Schema {
+            name: "Books".to_string(),
+            version: "1.0".to_string(),
+            icon: None,
+            items: vec![
+                SchemaItem {
+                    name: "read".to_string(),
+                    value: AttrKey::DatesPairCollection(None),
+                },
+                computed("timesRead", "len(read)"),
+                computed("twice", "timesRead * 2"),
+            ],
+            internal_path: "".to_string(),
+            internal_name: "".to_string(),
+        };
+        assert!(validate_computed_items(&schema).is_ok());
+
+        let mut attrs = book();
+        fill_computed_values(&schema.items, &mut attrs);
+        assert!(matches!(attrs.get("twice"), Some(AttrValue::Number(n)) if *n == 4.0));
+
+        schema.items.swap(1, 2);
+        let err = validate_computed_items(&schema).unwrap_err();
+        assert_eq!(err.sub_errors.len(), 1);
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs::write;
+
+use serde_json::{json, Map, Value};
+
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::operations::get_schema_cached_safe;
+use super::types::{AttrKey, Schema};
+
+pub const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+fn date_string() -> Value {
+    json!({ "type": "string", "format": "date" })
+}
+
+fn with_title(mut v: Value, title: &Option<String>) -> Value {
+    if let (Some(t), Some(obj)) = (title, v.as_object_mut()) {
+        obj.insert("title".to_string(), json!(t));
+    }
+    v
+}
+
+// None means field is not stored in frontmatter
+fn attr_key_to_json_schema(key: &AttrKey) -> Option<Value> {
+    match key {
+        AttrKey::Text(settings) => Some(with_title(
+            json!({ "type": "string" }),
+            &settings.as_ref().and_then(|s| s.display_name.clone()),
+        )),
+        AttrKey::Number(settings) => {
+            let mut v = json!({ "type": "number" });
+            if let Some(s) = settings {
+                let obj = v.as_object_mut().expect("json! object");
+                if let Some(min) = s.min {
+                    obj.insert("minimum".to_string(), json!(min));
+                }
+                if let Some(max) = s.max {
+                    obj.insert("maximum".to_string(), json!(max));
+                }
+                // Fractional multipleOf is unreliable with floats, so only whole numbers are enforced
+                if s.decimal_places == Some(0) {
+                    obj.insert("type".to_string(), json!("integer"));
+                }
+            }
+            Some(v)
+        }
+        AttrKey::Date(_) => Some(date_string()),
+        AttrKey::Image(_) => Some(json!({ "type": "string" })),
+        AttrKey::TextCollection(_) => Some(json!({
+            "type": "array",
+            "items": { "type": "string" }
+        })),
+        AttrKey::DateCollection(_) => Some(json!({
+            "type": "array",
+            "items": date_string()
+        })),
+        AttrKey::DatesPairCollection(_) => Some(json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "started": date_string(),
+                    "finished": date_string()
+                }
+            }
+        })),
+        AttrKey::Computed(_) => None,
+    }
+}
+
+// All fields are optional in notes, and unknown keys are kept by the app, so they are allowed here too
+pub fn schema_to_json_schema(schema: &Schema) -> Value {
+    let mut properties = Map::new();
+
+    for item in &schema.items {
+        if let Some(v) = attr_key_to_json_schema(&item.value) {
+            properties.insert(item.name.clone(), v);
+        }
+    }
+
+    json!({
+        "$schema": JSON_SCHEMA_DIALECT,
+        "title": schema.name,
+        "description": format!("Frontmatter of notes in \"{}\", schema version {}", schema.name, schema.version),
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": true
+    })
+}
+
+// Optionally writes result to target_path
+pub async fn get_json_schema(
+    schema_path: &str,
+    target_path: Option<String>,
+) -> Result<Value, ErrorFromRust> {
+    let schema = get_schema_cached_safe(schema_path).await?;
+
+    let result = schema_to_json_schema(&schema);
+
+    if let Some(target) = target_path {
+        let serialized = serde_json::to_string_pretty(&result)
+            .map_err(|e| ErrorFromRust::new("Error serializing JSON schema").raw(e))?;
+
+        write(&target, serialized).map_err(|e| {
+            ErrorFromRust::new("Error writing to disk")
+                .info(&target)
+                .raw(e)
+        })?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::types::{ComputedSettings, NumberSettings, SchemaItem, TextSettings};
+
+    fn schema(items: Vec<(&str, AttrKey)>) -> Schema {
+        Schema {
+            name: "Books".to_string(),
+            version: "1.0".to_string(),
+            icon: None,
+            items: items
+                .into_iter()
+                .map(|(name, value)| SchemaItem {
+                    name: name.to_string(),
+                    value,
+                })
+                .collect(),
+            internal_path: "/v/books".to_string(),
+            internal_name: "books".to_string(),
+        }
+    }
+
+    #[test]
+    fn fields_map_to_json_types() {
+        let result = schema_to_json_schema(&schema(vec![
+            (
+                "title",
+                AttrKey::Text(Some(TextSettings {
+                    display_name: Some("Title".to_string()),
+                    ..Default::default()
+                })),
+            ),
+            (
+                "rating",
+                AttrKey::Number(Some(NumberSettings {
+                    min: Some(0.0),
+                    max: Some(5.0),
+                    decimal_places: Some(0),
+                    ..Default::default()
+                })),
+            ),
+            ("price", AttrKey::Number(None)),
+            ("read", AttrKey::DatesPairCollection(None)),
+        ]));
+
+        let props = &result["properties"];
+        assert_eq!(
+            props["title"],
+            json!({ "type": "string", "title": "Title" })
+        );
+        assert_eq!(
+            props["rating"],
+            json!({ "type": "integer", "minimum": 0.0, "maximum": 5.0 })
+        );
+        assert_eq!(props["price"], json!({ "type": "number" }));
+        assert_eq!(
+            props["read"]["items"]["properties"]["finished"],
+            json!({ "type": "string", "format": "date" })
+        );
+        assert_eq!(result["$schema"], JSON_SCHEMA_DIALECT);
+        assert_eq!(result["additionalProperties"], true);
+    }
+
+    #[test]
+    fn computed_fields_are_not_in_frontmatter() {
+        let result = schema_to_json_schema(&schema(vec![(
+            "pages per day",
+            AttrKey::Computed(Some(ComputedSettings {
+                display_name: None,
+                expression: "pages / 2".to_string(),
+                returns: None,
+                decimal_places: None,
+            })),
+        )]));
+
+        assert_eq!(result["properties"], json!({}));
+    }
+}
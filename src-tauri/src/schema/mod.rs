@@ -1,3 +1,7 @@
 pub mod defaults;
+pub mod expression;
+pub mod json_schema;
 pub mod operations;
+pub mod package;
 pub mod types;
+pub mod views;
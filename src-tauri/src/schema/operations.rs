@@ -12,6 +12,7 @@ use tokio::sync::Mutex;
 
 use crate::utils::{errorhandling::ErrorFromRust, global_app::get_root_path};
 
+use super::expression::validate_computed_items;
 use super::types::{Schema, SCHEMA_VERSION};
 
 type GlobalSchema = Arc<Mutex<HashMap<String, Schema>>>;
@@ -166,6 +167,9 @@ pub async fn save_schema(
     mut schema: Schema,
 ) -> Result<Schema, ErrorFromRust> {
     schema.version = SCHEMA_VERSION.to_string();
+
+    validate_computed_items(&schema)?;
+
     let serialized = serde_yml::to_string(&schema)
         .map_err(|e| ErrorFromRust::new("Error serializing schema").raw(e))?;
 
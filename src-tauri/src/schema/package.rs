@@ -0,0 +1,339 @@
+use std::fs::{create_dir_all, read, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::utils::errorhandling::{ErrorActionCode, ErrorFromRust};
+
+use super::expression::validate_computed_items;
+use super::operations::{get_schema_cached_safe, save_schema};
+use super::types::{Schema, SCHEMA_VERSION};
+use super::views::{parse_views, VIEWS_FILE};
+
+// Package is a zip archive:
+//   manifest.yaml       - PackageManifest
+//   schema.yaml         - schema with item settings
+//   views.yaml          - optional saved views of schema folder
+//   examples/<name>.md  - optional example notes
+const MANIFEST_FILE: &str = "manifest.yaml";
+const SCHEMA_FILE: &str = "schema.yaml";
+const EXAMPLES_FOLDER: &str = "examples";
+
+pub const PACKAGE_VERSION: u32 = 1;
+pub const MAX_EXAMPLE_NOTES: usize = 10;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackageManifest {
+    pub package_version: u32,
+    pub schema_version: String,
+    pub name: String,
+    pub exported: String,
+    pub examples: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackageImportResult {
+    pub schema: Schema,
+    pub examples: Vec<String>,
+}
+
+fn zip_err(e: impl ToString) -> ErrorFromRust {
+    ErrorFromRust::new("Error when writing schema package").raw(e)
+}
+
+// Package is imported into a new folder directly in vault root
+pub fn validate_folder_name(folder_name: &str) -> Result<(), ErrorFromRust> {
+    let mut components = Path::new(folder_name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(ErrorFromRust::new("Invalid folder name")
+            .info("Folder name can't contain path separators or \"..\"")
+            .raw(folder_name)),
+    }
+}
+
+// Where entry of package is written, None for entries that are not imported
+fn entry_target(name: &Path, target_folder: &Path) -> Option<PathBuf> {
+    match (name.parent(), name.extension()) {
+        (Some(parent), Some(ext)) if parent == Path::new(EXAMPLES_FOLDER) && ext == "md" => {
+            Some(target_folder.join(name.file_name()?))
+        }
+        _ if name == Path::new(VIEWS_FILE) => Some(target_folder.join(VIEWS_FILE)),
+        _ => None,
+    }
+}
+
+pub async fn export_schema_package(
+    schema_path: &str,
+    target_path: &str,
+    example_paths: Vec<String>,
+) -> Result<PackageManifest, ErrorFromRust> {
+    let mut schema = get_schema_cached_safe(schema_path).await?;
+
+    if example_paths.len() > MAX_EXAMPLE_NOTES {
+        return Err(ErrorFromRust::new("Too many example notes").info(&format!(
+            "Package can include up to {} notes",
+            MAX_EXAMPLE_NOTES
+        )));
+    }
+
+    let schema_folder = PathBuf::from(&schema.internal_path);
+
+    // Local paths should not leak into shared file, they are set again on import
+    schema.internal_path = "".to_string();
+    schema.internal_name = "".to_string();
+
+    let mut examples: Vec<(String, Vec<u8>)> = Vec::new();
+    for p in example_paths {
+        let path = PathBuf::from(&p);
+        let content = read(&path).map_err(|e| {
+            ErrorFromRust::new("Error reading example note")
+                .info(&p)
+                .raw(e)
+        })?;
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(format!("example_{}.md", examples.len()));
+        examples.push((name, content));
+    }
+
+    let manifest = PackageManifest {
+        package_version: PACKAGE_VERSION,
+        schema_version: schema.version.clone(),
+        name: schema.name.clone(),
+        exported: Utc::now().to_rfc3339(),
+        examples: examples.iter().map(|e| e.0.clone()).collect(),
+    };
+
+    let file = File::create(target_path).map_err(|e| {
+        ErrorFromRust::new("Error creating package file")
+            .info(target_path)
+            .raw(e)
+    })?;
+
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let manifest_yaml = serde_yml::to_string(&manifest).map_err(zip_err)?;
+    zip.start_file(MANIFEST_FILE, options).map_err(zip_err)?;
+    zip.write_all(manifest_yaml.as_bytes()).map_err(zip_err)?;
+
+    let schema_yaml = serde_yml::to_string(&schema).map_err(zip_err)?;
+    zip.start_file(SCHEMA_FILE, options).map_err(zip_err)?;
+    zip.write_all(schema_yaml.as_bytes()).map_err(zip_err)?;
+
+    let views = schema_folder.join(VIEWS_FILE);
+    if views.exists() {
+        let content = read(&views).map_err(zip_err)?;
+        zip.start_file(VIEWS_FILE, options).map_err(zip_err)?;
+        zip.write_all(&content).map_err(zip_err)?;
+    }
+
+    for (name, content) in examples {
+        zip.start_file(format!("{}/{}", EXAMPLES_FOLDER, name), options)
+            .map_err(zip_err)?;
+        zip.write_all(&content).map_err(zip_err)?;
+    }
+
+    zip.finish().map_err(zip_err)?;
+
+    Ok(manifest)
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>, ErrorFromRust> {
+    let mut entry = archive.by_name(name).map_err(|e| {
+        ErrorFromRust::new("Invalid schema package")
+            .info(&format!("Package does not contain {}", name))
+            .raw(e)
+    })?;
+    let mut content = Vec::new();
+    entry
+        .read_to_end(&mut content)
+        .map_err(|e| ErrorFromRust::new("Error reading schema package").raw(e))?;
+    Ok(content)
+}
+
+pub fn read_package_manifest(package_path: &str) -> Result<PackageManifest, ErrorFromRust> {
+    let file = File::open(package_path).map_err(|e| {
+        ErrorFromRust::new("Error opening schema package")
+            .info(package_path)
+            .raw(e)
+    })?;
+    let mut archive = ZipArchive::new(file).map_err(|e| {
+        ErrorFromRust::new("Invalid schema package")
+            .info("File is not a valid archive")
+            .raw(e)
+    })?;
+
+    let content = read_entry(&mut archive, MANIFEST_FILE)?;
+
+    serde_yml::from_slice(&content).map_err(|e| {
+        ErrorFromRust::new("Invalid schema package")
+            .info("Unable to parse manifest")
+            .raw(e)
+    })
+}
+
+// When overwrite is false and folder already has a schema, returns error with action to retry with overwrite.
+// Example notes never replace existing files.
+pub async fn import_schema_package(
+    package_path: &str,
+    target_folder: &PathBuf,
+    overwrite: bool,
+) -> Result<PackageImportResult, ErrorFromRust> {
+    let manifest = read_package_manifest(package_path)?;
+
+    if manifest.package_version > PACKAGE_VERSION {
+        return Err(ErrorFromRust::new("Schema package is too new").info(
+            "Package was created by a newer version of the app. Update the app to import it",
+        ));
+    }
+
+    if manifest.schema_version != SCHEMA_VERSION {
+        return Err(
+            ErrorFromRust::new("Incompatible schema version").info(&format!(
+                "Package has schema version {}, this app supports {}",
+                manifest.schema_version, SCHEMA_VERSION
+            )),
+        );
+    }
+
+    if target_folder.exists() && !overwrite {
+        return Err(ErrorFromRust::new("Folder already exists")
+            .info(&target_folder.to_string_lossy())
+            .action_c(ErrorActionCode::SchemaImportOverwrite, "Overwrite"));
+    }
+
+    let file = File::open(package_path).map_err(|e| {
+        ErrorFromRust::new("Error opening schema package")
+            .info(package_path)
+            .raw(e)
+    })?;
+    let mut archive =
+        ZipArchive::new(file).map_err(|e| ErrorFromRust::new("Invalid schema package").raw(e))?;
+
+    let schema_content = read_entry(&mut archive, SCHEMA_FILE)?;
+    let schema: Schema = serde_yml::from_slice(&schema_content).map_err(|e| {
+        ErrorFromRust::new("Invalid schema package")
+            .info("Unable to parse schema")
+            .raw(e)
+    })?;
+
+    if schema.version != SCHEMA_VERSION {
+        return Err(
+            ErrorFromRust::new("Incompatible schema version").info(&format!(
+                "Schema has version {}, this app supports {}",
+                schema.version, SCHEMA_VERSION
+            )),
+        );
+    }
+
+    validate_computed_items(&schema)?;
+
+    let mut schema = schema;
+    schema.internal_path = target_folder.to_string_lossy().to_string();
+    schema.internal_name = target_folder
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let schema = save_schema(target_folder, schema).await?;
+
+    let mut examples: Vec<String> = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| ErrorFromRust::new("Error reading schema package").raw(e))?;
+
+        // enclosed_name rejects absolute paths and "..", so entries can't escape target folder
+        let name = match entry.enclosed_name() {
+            Some(n) => n,
+            None => continue,
+        };
+
+        let Some(target) = entry_target(&name, target_folder) else {
+            continue;
+        };
+        let is_views = name == Path::new(VIEWS_FILE);
+
+        // Views belong to imported schema and replace old ones together with it
+        if target.exists() && !is_views {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        entry
+            .read_to_end(&mut content)
+            .map_err(|e| ErrorFromRust::new("Error reading schema package").raw(e))?;
+
+        if is_views {
+            parse_views(&String::from_utf8_lossy(&content), VIEWS_FILE)?;
+        }
+
+        create_dir_all(target_folder)
+            .map_err(|e| ErrorFromRust::new("Error creating directory").raw(e))?;
+
+        let mut out = File::create(&target).map_err(|e| {
+            ErrorFromRust::new("Error writing to disk")
+                .info(&target.to_string_lossy())
+                .raw(e)
+        })?;
+        out.write_all(&content).map_err(|e| {
+            ErrorFromRust::new("Error writing to disk")
+                .info(&target.to_string_lossy())
+                .raw(e)
+        })?;
+
+        if target.extension().is_some_and(|e| e == "md") {
+            examples.push(target.to_string_lossy().to_string());
+        }
+    }
+
+    Ok(PackageImportResult { schema, examples })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_name_must_be_single_component() {
+        assert!(validate_folder_name("Books").is_ok());
+        assert!(validate_folder_name("My Books 2").is_ok());
+
+        assert!(validate_folder_name("").is_err());
+        assert!(validate_folder_name(".").is_err());
+        assert!(validate_folder_name("..").is_err());
+        assert!(validate_folder_name("../Books").is_err());
+        assert!(validate_folder_name("Books/Fiction").is_err());
+        assert!(validate_folder_name("/tmp/Books").is_err());
+    }
+
+    #[test]
+    fn only_examples_and_views_are_imported() {
+        let target = Path::new("vault").join("Books");
+
+        assert_eq!(
+            entry_target(Path::new("examples/Dune.md"), &target),
+            Some(target.join("Dune.md"))
+        );
+        assert_eq!(
+            entry_target(Path::new(VIEWS_FILE), &target),
+            Some(target.join(VIEWS_FILE))
+        );
+
+        assert_eq!(entry_target(Path::new(SCHEMA_FILE), &target), None);
+        assert_eq!(entry_target(Path::new(MANIFEST_FILE), &target), None);
+        assert_eq!(entry_target(Path::new("examples/cover.png"), &target), None);
+        assert_eq!(
+            entry_target(Path::new("examples/nested/Dune.md"), &target),
+            None
+        );
+        assert_eq!(entry_target(Path::new("icon.png"), &target), None);
+    }
+}
@@ -29,6 +29,8 @@ pub enum AttrKey {
     DateCollection(Option<EmptySettings>),
     DatesPairCollection(Option<EmptySettings>),
     Image(Option<EmptySettings>),
+    // Read-only, value is calculated from other fields and never written to file
+    Computed(Option<ComputedSettings>),
 }
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -77,6 +79,19 @@ impl Default for NumberSettings {
     }
 }
 
+#[skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComputedSettings {
+    pub display_name: Option<String>,
+    // See schema::expression for syntax
+    #[serde(default)]
+    pub expression: String,
+    // Defaults to Number
+    pub returns: Option<ComputedType>,
+    pub decimal_places: Option<u8>,
+}
+
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -108,6 +123,13 @@ pub enum TextTheme {
     Default,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ComputedType {
+    Number,
+    Text,
+    Date,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum NumberStyle {
     Default,
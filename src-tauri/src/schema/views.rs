@@ -0,0 +1,427 @@
+use std::fs::{read_to_string, write};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use tokio::sync::Mutex;
+
+use crate::cache::aggregate::{
+    aggregate_files_in_path, AggregateGroup, AggregateOptions, GroupKey, GroupOrder,
+};
+use crate::cache::filter::{Filter, SortKey};
+use crate::cache::query::{query_files, FilesQueryOptions, FilesQueryResult};
+use crate::utils::changes::ChangedPath;
+use crate::utils::errorhandling::ErrorFromRust;
+
+use super::operations::get_schema_cached_safe;
+use super::types::Schema;
+
+// Lives next to schema.yaml, so views are committed and shared together with notes.
+// It's not a note, scan and watcher don't cache it.
+pub const VIEWS_FILE: &str = "views.yaml";
+pub const VIEWS_VERSION: u32 = 1;
+
+// Read, modify, write of views file must not interleave
+static VIEWS_LOCK: Mutex<()> = Mutex::const_new(());
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedView {
+    // Generated from name when view is created, stays the same after rename
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    // Relative to schema folder, absolute paths differ between clones of vault
+    pub folder: Option<String>,
+    pub include_subfolders: Option<bool>,
+    pub filter: Option<Filter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sort: Vec<SortKey>,
+    pub search: Option<String>,
+    // Empty means all fields
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub visible_fields: Vec<String>,
+    pub group_by: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct ViewsFile {
+    version: u32,
+    #[serde(default)]
+    views: Vec<SavedView>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ViewEvaluation {
+    pub view: SavedView,
+    pub result: FilesQueryResult,
+    // Counts of all groups, not only ones on this page. Empty without group_by.
+    pub groups: Vec<AggregateGroup>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ViewsChangedEmit {
+    #[serde(rename = "schemaPath")]
+    pub schema_path: String,
+    // Views that have to be evaluated again
+    #[serde(rename = "viewIds")]
+    pub view_ids: Vec<String>,
+    // Views file itself changed, for example after git pull. List of views has to be loaded again.
+    #[serde(rename = "listChanged")]
+    pub list_changed: bool,
+}
+
+fn views_file_path(schema: &Schema) -> PathBuf {
+    PathBuf::from(&schema.internal_path).join(VIEWS_FILE)
+}
+
+fn read_views(schema: &Schema) -> Result<Vec<SavedView>, ErrorFromRust> {
+    let path = views_file_path(schema);
+
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let content = read_to_string(&path).map_err(|e| {
+        ErrorFromRust::new("Error when reading views file")
+            .info(&path.to_string_lossy())
+            .raw(e)
+    })?;
+
+    parse_views(&content, &path.to_string_lossy())
+}
+
+// Source is shown in error, path of file or name of package entry
+pub fn parse_views(content: &str, source: &str) -> Result<Vec<SavedView>, ErrorFromRust> {
+    let file: ViewsFile = serde_yml::from_str(content).map_err(|e| {
+        ErrorFromRust::new("Error parsing views file")
+            .info(source)
+            .raw(e)
+    })?;
+
+    Ok(file.views)
+}
+
+fn write_views(schema: &Schema, views: Vec<SavedView>) -> Result<(), ErrorFromRust> {
+    let file = ViewsFile {
+        version: VIEWS_VERSION,
+        views,
+    };
+
+    let serialized = serde_yml::to_string(&file)
+        .map_err(|e| ErrorFromRust::new("Error serializing views").raw(e))?;
+
+    write(views_file_path(schema), serialized).map_err(|e| {
+        ErrorFromRust::new("Error writing to disk")
+            .info("View was not saved")
+            .raw(e)
+    })
+}
+
+fn view_id_from_name(name: &str, views: &[SavedView]) -> String {
+    let words: Vec<String> = name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(String::from)
+        .collect();
+    let slug = match words.is_empty() {
+        true => "view".to_string(),
+        false => words.join("-"),
+    };
+
+    let mut id = slug.clone();
+    let mut n = 2;
+    while views.iter().any(|v| v.id == id) {
+        id = format!("{}-{}", slug, n);
+        n += 1;
+    }
+
+    id
+}
+
+fn view_folder(schema: &Schema, view: &SavedView) -> PathBuf {
+    let root = PathBuf::from(&schema.internal_path);
+    match view.folder.as_deref() {
+        Some(f) if !f.is_empty() => root.join(f),
+        _ => root,
+    }
+}
+
+fn validate_view(schema: &Schema, view: &SavedView) -> Result<(), ErrorFromRust> {
+    if view.name.trim().is_empty() {
+        return Err(ErrorFromRust::new("View name is empty"));
+    }
+
+    if let Some(folder) = &view.folder {
+        if !Path::new(folder)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(ErrorFromRust::new("Invalid view folder")
+                .info("Folder must be relative to schema folder")
+                .raw(folder));
+        }
+    }
+
+    let fields = view
+        .sort
+        .iter()
+        .map(|s| &s.field)
+        .chain(view.visible_fields.iter())
+        .chain(view.group_by.iter());
+
+    for field in fields {
+        if !schema.items.iter().any(|i| &i.name == field) {
+            return Err(ErrorFromRust::new("Unknown field in view").raw(field));
+        }
+    }
+
+    Ok(())
+}
+
+fn view_options(
+    view: &SavedView,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    cursor: Option<String>,
+) -> FilesQueryOptions {
+    FilesQueryOptions {
+        filter: view.filter.clone(),
+        sort: view.sort.clone(),
+        limit,
+        offset,
+        cursor,
+        include_subfolders: view.include_subfolders,
+        search: view.search.clone(),
+    }
+}
+
+pub async fn get_views(schema_path: &str) -> Result<Vec<SavedView>, ErrorFromRust> {
+    let schema = get_schema_cached_safe(schema_path).await?;
+    read_views(&schema)
+}
+
+// View without id is created, otherwise view with the same id is replaced
+pub async fn save_view(schema_path: &str, mut view: SavedView) -> Result<SavedView, ErrorFromRust> {
+    let schema = get_schema_cached_safe(schema_path).await?;
+    validate_view(&schema, &view)?;
+
+    let folder = view_folder(&schema, &view);
+    if !folder.is_dir() {
+        return Err(
+            ErrorFromRust::new("View folder does not exist").info(&folder.to_string_lossy())
+        );
+    }
+
+    // Broken filter or search should fail now, not every time view is opened
+    query_files(
+        folder.to_string_lossy().to_string(),
+        view_options(&view, Some(1), None, None),
+    )
+    .await?;
+
+    let _lock = VIEWS_LOCK.lock().await;
+    let mut views = read_views(&schema)?;
+
+    if view.id.is_empty() {
+        view.id = view_id_from_name(&view.name, &views);
+        views.push(view.clone());
+    } else {
+        match views.iter_mut().find(|v| v.id == view.id) {
+            Some(v) => *v = view.clone(),
+            None => {
+                return Err(ErrorFromRust::new("View does not exist")
+                    .info("It could have been deleted in another window or by git")
+                    .raw(&view.id))
+            }
+        }
+    }
+
+    write_views(&schema, views)?;
+
+    Ok(view)
+}
+
+// Returns false when there was no such view
+pub async fn delete_view(schema_path: &str, id: &str) -> Result<bool, ErrorFromRust> {
+    let schema = get_schema_cached_safe(schema_path).await?;
+
+    let _lock = VIEWS_LOCK.lock().await;
+    let mut views = read_views(&schema)?;
+
+    let count = views.len();
+    views.retain(|v| v.id != id);
+    if views.len() == count {
+        return Ok(false);
+    }
+
+    write_views(&schema, views)?;
+
+    Ok(true)
+}
+
+pub async fn evaluate_view(
+    schema_path: &str,
+    id: &str,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    cursor: Option<String>,
+) -> Result<ViewEvaluation, ErrorFromRust> {
+    let schema = get_schema_cached_safe(schema_path).await?;
+
+    let view = read_views(&schema)?
+        .into_iter()
+        .find(|v| v.id == id)
+        .ok_or_else(|| ErrorFromRust::new("View does not exist").raw(id))?;
+
+    let folder = view_folder(&schema, &view).to_string_lossy().to_string();
+
+    let mut result =
+        query_files(folder.clone(), view_options(&view, limit, offset, cursor)).await?;
+
+    let groups = match &view.group_by {
+        Some(field) => {
+            let options = AggregateOptions {
+                group_by: vec![GroupKey {
+                    field: field.clone(),
+                    date_bucket: None,
+                    width: None,
+                    part: None,
+                }],
+                aggregates: vec![],
+                filter: view.filter.clone(),
+                search: view.search.clone(),
+                include_subfolders: view.include_subfolders,
+                order: GroupOrder::Key,
+                limit: None,
+            };
+            aggregate_files_in_path(folder, options).await?.groups
+        }
+        None => vec![],
+    };
+
+    if !view.visible_fields.is_empty() {
+        for book in result.books.iter_mut() {
+            book.attrs.retain(|name, _| {
+                view.visible_fields.contains(name) || view.group_by.as_ref() == Some(name)
+            });
+        }
+    }
+
+    Ok(ViewEvaluation {
+        view,
+        result,
+        groups,
+    })
+}
+
+// Note or folder change can change results of view. Removed folder can contain notes
+// of view even when view does not include subfolders.
+fn view_affected_by(folder: &Path, include_subfolders: bool, change: &ChangedPath) -> bool {
+    match include_subfolders || change.is_folder {
+        true => change.path.starts_with(folder),
+        false => change.path.parent() == Some(folder),
+    }
+}
+
+// Called with paths of schema reported by watcher since last emit.
+// Views file or notes and folders inside of view folder.
+pub fn views_changed_by(schema: &Schema, changes: &[&ChangedPath]) -> Option<ViewsChangedEmit> {
+    let list_changed = changes.iter().any(|c| c.path == views_file_path(schema));
+
+    // Broken views file is reported when views are loaded
+    let views = read_views(schema).unwrap_or_default();
+
+    let view_ids: Vec<String> = views
+        .iter()
+        .filter(|v| {
+            let folder = view_folder(schema, v);
+            let subfolders = v.include_subfolders.unwrap_or(true);
+            list_changed
+                || changes
+                    .iter()
+                    .any(|c| view_affected_by(&folder, subfolders, c))
+        })
+        .map(|v| v.id.clone())
+        .collect();
+
+    if view_ids.is_empty() && !list_changed {
+        return None;
+    }
+
+    Some(ViewsChangedEmit {
+        schema_path: schema.internal_path.clone(),
+        view_ids,
+        list_changed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(id: &str) -> SavedView {
+        SavedView {
+            id: id.to_string(),
+            name: id.to_string(),
+            folder: None,
+            include_subfolders: None,
+            filter: None,
+            sort: vec![],
+            search: None,
+            visible_fields: vec![],
+            group_by: None,
+        }
+    }
+
+    fn change(path: &str, is_folder: bool) -> ChangedPath {
+        ChangedPath {
+            path: PathBuf::from(path),
+            is_folder,
+            tree_changed: true,
+        }
+    }
+
+    #[test]
+    fn view_id_from_name_makes_unique_slug() {
+        assert_eq!(view_id_from_name("To Read: 2024!", &[]), "to-read-2024");
+        assert_eq!(view_id_from_name("Прочитать позже", &[]), "прочитать-позже");
+        assert_eq!(view_id_from_name(" ?! ", &[]), "view");
+
+        let existing = [view("to-read"), view("to-read-2")];
+        assert_eq!(view_id_from_name("To read", &existing), "to-read-3");
+    }
+
+    #[test]
+    fn view_affected_by_respects_subfolders() {
+        let folder = Path::new("/v/books/read");
+
+        assert!(view_affected_by(
+            folder,
+            true,
+            &change("/v/books/read/2024/a.md", false)
+        ));
+        assert!(!view_affected_by(
+            folder,
+            false,
+            &change("/v/books/read/2024/a.md", false)
+        ));
+        assert!(view_affected_by(
+            folder,
+            false,
+            &change("/v/books/read/a.md", false)
+        ));
+        // Removed subfolder could contain notes of view
+        assert!(view_affected_by(
+            folder,
+            false,
+            &change("/v/books/read/2024", true)
+        ));
+        assert!(!view_affected_by(
+            folder,
+            true,
+            &change("/v/books/reading/a.md", false)
+        ));
+    }
+}
@@ -0,0 +1,11 @@
+use std::path::PathBuf;
+
+// Note, folder or views file changed on disk. Watcher collects them and cache and views
+// decide what to send to frontend, so neither depends on watcher.
+#[derive(Clone, Debug)]
+pub struct ChangedPath {
+    pub path: PathBuf,
+    pub is_folder: bool,
+    // Editing note does not change folders or counts
+    pub tree_changed: bool,
+}
@@ -9,6 +9,7 @@ pub enum ErrorActionCode {
     PrepareCacheRetry,
     WatchPathRetry,
     NoRootPath,
+    SchemaImportOverwrite,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
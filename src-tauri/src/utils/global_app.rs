@@ -1,9 +1,10 @@
+use std::path::PathBuf;
 use std::sync::Mutex;
 
 use once_cell::sync::OnceCell;
 
 use serde_json::Value;
-use tauri::{AppHandle, Wry};
+use tauri::{AppHandle, Manager, Wry};
 use tauri_plugin_store::{Store, StoreExt};
 
 use super::errorhandling::{ErrorActionCode, ErrorFromRust};
@@ -45,3 +46,16 @@ pub fn get_root_path() -> Result<String, ErrorFromRust> {
         }
     };
 }
+
+pub fn get_app_data_dir() -> Result<PathBuf, ErrorFromRust> {
+    get_global_app()
+        .lock()
+        .unwrap()
+        .path()
+        .app_data_dir()
+        .map_err(|e| {
+            ErrorFromRust::new("Unable to get app data directory")
+                .info("This is likely a bug, please report it")
+                .raw(e)
+        })
+}
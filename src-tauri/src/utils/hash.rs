@@ -0,0 +1,13 @@
+// FNV-1a. Used for cache bookkeeping only, so speed and stability across builds matter, not strength.
+// std DefaultHasher is not guaranteed to give same result between Rust versions.
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub fn stable_hash(bytes: &[u8]) -> String {
+    let mut hash = FNV_OFFSET;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    format!("{:016x}", hash)
+}
@@ -1,2 +1,4 @@
+pub mod changes;
 pub mod errorhandling;
 pub mod global_app;
+pub mod hash;
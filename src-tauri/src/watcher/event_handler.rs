@@ -6,11 +6,16 @@ use std::ffi::OsStr;
 use std::path::Path;
 use tauri::{AppHandle, Emitter};
 
+use crate::cache::folders::{folder_tree_changed_by, is_cached_folder};
 use crate::cache::write::{
-    cache_file, cache_files_and_folders, cache_folder, remove_file_from_cache,
-    remove_files_in_folder_rom_cache, remove_folder_from_cache,
+    cache_file, cache_files_and_folders, cache_folder, default_parse_workers,
+    remove_file_from_cache, remove_files_in_folder_rom_cache, remove_folder_from_cache,
+    ScanProgress,
 };
-use crate::schema::operations::get_schema_path;
+use crate::schema::operations::{get_schema_cached, get_schema_path};
+use crate::schema::types::Schema;
+use crate::schema::views::{views_changed_by, VIEWS_FILE};
+use crate::utils::changes::ChangedPath;
 use crate::utils::errorhandling::send_err_to_frontend;
 
 async fn handle_file_remove(app: &AppHandle, path: &Path, ext: &OsStr) {
@@ -70,9 +75,13 @@ async fn handle_folder_remove(app: &AppHandle, path: &Path) {
 }
 
 async fn handle_folder_add(app: &AppHandle, path: &Path) {
+    let on_progress = |p: &ScanProgress| {
+        let _ = app.emit("cache_progress", p);
+    };
+
     match cache_folder(path).await {
         Err(e) => send_err_to_frontend(app, &e),
-        Ok(_) => match cache_files_and_folders(path).await {
+        Ok(_) => match cache_files_and_folders(path, default_parse_workers(), on_progress).await {
             Err(e) => send_err_to_frontend(app, &e),
             Ok(_) => app
                 .emit(
@@ -87,9 +96,20 @@ async fn handle_folder_add(app: &AppHandle, path: &Path) {
     };
 }
 
-pub async fn handle_event(event: Event, app: &AppHandle) {
+// Emits for changed paths are sent in batch when events settle
+pub async fn handle_event(event: Event, app: &AppHandle) -> Vec<ChangedPath> {
+    let mut changed: Vec<ChangedPath> = Vec::new();
     for (index, path) in event.paths.iter().enumerate() {
         println!("{:?}", event);
+
+        // Checked before cache is updated, removed folder is known only from cache
+        let is_folder = path.is_dir()
+            || (!path.is_file()
+                && is_cached_folder(path).await.unwrap_or_else(|e| {
+                    send_err_to_frontend(app, &e);
+                    false
+                }));
+
         match event.kind {
             EventKind::Create(kind) => match (kind, path.extension()) {
                 (CreateKind::File, Some(ext)) => handle_file_add(app, &path, ext).await,
@@ -149,5 +169,60 @@ pub async fn handle_event(event: Event, app: &AppHandle) {
             },
             _ => (),
         };
+
+        let is_note = !is_folder && path.extension().is_some_and(|e| e == "md");
+        let is_views = path.file_name().is_some_and(|n| n == VIEWS_FILE);
+        if (is_note || is_folder || is_views)
+            && matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            )
+        {
+            changed.push(ChangedPath {
+                path: path.clone(),
+                is_folder,
+                tree_changed: matches!(
+                    event.kind,
+                    EventKind::Create(_)
+                        | EventKind::Modify(ModifyKind::Name(_))
+                        | EventKind::Remove(_)
+                ),
+            });
+        }
+    }
+
+    changed
+}
+
+async fn group_by_schema(changes: &[ChangedPath]) -> Vec<(Schema, Vec<&ChangedPath>)> {
+    let mut by_schema: Vec<(Schema, Vec<&ChangedPath>)> = Vec::new();
+    for change in changes.iter() {
+        let Some(schema) = get_schema_cached(&change.path.to_string_lossy()).await else {
+            continue;
+        };
+        match by_schema
+            .iter_mut()
+            .find(|(s, _)| s.internal_path == schema.internal_path)
+        {
+            Some((_, list)) => list.push(change),
+            None => by_schema.push((schema, vec![change])),
+        }
+    }
+    by_schema
+}
+
+// Sent after cache is updated, so views evaluated again see the change.
+// Views file is read and folders are counted once per schema for whole batch.
+pub async fn emit_changes(app: &AppHandle, changes: Vec<ChangedPath>) {
+    for (schema, changes) in group_by_schema(&changes).await {
+        if let Some(v) = views_changed_by(&schema, &changes) {
+            app.emit("views_changed", v).unwrap();
+        }
+
+        match folder_tree_changed_by(&schema, &changes).await {
+            Ok(Some(v)) => app.emit("folder_tree_changed", v).unwrap(),
+            Ok(None) => (),
+            Err(e) => send_err_to_frontend(app, &e),
+        }
     }
 }
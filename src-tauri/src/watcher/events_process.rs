@@ -1,11 +1,19 @@
 use notify::Event;
 
+use std::time::Duration;
 use tauri::AppHandle;
 use tokio::select;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
 
-use crate::watcher::event_handler::handle_event;
+use crate::utils::changes::ChangedPath;
+use crate::watcher::event_handler::{emit_changes, handle_event};
+
+// Copying or pulling many notes produces burst of events. Views and folder counts
+// are sent once events stop coming, or at least this often while they keep coming.
+const EMIT_QUIET: Duration = Duration::from_millis(300);
+const EMIT_MAX_DELAY: Duration = Duration::from_secs(2);
 
 #[allow(dead_code)]
 pub enum MonitorCommand {
@@ -29,15 +37,30 @@ pub async fn run_monitor(
 
     tokio::spawn(async move {
         let mut event_rx = event_rx;
+        let mut pending: Vec<ChangedPath> = Vec::new();
+        let mut first_pending = Instant::now();
+        let mut last_event = Instant::now();
 
         loop {
+            let emit_at = (last_event + EMIT_QUIET).min(first_pending + EMIT_MAX_DELAY);
+
             select! {
                 Ok(event) = event_rx.recv() => {
                     if config.log_to_stdout {
                              println!("Received event: {:?}", event);
                     }
 
-                         handle_event(event, &config.app).await
+                    let changed = handle_event(event, &config.app).await;
+                    if !changed.is_empty() {
+                        if pending.is_empty() {
+                            first_pending = Instant::now();
+                        }
+                        last_event = Instant::now();
+                        pending.extend(changed);
+                    }
+                }
+                _ = sleep_until(emit_at), if !pending.is_empty() => {
+                    emit_changes(&config.app, std::mem::take(&mut pending)).await;
                 }
                 Some(MonitorCommand::Shutdown) = cmd_rx.recv() => {
                     println!("Shutting down monitor...");